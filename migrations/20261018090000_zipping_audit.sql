@@ -0,0 +1,31 @@
+-- Operational history of zipper runs, and of each source processed within them.
+
+create schema if not exists zipping;
+
+create table if not exists zipping.runs
+(
+    id                  serial          primary key,
+    started_at          timestamptz     not null,
+    ended_at            timestamptz     null,
+    operation           varchar         not null,
+    outcome             varchar         null,
+    error_text          varchar         null
+);
+
+create table if not exists zipping.run_sources
+(
+    id                  serial          primary key,
+    run_id              int             not null references zipping.runs(id),
+    source_id           int             null,
+    started_at          timestamptz     not null,
+    ended_at            timestamptz     not null,
+    operation           varchar         not null,
+    archive_names       varchar[]       not null default '{}',
+    file_count          bigint          not null default 0,
+    bytes_in            bigint          not null default 0,
+    bytes_out           bigint          not null default 0,
+    outcome             varchar         not null,
+    error_text          varchar         null
+);
+
+create index if not exists run_sources_source_id on zipping.run_sources(source_id);
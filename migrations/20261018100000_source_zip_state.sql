@@ -0,0 +1,12 @@
+-- The state of each source's most recent archive set, updated after each successful zip.
+
+create table if not exists zipping.source_zip_state
+(
+    source_id               int             primary key,
+    last_zipped_at          timestamptz     not null,
+    last_zip_set_name       varchar         not null,
+    last_zip_archive_count  int             not null,
+    last_zip_file_count     bigint          not null,
+    last_zip_bytes_in       bigint          not null,
+    last_zip_bytes_out      bigint          not null
+);
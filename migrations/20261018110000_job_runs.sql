@@ -0,0 +1,3 @@
+-- Jobs defined in the configuration are recorded in run_sources by name, with no source id.
+
+alter table zipping.run_sources add column if not exists job_name varchar null;
@@ -0,0 +1,478 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::Local;
+use sqlx::{Pool, Postgres};
+use crate::api::{self, ArchiveObserver, ArchiveOptions, CancelToken, CompressionMethod, NoObserver};
+use crate::err::AppError;
+use crate::locks::{self, RunLock};
+use crate::runner;
+use crate::setup::cli_reader::Operation;
+use crate::storage::{LocalStorage, Storage};
+use crate::{data, ArchiveSummary, SourceDetails};
+
+// ZipJob and UnzipJob set up a zip or unzip entirely in code, without the command line, configuration
+// file or log, and can be used any number of times, with different settings, in one process.
+// A job works either on MDR sources (given as SourceDetails, or as ids looked up in the database)
+// or on a single folder and its archive. If a database pool is given the job is recorded as a run in
+// the zipping audit tables, as it would be from the command line, and (for zips) each source's archive
+// set state is saved. Without a pool nothing is written to the database, so source ids and
+// changed_only, which need it, cannot be used. The archive work itself is done on tokio's blocking threads.
+
+// The outcome for each source (or for the folder) processed. Sources not reached, because an
+// earlier one failed without keep_going or the job was cancelled, are not included.
+
+#[derive(Debug)]
+pub enum SourceOutcome {
+    Completed(ArchiveSummary),
+    Skipped(String),
+    Failed(AppError),
+}
+
+#[derive(Debug)]
+pub struct SourceResult {
+    pub source_id: Option<i32>,
+    pub name: String,
+    pub outcome: SourceOutcome,
+}
+
+#[derive(Debug)]
+pub struct JobResult {
+    pub run_id: Option<i32>,
+    pub sources: Vec<SourceResult>,
+    pub cancelled: bool,
+}
+
+impl JobResult {
+
+    pub fn is_success(&self) -> bool {
+        !self.cancelled && !self.sources.iter().any(|s| matches!(s.outcome, SourceOutcome::Failed(_)))
+    }
+
+    pub fn failures(&self) -> Vec<&SourceResult> {
+        self.sources.iter().filter(|s| matches!(s.outcome, SourceOutcome::Failed(_))).collect()
+    }
+
+    pub fn summaries(&self) -> Vec<&ArchiveSummary> {
+        self.sources.iter().filter_map(|s| match &s.outcome {
+            SourceOutcome::Completed(summary) => Some(summary),
+            _ => None,
+        }).collect()
+    }
+}
+
+
+// The settings common to both kinds of job.
+
+struct JobSettings {
+    options: ArchiveOptions,
+    use_folder: bool,
+    source_ids: Vec<i32>,
+    sources: Vec<SourceDetails>,
+    keep_going: bool,
+    locking: bool,
+    pool: Option<Pool<Postgres>>,
+    observer: Arc<dyn ArchiveObserver>,
+    storage: Option<Arc<dyn Storage>>,
+    keep_local: bool,
+}
+
+impl JobSettings {
+    fn new(zipped: PathBuf, unzipped: PathBuf, use_folder: bool) -> JobSettings {
+        JobSettings {
+            options: ArchiveOptions::new(zipped, unzipped),
+            use_folder,
+            source_ids: Vec::new(),
+            sources: Vec::new(),
+            keep_going: false,
+            locking: true,
+            pool: None,
+            observer: Arc::new(NoObserver),
+            storage: None,
+            keep_local: true,
+        }
+    }
+}
+
+
+pub struct ZipJob {
+    settings: JobSettings,
+    changed_only: bool,
+}
+
+impl ZipJob {
+
+    // Zips MDR sources, from their folders in unzipped_parent to their folders in zipped_parent.
+    pub fn sources(unzipped_parent: impl Into<PathBuf>, zipped_parent: impl Into<PathBuf>) -> ZipJob {
+        ZipJob { settings: JobSettings::new(zipped_parent.into(), unzipped_parent.into(), false), changed_only: false }
+    }
+
+    // Zips the folder (and its sub-folders) to the archive.
+    pub fn folder(folder: impl Into<PathBuf>, archive: impl Into<PathBuf>) -> ZipJob {
+        ZipJob { settings: JobSettings::new(archive.into(), folder.into(), true), changed_only: false }
+    }
+
+    pub fn source(mut self, source: SourceDetails) -> ZipJob {
+        self.settings.sources.push(source);
+        self
+    }
+
+    pub fn source_ids(mut self, ids: impl IntoIterator<Item = i32>) -> ZipJob {
+        self.settings.source_ids.extend(ids);
+        self
+    }
+
+    // Filters and compression apply to folder zips.
+    pub fn include(mut self, pattern: &str) -> ZipJob {
+        self.settings.options.include.push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> ZipJob {
+        self.settings.options.exclude.push(pattern.to_string());
+        self
+    }
+
+    pub fn compression(mut self, method: CompressionMethod, level: Option<i64>) -> ZipJob {
+        self.settings.options.compression = method;
+        self.settings.options.compression_level = level;
+        self
+    }
+
+    // Sources unchanged since their last recorded archive set are skipped (needs a pool).
+    pub fn changed_only(mut self, changed_only: bool) -> ZipJob {
+        self.changed_only = changed_only;
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> ZipJob {
+        self.settings.keep_going = keep_going;
+        self
+    }
+
+    // A lock is taken in each source's zipped folder (or the archive's folder) unless turned off.
+    pub fn locking(mut self, locking: bool) -> ZipJob {
+        self.settings.locking = locking;
+        self
+    }
+
+    pub fn pool(mut self, pool: Pool<Postgres>) -> ZipJob {
+        self.settings.pool = Some(pool);
+        self
+    }
+
+    pub fn observer(mut self, observer: Arc<dyn ArchiveObserver>) -> ZipJob {
+        self.settings.observer = observer;
+        self
+    }
+
+    // For source zips, each archive set is also stored in the backend (see Storage), with the local
+    // copies of the archives removed once it is stored, unless keep_local.
+    pub fn storage(mut self, storage: Arc<dyn Storage>, keep_local: bool) -> ZipJob {
+        self.settings.storage = Some(storage);
+        self.settings.keep_local = keep_local;
+        self
+    }
+
+    // The job stops after the current file once the token (or a clone of it) is cancelled.
+    pub fn cancel_token(mut self, token: CancelToken) -> ZipJob {
+        self.settings.options.cancel = token;
+        self
+    }
+
+    pub async fn run(self) -> Result<JobResult, AppError> {
+        if self.settings.use_folder {
+            run_folder_job(self.settings, "zip", api::zip_folder).await
+        }
+        else {
+            run_source_job(self.settings, Operation::Zip, "zip", self.changed_only).await
+        }
+    }
+}
+
+
+pub struct UnzipJob {
+    settings: JobSettings,
+}
+
+impl UnzipJob {
+
+    // Unzips MDR sources' archives, from their folders in zipped_parent to their folders in unzipped_parent.
+    pub fn sources(zipped_parent: impl Into<PathBuf>, unzipped_parent: impl Into<PathBuf>) -> UnzipJob {
+        UnzipJob { settings: JobSettings::new(zipped_parent.into(), unzipped_parent.into(), false) }
+    }
+
+    // Unzips the archive to the folder.
+    pub fn archive(archive: impl Into<PathBuf>, folder: impl Into<PathBuf>) -> UnzipJob {
+        UnzipJob { settings: JobSettings::new(archive.into(), folder.into(), true) }
+    }
+
+    pub fn source(mut self, source: SourceDetails) -> UnzipJob {
+        self.settings.sources.push(source);
+        self
+    }
+
+    pub fn source_ids(mut self, ids: impl IntoIterator<Item = i32>) -> UnzipJob {
+        self.settings.source_ids.extend(ids);
+        self
+    }
+
+    // Only the listed files (or trial ids) are extracted from each source's latest archive set.
+    pub fn files(mut self, file_list: impl IntoIterator<Item = String>) -> UnzipJob {
+        self.settings.options.file_list.extend(file_list);
+        self
+    }
+
+    // Filters apply to archive unzips.
+    pub fn include(mut self, pattern: &str) -> UnzipJob {
+        self.settings.options.include.push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> UnzipJob {
+        self.settings.options.exclude.push(pattern.to_string());
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> UnzipJob {
+        self.settings.keep_going = keep_going;
+        self
+    }
+
+    pub fn locking(mut self, locking: bool) -> UnzipJob {
+        self.settings.locking = locking;
+        self
+    }
+
+    pub fn pool(mut self, pool: Pool<Postgres>) -> UnzipJob {
+        self.settings.pool = Some(pool);
+        self
+    }
+
+    pub fn observer(mut self, observer: Arc<dyn ArchiveObserver>) -> UnzipJob {
+        self.settings.observer = observer;
+        self
+    }
+
+    // For source unzips, each source's latest set is first fetched from the backend, if not already present.
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> UnzipJob {
+        self.settings.storage = Some(storage);
+        self
+    }
+
+    pub fn cancel_token(mut self, token: CancelToken) -> UnzipJob {
+        self.settings.options.cancel = token;
+        self
+    }
+
+    pub async fn run(self) -> Result<JobResult, AppError> {
+        let operation = if self.settings.options.file_list.is_empty() { "unzip" } else { "extract" };
+        if self.settings.use_folder {
+            run_folder_job(self.settings, operation, api::unzip_archive).await
+        }
+        else {
+            run_source_job(self.settings, Operation::Unzip, operation, false).await
+        }
+    }
+}
+
+
+type FolderOperation = fn(&ArchiveOptions, &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError>;
+
+
+async fn run_folder_job(settings: JobSettings, operation: &str, folder_operation: FolderOperation) -> Result<JobResult, AppError> {
+
+    let options = &settings.options;
+    let run_id = start_run(operation, &settings.pool).await?;
+
+    let started_at = Local::now();
+    let archive_folder = options.zipped.parent().unwrap_or(Path::new("."));
+    let res = match lock_folder(settings.locking, archive_folder) {
+        Err(e) => Err(e),
+        Ok(_lock) => {
+            let (options, observer) = (options.clone(), settings.observer.clone());
+            api::run_blocking(move || folder_operation(&options, observer.as_ref())).await
+        },
+    };
+    if let (Some(pool), Some(run_id)) = (&settings.pool, run_id) {
+        data::record_source_run(run_id, None, operation, started_at, &res, pool).await?;
+    }
+
+    let result = JobResult {
+        run_id,
+        cancelled: matches!(res, Err(AppError::Cancelled)),
+        sources: vec![SourceResult { source_id: None, name: options.zipped.to_string_lossy().to_string(), outcome: runner::to_outcome(res) }],
+    };
+    end_run(&result, settings.keep_going, &settings.pool).await?;
+    Ok(result)
+}
+
+
+async fn run_source_job(settings: JobSettings, operation: Operation, operation_name: &str,
+                        changed_only: bool) -> Result<JobResult, AppError> {
+
+    // Problems with the job's settings are returned as errors before any work is done. The sources
+    // are then processed exactly as they would be from the command line (see runner).
+
+    let pool = settings.pool.as_ref();
+    if pool.is_none() && (changed_only || !settings.source_ids.is_empty()) {
+        return Err(AppError::MissingProgramParameter("database pool (needed for source ids and changed_only)".to_string()));
+    }
+    if settings.sources.is_empty() && settings.source_ids.is_empty() {
+        return Err(AppError::MissingProgramParameter("sources".to_string()));
+    }
+
+    let mut sources = settings.sources;
+    if let Some(pool) = pool {
+        for id in &settings.source_ids {
+            sources.push(data::get_source_details(*id, pool).await?);
+        }
+    }
+
+    let options = &settings.options;
+    let run = runner::SourceRun {
+        operation,
+        operation_name,
+        pars: runner::SourcePars {
+            parent_zipped: options.zipped.clone(),
+            parent_unzipped: options.unzipped.clone(),
+            file_list: options.file_list.clone(),
+            keep: 1,
+            dry_run: false,
+            storage: settings.storage.unwrap_or_else(|| Arc::new(LocalStorage::new(&options.zipped))),
+            keep_local: settings.keep_local,
+        },
+        changed_only,
+        keep_going: settings.keep_going,
+        locking: settings.locking,
+        run_id: start_run(operation_name, &settings.pool).await?,
+        pool,
+        observer: settings.observer.clone(),
+        cancel: options.cancel.clone(),
+    };
+    let result = runner::run_sources(&run, sources, &mut |_, _| ()).await?;
+    end_run(&result, settings.keep_going, &settings.pool).await?;
+    Ok(result)
+}
+
+
+fn lock_folder(locking: bool, folder: &Path) -> Result<Option<RunLock>, AppError> {
+    if locking { locks::acquire_lock(folder).map(Some) } else { Ok(None) }
+}
+
+
+async fn start_run(operation: &str, pool: &Option<Pool<Postgres>>) -> Result<Option<i32>, AppError> {
+    match pool {
+        Some(pool) => Ok(Some(data::start_run_record(operation, pool).await?)),
+        None => Ok(None),
+    }
+}
+
+
+async fn end_run(result: &JobResult, keep_going: bool, pool: &Option<Pool<Postgres>>) -> Result<(), AppError> {
+
+    // The run's outcome is found as for a command line run (see runner::run_outcome).
+
+    if let (Some(pool), Some(run_id)) = (pool, result.run_id) {
+        let failures = result.failures();
+        let outcome = runner::run_outcome(result, keep_going);
+        let error_text = failures.iter()
+            .map(|f| match &f.outcome { SourceOutcome::Failed(e) => format!("{}: {}", f.name, e), _ => String::new() })
+            .reduce(|a, b| format!("{}\n{}", a, b));
+        data::end_run_record(run_id, outcome, error_text, pool).await?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[tokio::test]
+    async fn check_folder_jobs_without_pool() {
+        let folder = std::env::temp_dir().join(format!("zipper_builder_test_{}", std::process::id()));
+        let unzipped = folder.join("files");
+        fs::create_dir_all(&unzipped).unwrap();
+        fs::write(unzipped.join("a.json"), "{}").unwrap();
+        fs::write(unzipped.join("b.tmp"), "temp").unwrap();
+
+        let result = ZipJob::folder(&unzipped, folder.join("archives").join("files.zip"))
+            .exclude("*.tmp")
+            .compression(CompressionMethod::Stored, None)
+            .run().await.unwrap();
+        assert!(result.is_success());
+        assert_eq!(result.run_id, None);
+        assert_eq!(result.summaries()[0].file_count, 1);
+        assert_eq!(fs::read_to_string(folder.join("archives").join(locks::LOCK_FILE_NAME)).unwrap(), "");
+        assert!(locks::acquire_lock(&folder.join("archives")).is_ok());
+
+        let result = UnzipJob::archive(folder.join("archives").join("files.zip"), folder.join("out"))
+            .run().await.unwrap();
+        assert_eq!(result.summaries()[0].file_count, 1);
+        assert!(folder.join("out").join("a.json").exists());
+
+        let result = UnzipJob::archive(folder.join("missing.zip"), folder.join("out")).run().await.unwrap();
+        assert!(!result.is_success());
+        assert_eq!(result.failures().len(), 1);
+
+        let res = ZipJob::sources(&unzipped, folder.join("archives")).source_ids([100120]).run().await;
+        assert!(matches!(res, Err(AppError::MissingProgramParameter(_))));
+
+        let token = CancelToken::new();
+        token.cancel();
+        let result = ZipJob::folder(&unzipped, folder.join("archives").join("cancelled.zip"))
+            .cancel_token(token)
+            .run().await.unwrap();
+        assert!(result.cancelled && !result.is_success());
+        assert!(!folder.join("archives").join("cancelled.zip").exists());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_source_jobs_use_storage_and_keep_going() {
+        let folder = std::env::temp_dir().join(format!("zipper_builder_source_test_{}", std::process::id()));
+        let (unzipped, zipped, stored) = (folder.join("files"), folder.join("zipped"), folder.join("stored"));
+        fs::create_dir_all(unzipped.join("ctg")).unwrap();
+        fs::write(unzipped.join("ctg").join("NCT00000001.json"), "{}").unwrap();
+        let source = |id: i32, name: &str| SourceDetails { id, database_name: name.to_string(), local_folder: String::new(),
+                                                         local_files_grouped: false, grouping_range_by_id: None };
+
+        // The source with no folder fails, but with keep_going the other is still zipped, and stored.
+
+        let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(&stored));
+        let result = ZipJob::sources(&unzipped, &zipped)
+            .source(source(100121, "missing"))
+            .source(source(100120, "ctg"))
+            .storage(storage.clone(), false)
+            .keep_going(true)
+            .run().await.unwrap();
+        assert_eq!((result.failures().len(), result.summaries().len()), (1, 1));
+        assert_eq!(runner::run_outcome(&result, true), "partial");
+        let archive_name = result.summaries()[0].archives[0].name.clone();
+        assert!(stored.join("ctg").join(&archive_name).is_file());
+        assert!(!zipped.join("ctg").join(&archive_name).exists());
+
+        // Without keep_going the job stops at the failure.
+
+        let result = ZipJob::sources(&unzipped, &zipped)
+            .source(source(100121, "missing"))
+            .source(source(100120, "ctg"))
+            .run().await.unwrap();
+        assert_eq!(result.sources.len(), 1);
+        assert_eq!(runner::run_outcome(&result, false), "failed");
+
+        // The stored set is fetched to be unzipped.
+
+        fs::remove_dir_all(&unzipped).unwrap();
+        let result = UnzipJob::sources(&zipped, &unzipped)
+            .source(source(100120, "ctg"))
+            .storage(storage)
+            .run().await.unwrap();
+        assert!(result.is_success());
+        assert_eq!(fs::read_to_string(unzipped.join("ctg").join("NCT00000001.json")).unwrap(), "{}");
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
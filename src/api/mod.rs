@@ -0,0 +1,347 @@
+use std::path::PathBuf;
+use crate::err::AppError;
+use crate::cancel;
+use crate::{jobs, zipper, unzipper, ArchiveDetails, ArchiveSummary, SourceDetails};
+
+mod builders;
+mod tasks;
+
+pub use zip::CompressionMethod;
+pub use cancel::CancelToken;
+pub use crate::storage::{Storage, StoredObject, LocalStorage};
+#[cfg(feature = "s3")]
+pub use crate::storage::S3Storage;
+#[cfg(feature = "sftp")]
+pub use crate::storage::SftpStorage;
+pub use builders::{JobResult, SourceOutcome, SourceResult, UnzipJob, ZipJob};
+pub use tasks::{zip_folder_async, unzip_archive_async, verify_archive_async, zip_source_async, unzip_source_async, verify_source_async};
+pub(crate) use tasks::run_blocking;
+
+// The library API, for programs that embed the crate rather than running it from the command line.
+// Each function here carries out a single operation, for a folder / archive or for one MDR source,
+// with the paths and other settings given in ArchiveOptions. None of them use the configuration
+// file, the database or the log set up; the caller's observer is told of progress as the work is done.
+// ZipJob and UnzipJob (see builders) run whole zips or unzips, with their policies, on top of these.
+// Each function also has an async version (see tasks) that does the work on tokio's blocking threads.
+
+// Callbacks made as an operation proceeds. All have empty defaults, so only those needed have to be
+// implemented. The callbacks are made on the thread doing the work, so should return quickly.
+
+pub trait ArchiveObserver: Send + Sync {
+
+    // The operation has started, for the source (database name) or the folder / archive path.
+    fn source_started(&self, _name: &str) {}
+
+    // The number of files, and their total (uncompressed) size, expected to be processed -
+    // given as zero where it cannot be found in advance.
+    fn totals_found(&self, _file_count: usize, _bytes: u64) {}
+
+    // An archive is being created, or has been opened to be read.
+    fn archive_opened(&self, _archive_name: &str) {}
+
+    // A file has been added to, extracted from (or, when verifying, read from) the current archive.
+    fn file_processed(&self, _file_name: &str, _bytes: u64) {}
+
+    // The current archive is complete (or has been read), with its final file count and size.
+    fn archive_finished(&self, _archive: &ArchiveDetails) {}
+
+    // The operation has completed successfully.
+    fn source_finished(&self, _summary: &ArchiveSummary) {}
+
+    // The source has been skipped (with changed_only), for the reason given.
+    fn source_skipped(&self, _name: &str, _reason: &str) {}
+
+    // The operation has failed, with the error that is then returned.
+    fn error(&self, _error: &AppError) {}
+}
+
+pub struct NoObserver;
+
+impl ArchiveObserver for NoObserver {}
+
+
+// For folder operations 'zipped' is the archive file and 'unzipped' the folder. For source operations
+// they are the parent folders of the sources' archive and file folders, as with mdr_zipped and mdr_unzipped
+// in the configuration. Filters (see Jobs in the README) and compression apply to folder operations,
+// the file list (of file names or trial ids) to unzip_source, which then only extracts those files.
+// Cancelling the token (from another thread) stops the operation after the current file, with
+// AppError::Cancelled; a zip then removes the archives it had written.
+
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    pub zipped: PathBuf,
+    pub unzipped: PathBuf,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub compression: CompressionMethod,
+    pub compression_level: Option<i64>,
+    pub file_list: Vec<String>,
+    pub cancel: CancelToken,
+}
+
+impl ArchiveOptions {
+
+    pub fn new(zipped: impl Into<PathBuf>, unzipped: impl Into<PathBuf>) -> ArchiveOptions {
+        ArchiveOptions {
+            zipped: zipped.into(),
+            unzipped: unzipped.into(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            compression: CompressionMethod::Deflated,
+            compression_level: None,
+            file_list: Vec::new(),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    fn is_selected(&self, name: &str) -> bool {
+        jobs::is_selected(name, &self.include, &self.exclude)
+    }
+
+    fn is_filtered(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+}
+
+
+pub fn zip_folder(options: &ArchiveOptions, observer: &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError> {
+    observe(&options.zipped.to_string_lossy(), observer, ||
+        zipper::zip_filtered_folder(&options.unzipped, &options.zipped, options.compression,
+                                    options.compression_level, &|n| options.is_selected(n), observer, &options.cancel))
+}
+
+
+pub fn unzip_archive(options: &ArchiveOptions, observer: &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError> {
+    observe(&options.zipped.to_string_lossy(), observer, || {
+        if options.is_filtered() {
+            unzipper::unzip_filtered_archive(&options.zipped, &options.unzipped, &|n| options.is_selected(n), observer, &options.cancel)
+        }
+        else {
+            unzipper::unzip_folder(&options.zipped, &options.unzipped, observer, &options.cancel)
+        }
+    })
+}
+
+
+pub fn verify_archive(options: &ArchiveOptions, observer: &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError> {
+    observe(&options.zipped.to_string_lossy(), observer, ||
+        unzipper::verify_folder(&options.zipped, observer))
+}
+
+
+pub fn zip_source(source: SourceDetails, options: &ArchiveOptions, observer: &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError> {
+    let name = source.database_name.clone();
+    observe(&name, observer, ||
+        zipper::zip_mdr_folder(source, &options.unzipped, &options.zipped, observer, &options.cancel))
+}
+
+
+pub fn unzip_source(source: SourceDetails, options: &ArchiveOptions, observer: &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError> {
+    let name = source.database_name.clone();
+    observe(&name, observer, || {
+        if options.file_list.is_empty() {
+            unzipper::unzip_mdr_folder(source, &options.zipped, &options.unzipped, observer, &options.cancel)
+        }
+        else {
+            unzipper::extract_mdr_files(source, &options.file_list, &options.zipped, &options.unzipped, observer, &options.cancel)
+        }
+    })
+}
+
+
+pub fn verify_source(source: SourceDetails, options: &ArchiveOptions, observer: &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError> {
+    let name = source.database_name.clone();
+    observe(&name, observer, ||
+        unzipper::verify_mdr_folder(source, &options.zipped, observer, &options.cancel))
+}
+
+
+fn observe(name: &str, observer: &dyn ArchiveObserver,
+           operation: impl FnOnce() -> Result<ArchiveSummary, AppError>) -> Result<ArchiveSummary, AppError> {
+    observer.source_started(name);
+    let res = operation();
+    match &res {
+        Ok(summary) => observer.source_finished(summary),
+        Err(e) => observer.error(e),
+    }
+    res
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl ArchiveObserver for RecordingObserver {
+        fn source_started(&self, _name: &str) { self.push("started".to_string()) }
+        fn totals_found(&self, file_count: usize, _bytes: u64) { self.push(format!("totals {}", file_count)) }
+        fn archive_opened(&self, archive_name: &str) { self.push(format!("opened {}", archive_name)) }
+        fn file_processed(&self, file_name: &str, bytes: u64) { self.push(format!("file {} {}", file_name, bytes)) }
+        fn archive_finished(&self, archive: &ArchiveDetails) { self.push(format!("finished {} {}", archive.name, archive.file_count)) }
+        fn source_finished(&self, summary: &ArchiveSummary) { self.push(format!("done {}", summary.file_count)) }
+        fn error(&self, _error: &AppError) { self.push("error".to_string()) }
+    }
+
+    impl RecordingObserver {
+        fn push(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.events.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn check_observer_called_when_zipping_and_unzipping() {
+        let folder = std::env::temp_dir().join(format!("zipper_api_test_{}", std::process::id()));
+        let unzipped = folder.join("files");
+        fs::create_dir_all(unzipped.join("sub")).unwrap();
+        fs::write(unzipped.join("a.json"), "{}").unwrap();
+        fs::write(unzipped.join("sub").join("b.txt"), "hello").unwrap();
+
+        let mut options = ArchiveOptions::new(folder.join("files.zip"), &unzipped);
+        let observer = RecordingObserver::default();
+        let summary = zip_folder(&options, &observer).unwrap();
+        assert_eq!(summary.file_count, 2);
+        assert_eq!(observer.take(), vec!["started", "totals 2", "opened files.zip", "file a.json 2",
+                                         "file sub/b.txt 5", "finished files.zip 2", "done 2"]);
+
+        options.unzipped = folder.join("out");
+        options.include = vec!["*.txt".to_string()];
+        let summary = unzip_archive(&options, &observer).unwrap();
+        assert_eq!(summary.file_count, 1);
+        assert_eq!(fs::read_to_string(folder.join("out").join("sub").join("b.txt")).unwrap(), "hello");
+        assert!(observer.take().contains(&"file sub/b.txt 5".to_string()));
+
+        options.zipped = folder.join("missing.zip");
+        assert!(verify_archive(&options, &observer).is_err());
+        assert_eq!(observer.take(), vec!["started", "error"]);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_cancelled_zips_leave_no_archives() {
+        let folder = std::env::temp_dir().join(format!("zipper_api_cancel_test_{}", std::process::id()));
+        let unzipped = folder.join("files");
+        fs::create_dir_all(unzipped.join("ctg")).unwrap();
+        for i in 0..3 {
+            fs::write(unzipped.join("ctg").join(format!("NCT0000000{}.json", i)), "{}").unwrap();
+        }
+
+        let mut options = ArchiveOptions::new(folder.join("ctg.zip"), unzipped.join("ctg"));
+        options.cancel.cancel();
+        assert!(matches!(zip_folder(&options, &NoObserver), Err(AppError::Cancelled)));
+        assert!(!folder.join("ctg.zip").exists());
+
+        options.zipped = folder.join("zipped");
+        options.unzipped = unzipped.clone();
+        let source = SourceDetails { id: 100120, database_name: "ctg".to_string(), local_folder: String::new(),
+                                     local_files_grouped: false, grouping_range_by_id: None };
+        assert!(matches!(zip_source(source, &options, &NoObserver), Err(AppError::Cancelled)));
+        assert_eq!(fs::read_dir(folder.join("zipped").join("ctg")).unwrap().count(), 0);
+        for i in 0..3 {
+            assert_eq!(fs::read_to_string(unzipped.join("ctg").join(format!("NCT0000000{}.json", i))).unwrap(), "{}");
+        }
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_source_files_unchanged_by_zip() {
+        let folder = std::env::temp_dir().join(format!("zipper_api_source_test_{}", std::process::id()));
+        let unzipped = folder.join("files");
+        fs::create_dir_all(unzipped.join("ctg")).unwrap();
+        let contents: Vec<String> = (0..3).map(|i| format!("{{\"id\": \"NCT0000000{}\", \"title\": \"Trial {}\"}}", i, i)).collect();
+        for (i, c) in contents.iter().enumerate() {
+            fs::write(unzipped.join("ctg").join(format!("NCT0000000{}.json", i)), c).unwrap();
+        }
+
+        let options = ArchiveOptions::new(folder.join("zipped"), &unzipped);
+        let source = SourceDetails { id: 100120, database_name: "ctg".to_string(), local_folder: String::new(),
+                                     local_files_grouped: false, grouping_range_by_id: None };
+        let summary = zip_source(source, &options, &NoObserver).unwrap();
+        assert_eq!((summary.file_count, summary.archives.len()), (3, 1));
+        assert!(summary.started_at.unwrap() <= summary.completed_at.unwrap());
+
+        // Each source file is left exactly as it was, and is archived with that content.
+
+        let archive_path = folder.join("zipped").join("ctg").join(&summary.archives[0].name);
+        let mut archive = zip::ZipArchive::new(fs::File::open(archive_path).unwrap()).unwrap();
+        for (i, c) in contents.iter().enumerate() {
+            let name = format!("NCT0000000{}.json", i);
+            assert_eq!(&fs::read_to_string(unzipped.join("ctg").join(&name)).unwrap(), c);
+            let mut archived = String::new();
+            std::io::Read::read_to_string(&mut archive.by_name(&name).unwrap(), &mut archived).unwrap();
+            assert_eq!(&archived, c);
+        }
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_same_day_set_replaced() {
+        let folder = std::env::temp_dir().join(format!("zipper_api_same_day_test_{}", std::process::id()));
+        let unzipped = folder.join("files");
+        for group in ["NCT0000", "NCT0001"] {
+            fs::create_dir_all(unzipped.join("ctg").join(group)).unwrap();
+            fs::write(unzipped.join("ctg").join(group).join(format!("{}0101.json", group)), "{}").unwrap();
+        }
+
+        let options = ArchiveOptions::new(folder.join("zipped"), &unzipped);
+        let source = || SourceDetails { id: 100120, database_name: "ctg".to_string(), local_folder: String::new(),
+                                        local_files_grouped: true, grouping_range_by_id: None };
+        let first = zip_source(source(), &options, &NoObserver).unwrap();
+
+        // Zipped again the same day with fewer files, the archive names differ from those of the first set.
+
+        fs::remove_dir_all(unzipped.join("ctg").join("NCT0001")).unwrap();
+        let second = zip_source(source(), &options, &NoObserver).unwrap();
+        assert_ne!(first.archive_names(), second.archive_names());
+
+        let latest = crate::archive_sets::get_latest_archive_set("ctg", &folder.join("zipped").join("ctg")).unwrap();
+        let latest_names: Vec<&str> = latest.iter().map(|a| a.file_name()).collect();
+        assert_eq!(latest_names, second.archive_names());
+        let index = crate::catalogue::read_index("ctg", &latest[0].date, &folder.join("zipped").join("ctg")).unwrap().unwrap();
+        assert_eq!(index.entries.len(), 1);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_only_latest_set_unzipped() {
+        let folder = std::env::temp_dir().join(format!("zipper_api_latest_test_{}", std::process::id()));
+        let zipped = folder.join("zipped");
+        fs::create_dir_all(zipped.join("ctg")).unwrap();
+
+        // The older set holds a stale version of the file, and a file since removed.
+
+        let write_archive = |name: &str, entries: &[(&str, &str)]| {
+            let mut zip = zip::ZipWriter::new(fs::File::create(zipped.join("ctg").join(name)).unwrap());
+            for (entry, content) in entries {
+                zip.start_file(*entry, zip::write::SimpleFileOptions::default()).unwrap();
+                std::io::Write::write_all(&mut zip, content.as_bytes()).unwrap();
+            }
+            zip.finish().unwrap();
+        };
+        write_archive("ctg 250201 1 to 1.zip", &[("NCT00000001.json", "new")]);
+        write_archive("ctg 250101 1 to 2.zip", &[("NCT00000001.json", "old"), ("NCT00000002.json", "old")]);
+
+        let options = ArchiveOptions::new(&zipped, folder.join("files"));
+        let source = SourceDetails { id: 100120, database_name: "ctg".to_string(), local_folder: String::new(),
+                                     local_files_grouped: false, grouping_range_by_id: None };
+        let summary = unzip_source(source, &options, &NoObserver).unwrap();
+        assert_eq!(summary.archive_names(), vec!["ctg 250201 1 to 1.zip"]);
+        assert_eq!(fs::read_to_string(folder.join("files").join("ctg").join("NCT00000001.json")).unwrap(), "new");
+        assert!(!folder.join("files").join("ctg").join("NCT00000002.json").exists());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use crate::api::{self, ArchiveObserver, ArchiveOptions};
+use crate::err::AppError;
+use crate::{ArchiveSummary, SourceDetails};
+
+// Async versions of the api functions, for callers running on a tokio runtime. The archive work,
+// which is all blocking file IO, is done on tokio's blocking thread pool, so the runtime's own
+// threads stay free for database work, signal handling and progress reporting meanwhile. The
+// options and observer are owned, as the work may outlive the caller's borrow. Observer callbacks
+// are made on the worker thread.
+
+pub async fn zip_folder_async(options: ArchiveOptions, observer: Arc<dyn ArchiveObserver>) -> Result<ArchiveSummary, AppError> {
+    run_blocking(move || api::zip_folder(&options, observer.as_ref())).await
+}
+
+
+pub async fn unzip_archive_async(options: ArchiveOptions, observer: Arc<dyn ArchiveObserver>) -> Result<ArchiveSummary, AppError> {
+    run_blocking(move || api::unzip_archive(&options, observer.as_ref())).await
+}
+
+
+pub async fn verify_archive_async(options: ArchiveOptions, observer: Arc<dyn ArchiveObserver>) -> Result<ArchiveSummary, AppError> {
+    run_blocking(move || api::verify_archive(&options, observer.as_ref())).await
+}
+
+
+pub async fn zip_source_async(source: SourceDetails, options: ArchiveOptions, observer: Arc<dyn ArchiveObserver>) -> Result<ArchiveSummary, AppError> {
+    run_blocking(move || api::zip_source(source, &options, observer.as_ref())).await
+}
+
+
+pub async fn unzip_source_async(source: SourceDetails, options: ArchiveOptions, observer: Arc<dyn ArchiveObserver>) -> Result<ArchiveSummary, AppError> {
+    run_blocking(move || api::unzip_source(source, &options, observer.as_ref())).await
+}
+
+
+pub async fn verify_source_async(source: SourceDetails, options: ArchiveOptions, observer: Arc<dyn ArchiveObserver>) -> Result<ArchiveSummary, AppError> {
+    run_blocking(move || api::verify_source(source, &options, observer.as_ref())).await
+}
+
+
+pub(crate) async fn run_blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T, AppError> + Send + 'static) -> Result<T, AppError> {
+
+    // A panic in the work is passed on to the caller, as it would be if the work were done in line.
+    // The only other failure, the runtime shutting down before the work is done, is treated as a cancellation.
+    // The log context is per thread, so any needed by the work must be set within it.
+
+    match tokio::task::spawn_blocking(work).await {
+        Ok(res) => res,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(AppError::Cancelled),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::api::NoObserver;
+
+    #[tokio::test]
+    async fn check_archive_work_done_off_the_runtime_thread() {
+        let folder = std::env::temp_dir().join(format!("zipper_tasks_test_{}", std::process::id()));
+        let unzipped = folder.join("files");
+        fs::create_dir_all(&unzipped).unwrap();
+        fs::write(unzipped.join("a.json"), "{}").unwrap();
+
+        let options = ArchiveOptions::new(folder.join("files.zip"), &unzipped);
+        let summary = zip_folder_async(options.clone(), Arc::new(NoObserver)).await.unwrap();
+        assert_eq!(summary.file_count, 1);
+        let summary = verify_archive_async(options, Arc::new(NoObserver)).await.unwrap();
+        assert_eq!(summary.file_count, 1);
+
+        let runtime_thread = std::thread::current().id();
+        let worker_thread = run_blocking(|| Ok(std::thread::current().id())).await.unwrap();
+        assert_ne!(runtime_thread, worker_thread);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::File;
+use log::info;
+use zip::ZipArchive;
+use crate::err::AppError;
+use crate::{catalogue, zipper};
+use crate::{ArchiveDetails, ArchiveSummary, SourceDetails};
+
+// The archive names created by the zipper take the form '{db name} {yymmdd} {start} to {end}.zip',
+// where start and end are file positions for single folder sources and folder names for
+// sources with grouped files. All the archives made for a source on the same day form a 'set'.
+
+pub struct ArchiveName {
+    pub path: PathBuf,
+    pub date: String,
+    pub start: String,
+    pub end: String,
+}
+
+impl ArchiveName {
+    pub fn file_name(&self) -> &str {
+        self.path.file_name().and_then(|n| n.to_str()).unwrap_or_default()
+    }
+}
+
+pub fn parse_archive_name(database_name: &str, path: PathBuf) -> Option<ArchiveName> {
+
+    let stem = path.file_stem()?.to_str()?.to_string();
+    let rest = stem.strip_prefix(database_name)?.trim_start();
+    let (date, range) = rest.split_once(' ')?;
+    if date.len() != 6 || !date.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (start, end) = range.split_once(" to ")?;
+
+    Some(ArchiveName {
+        date: date.to_string(),
+        start: start.trim().to_string(),
+        end: end.trim().to_string(),
+        path,
+    })
+}
+
+
+pub fn get_archive_sets(database_name: &str, srce_folder: &Path) -> Result<BTreeMap<String, Vec<ArchiveName>>, AppError> {
+
+    // Returns the source's archives grouped by set date, oldest set first.
+
+    let entries = fs::read_dir(srce_folder)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, srce_folder.to_owned()))?;
+
+    let paths = entries
+        .filter_map(|e| Some(e.ok()?.path()))
+        .filter(|p| p.is_file());
+    Ok(group_archive_sets(database_name, paths))
+}
+
+
+pub fn group_archive_sets(database_name: &str, paths: impl IntoIterator<Item = PathBuf>) -> BTreeMap<String, Vec<ArchiveName>> {
+
+    // Also used for the names of archives held in storage (see storage), so that sets are
+    // identified in the same way wherever they are kept.
+
+    let mut sets: BTreeMap<String, Vec<ArchiveName>> = BTreeMap::new();
+    let archives = paths.into_iter()
+        .filter(|p| p.extension().is_some_and(|x| x == "zip"))
+        .filter_map(|p| parse_archive_name(database_name, p));
+    for a in archives {
+        sets.entry(a.date.clone()).or_default().push(a);
+    }
+    for set in sets.values_mut() {
+        set.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    sets
+}
+
+
+pub fn get_latest_archive_set(database_name: &str, srce_folder: &Path) -> Result<Vec<ArchiveName>, AppError> {
+
+    // Older sets may also be present in the folder, but are normally ignored.
+
+    let mut sets = get_archive_sets(database_name, srce_folder)?;
+    Ok(sets.pop_last().map(|(_, set)| set).unwrap_or_default())
+}
+
+
+pub fn remove_archive_set(database_name: &str, date: &str, srce_folder: &Path) -> Result<usize, AppError> {
+
+    // Used before a set is zipped, as a set made earlier on the same day would otherwise share its
+    // name. Any of that set's archives not overwritten (e.g. if fewer files are zipped the second
+    // time) would then be taken as part of the new set. Returns the number of archives removed.
+
+    let mut sets = get_archive_sets(database_name, srce_folder)?;
+    let set = sets.remove(date).unwrap_or_default();
+    for a in &set {
+        fs::remove_file(&a.path)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, a.path.to_owned()))?;
+    }
+    let index_path = catalogue::index_path(database_name, date, srce_folder);
+    if index_path.is_file() {
+        fs::remove_file(&index_path)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, index_path.to_owned()))?;
+    }
+    if !set.is_empty() {
+        info!("{} archive(s) of the earlier set '{} {}' removed", set.len(), database_name, date);
+    }
+    Ok(set.len())
+}
+
+
+pub fn archive_may_hold(archive: &ArchiveName, wanted: &str) -> bool {
+
+    // Without a folder part the file could be in any of the archives.
+
+    match wanted.split_once('/') {
+        Some((folder, _)) => folder >= archive.start.as_str() && (archive.end.is_empty() || folder <= archive.end.as_str()),
+        None => true,
+    }
+}
+
+
+pub fn list_mdr_archives(source: &SourceDetails, parent_zipped_src_fdr: &Path) -> Result<usize, AppError> {
+
+    // Used with list. Reports each archive set held for the source, most recent first.
+
+    let srce_folder = parent_zipped_src_fdr.join(&source.database_name);
+    let sets = get_archive_sets(&source.database_name, &srce_folder)?;
+    if sets.is_empty() {
+        println!("{} ({}): no archive sets in {:?}", source.database_name, source.id, srce_folder);
+    }
+
+    for (date, set) in sets.iter().rev() {
+        let set_bytes: u64 = set.iter()
+                .filter_map(|a| fs::metadata(&a.path).ok())
+                .map(|m| m.len())
+                .sum();
+        let indexed = catalogue::read_index(&source.database_name, date, &srce_folder)?;
+        let file_count = match &indexed {
+            Some(c) => c.entries.len().to_string(),
+            None => "unknown (no index)".to_string(),
+        };
+        println!("{} ({}): set '{} {}', {} archive(s), {} bytes, files: {}",
+                    source.database_name, source.id, source.database_name, date, set.len(), set_bytes, file_count);
+        for a in set {
+            println!("    {}", a.file_name());
+        }
+    }
+    Ok(sets.len())
+}
+
+
+pub fn prune_mdr_archives(source: &SourceDetails, parent_zipped_src_fdr: &Path, keep: usize, dry_run: bool) -> Result<ArchiveSummary, AppError> {
+
+    // Used with prune. Deletes all but the most recent 'keep' archive sets, with their
+    // catalogue indexes. The summary lists the archives deleted (or to be deleted, if a dry run).
+
+    let srce_folder = parent_zipped_src_fdr.join(&source.database_name);
+    let sets = get_archive_sets(&source.database_name, &srce_folder)?;
+
+    let mut summary = ArchiveSummary::default();
+    let prune_num = sets.len().saturating_sub(keep);
+
+    for (date, set) in sets.into_iter().take(prune_num) {
+        for a in set {
+            let archive_bytes = fs::metadata(&a.path).map(|m| m.len()).unwrap_or_default();
+            if dry_run {
+                info!("{:?} would be deleted", a.path);
+            }
+            else {
+                fs::remove_file(&a.path)
+                    .map_err(|e| AppError::IoWriteErrorWithPath(e, a.path.to_owned()))?;
+                info!("{:?} deleted", a.path);
+            }
+            summary.bytes_in += archive_bytes;
+            summary.archives.push(ArchiveDetails { name: a.file_name().to_string(), file_count: 0, archive_bytes });
+        }
+
+        let index_path = catalogue::index_path(&source.database_name, &date, &srce_folder);
+        if !dry_run && index_path.is_file() {
+            fs::remove_file(&index_path)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, index_path.to_owned()))?;
+        }
+    }
+
+    info!("{}: {} archive set(s) {}, {} archive(s), {} bytes", source.database_name, prune_num,
+              if dry_run {"would be pruned"} else {"pruned"}, summary.archives.len(), summary.bytes_in);
+    Ok(summary)
+}
+
+
+pub fn diff_mdr_folder(source: &SourceDetails, parent_zipped_src_fdr: &Path, parent_unzipped_fdr: &Path) -> Result<ArchiveSummary, AppError> {
+
+    // Used with diff. Compares the entries in the source's latest archive set with the files
+    // in its unzipped folder. The catalogue index is used for the archive contents if present.
+
+    let srce_folder = parent_zipped_src_fdr.join(&source.database_name);
+    let unzipped_folder = parent_unzipped_fdr.join(&source.database_name);
+
+    let set = get_latest_archive_set(&source.database_name, &srce_folder)?;
+    let mut archived: HashMap<String, u64> = HashMap::new();
+
+    match set.first().map(|a| catalogue::read_index(&source.database_name, &a.date, &srce_folder)).transpose()?.flatten() {
+        Some(c) => {
+            archived.extend(c.entries.into_iter().map(|e| (e.name, e.size)));
+        },
+        None => {
+            for a in &set {
+                add_archive_entries(&a.path, &mut archived)?;
+            }
+        },
+    }
+
+    let archive_paths: Vec<PathBuf> = set.into_iter().map(|a| a.path).collect();
+    compare_with_folder(&source.database_name, &archive_paths, archived, &unzipped_folder)
+}
+
+
+pub fn diff_folder(zipped_source: &Path, unzipped_folder: &Path) -> Result<ArchiveSummary, AppError> {
+
+    // Used with diff -f. Compares a single archive with the unzipped folder.
+
+    let mut archived: HashMap<String, u64> = HashMap::new();
+    add_archive_entries(zipped_source, &mut archived)?;
+    let label = zipped_source.display().to_string();
+    compare_with_folder(&label, &[zipped_source.to_owned()], archived, unzipped_folder)
+}
+
+
+fn add_archive_entries(archive_path: &Path, archived: &mut HashMap<String, u64>) -> Result<(), AppError> {
+
+    let file = File::open(archive_path)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, archive_path.to_owned()))?;
+    let mut archive = ZipArchive::new(file)
+            .map_err(|e| AppError::UnzipError(e, archive_path.to_owned()))?;
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)
+                .map_err(|e| AppError::UnzipError(e, archive_path.to_owned()))?;
+        if !entry.is_dir() {
+            archived.insert(entry.name().to_string(), entry.size());
+        }
+    }
+    Ok(())
+}
+
+
+fn compare_with_folder(label: &str, archive_paths: &[PathBuf], mut archived: HashMap<String, u64>,
+                       unzipped_folder: &Path) -> Result<ArchiveSummary, AppError> {
+
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    if unzipped_folder.is_dir() {
+        zipper::collect_files(unzipped_folder, unzipped_folder, &mut files)?;
+    }
+    let mut on_disk: HashMap<String, u64> = HashMap::new();
+    for (name, path) in files {
+        let size = fs::metadata(&path).map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))?.len();
+        on_disk.insert(name, size);
+    }
+
+    let mut summary = ArchiveSummary::default();
+    for p in archive_paths {
+        summary.add_archive(p, false)?;
+    }
+
+    let mut only_on_disk: Vec<String> = Vec::new();
+    let mut size_differs: Vec<String> = Vec::new();
+    for (name, len) in &on_disk {
+        match archived.remove(name) {
+            Some(size) => if size != *len { size_differs.push(name.clone()) },
+            None => only_on_disk.push(name.clone()),
+        }
+    }
+    let mut only_archived: Vec<String> = archived.into_keys().collect();
+
+    only_archived.sort();
+    only_on_disk.sort();
+    size_differs.sort();
+
+    println!("{}: {} file(s) in archives, {} in {:?}", label, summary.file_count, on_disk.len(), unzipped_folder);
+    report_differences(label, "only in archives", &only_archived);
+    report_differences(label, "only in unzipped folder", &only_on_disk);
+    report_differences(label, "with a different size", &size_differs);
+
+    Ok(summary)
+}
+
+
+fn report_differences(label: &str, description: &str, names: &[String]) {
+
+    // Only the first few names are listed, as there may be many thousands.
+
+    let max_listed = 20;
+    println!("{}: {} file(s) {}", label, names.len(), description);
+    for n in names.iter().take(max_listed) {
+        println!("    {}", n);
+    }
+    if names.len() > max_listed {
+        println!("    ... and {} more", names.len() - max_listed);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_archive_names_parsed_correctly() {
+        let a = parse_archive_name("ctg", PathBuf::from("ctg 250101 1 to 10000.zip")).unwrap();
+        assert_eq!(a.date, "250101");
+        assert_eq!(a.start, "1");
+        assert_eq!(a.end, "10000");
+
+        let a = parse_archive_name("ctg", PathBuf::from("ctg 250101 NCT0000 to NCT0012.zip")).unwrap();
+        assert_eq!(a.start, "NCT0000");
+        assert_eq!(a.end, "NCT0012");
+        assert_eq!(a.file_name(), "ctg 250101 NCT0000 to NCT0012.zip");
+    }
+
+    #[test]
+    fn check_other_names_not_parsed() {
+        assert!(parse_archive_name("ctg", PathBuf::from("euctr 250101 1 to 10000.zip")).is_none());
+        assert!(parse_archive_name("ctg", PathBuf::from("ctg 2501 1 to 10000.zip")).is_none());
+        assert!(parse_archive_name("ctg", PathBuf::from("ctg 250101 index.json")).is_none());
+    }
+
+    #[test]
+    fn check_folder_ranges_used_to_select_archives() {
+        let a = parse_archive_name("ctg", PathBuf::from("ctg 250101 NCT0000 to NCT0012.zip")).unwrap();
+        assert!(archive_may_hold(&a, "NCT0005/NCT00050101.json"));
+        assert!(!archive_may_hold(&a, "NCT0013/NCT00130101.json"));
+        assert!(archive_may_hold(&a, "NCT00130101.json"));
+    }
+}
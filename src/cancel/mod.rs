@@ -0,0 +1,122 @@
+use std::sync::{Arc, Once};
+use std::sync::atomic::{AtomicBool, Ordering};
+use log::error;
+use crate::err::AppError;
+
+// Runs are cancelled cooperatively. The zipper and unzipper functions check the run's CancelToken
+// before each file, so the file being processed is always completed. A cancelled zip removes the
+// archive it was writing (and any already completed for the same set, which would otherwise look
+// like a complete set); an unzip leaves the files already extracted. The run is then recorded, and
+// reported, as cancelled. Command line runs are cancelled by SIGINT (Ctrl-C) or SIGTERM (on Windows,
+// by Ctrl-C only).
+// A second signal ends the program at once.
+
+static SIGNAL_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    on_signal: bool,
+}
+
+impl CancelToken {
+
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    // A token that is also cancelled when SIGINT or SIGTERM is received.
+
+    pub fn on_signal() -> CancelToken {
+        install_signal_handlers();
+        CancelToken { cancelled: Arc::new(AtomicBool::new(false)), on_signal: true }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || (self.on_signal && signal_received())
+    }
+
+    pub(crate) fn check(&self) -> Result<(), AppError> {
+        if self.is_cancelled() {
+            Err(AppError::Cancelled)
+        }
+        else {
+            Ok(())
+        }
+    }
+}
+
+
+pub fn signal_received() -> bool {
+    SIGNAL_RECEIVED.load(Ordering::SeqCst)
+}
+
+
+#[cfg(test)]
+pub(crate) fn set_signal_received(received: bool) {
+    SIGNAL_RECEIVED.store(received, Ordering::SeqCst);
+}
+
+
+pub fn install_signal_handlers() {
+
+    // The signals are awaited on a thread of their own, with its own runtime, so they are still
+    // seen while the caller's runtime is busy with (blocking) archive work. Installing more than
+    // once has no further effect.
+
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let listener = std::thread::Builder::new().name("signals".to_string()).spawn(|| {
+            let res = tokio::runtime::Builder::new_current_thread().enable_all().build()
+                .and_then(|runtime| runtime.block_on(listen_for_signals()));
+            if let Err(e) = res {
+                error!("Unable to listen for signals: {}", e);
+            }
+        });
+        if let Err(e) = listener {
+            error!("Unable to listen for signals: {}", e);
+        }
+    });
+}
+
+
+async fn listen_for_signals() -> std::io::Result<()> {
+
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    loop {
+        #[cfg(unix)]
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res?,
+            _ = terminate.recv() => (),
+        }
+        #[cfg(not(unix))]
+        tokio::signal::ctrl_c().await?;
+
+        if SIGNAL_RECEIVED.swap(true, Ordering::SeqCst) {
+            std::process::exit(crate::err::EXIT_CANCELLED as i32);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_token_cancelled_by_any_clone() {
+        let token = CancelToken::new();
+        let other = token.clone();
+        assert!(!token.is_cancelled());
+        assert!(token.check().is_ok());
+        other.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(AppError::Cancelled)));
+    }
+}
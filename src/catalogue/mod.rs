@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::fs::File;
+use log::info;
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+use chrono::Local;
+use crate::err::AppError;
+
+// A catalogue index is written alongside each set of MDR archives, as a json 'sidecar' file
+// named '{db name} {yymmdd} index.json'. It maps each entry name to the archive holding it,
+// so that a file can be located (or extracted) without opening the archives one by one.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogueEntry {
+    pub name: String,
+    pub archive: String,
+    pub offset: u64,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub crc32: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Catalogue {
+    pub database_name: String,
+    pub set_date: String,
+    pub created_at: String,
+    pub entries: Vec<CatalogueEntry>,
+}
+
+impl Catalogue {
+
+    pub fn find(&self, wanted: &str) -> Vec<&CatalogueEntry> {
+
+        // Match either on the full entry name or on the final (file name) part of it.
+
+        self.entries.iter()
+            .filter(|e| e.name == wanted || e.name.rsplit('/').next() == Some(wanted))
+            .collect()
+    }
+}
+
+
+pub fn write_index(database_name: &str, set_date: &str, archives: &[PathBuf], dest_folder: &Path) -> Result<PathBuf, AppError> {
+
+    let mut entries = Vec::new();
+
+    for a in archives {
+        let archive_name = a.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+        let file = File::open(a)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, a.to_owned()))?;
+        let mut archive = ZipArchive::new(file)
+                .map_err(|e| AppError::UnzipError(e, a.to_owned()))?;
+
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i)
+                    .map_err(|e| AppError::UnzipError(e, a.to_owned()))?;
+            entries.push(CatalogueEntry {
+                name: entry.name().to_string(),
+                archive: archive_name.clone(),
+                offset: entry.data_start(),
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+                crc32: entry.crc32(),
+            });
+        }
+    }
+
+    let catalogue = Catalogue {
+        database_name: database_name.to_string(),
+        set_date: set_date.to_string(),
+        created_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        entries,
+    };
+
+    let index_path = index_path(database_name, set_date, dest_folder);
+    let json = serde_json::to_string_pretty(&catalogue)?;
+    fs::write(&index_path, json)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, index_path.to_owned()))?;
+
+    info!("{:?} index written, listing {} entries", index_path, catalogue.entries.len());
+    Ok(index_path)
+}
+
+
+pub fn read_index(database_name: &str, set_date: &str, folder: &Path) -> Result<Option<Catalogue>, AppError> {
+
+    let index_path = index_path(database_name, set_date, folder);
+    if !index_path.is_file() {
+        return Ok(None);
+    }
+
+    let json = fs::read_to_string(&index_path)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, index_path.to_owned()))?;
+    Ok(Some(serde_json::from_str(&json)?))
+}
+
+
+pub fn read_latest_index(database_name: &str, folder: &Path) -> Result<Option<Catalogue>, AppError> {
+
+    let entries = fs::read_dir(folder)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, folder.to_owned()))?;
+
+    let latest_date = entries
+        .filter_map(|e| Some(e.ok()?.file_name().to_str()?.to_string()))
+        .filter_map(|n| {
+            let date = n.strip_prefix(database_name)?.trim_start().strip_suffix(" index.json")?;
+            if date.len() == 6 && date.chars().all(|c| c.is_ascii_digit()) { Some(date.to_string()) } else { None }
+        })
+        .max();
+
+    match latest_date {
+        Some(d) => read_index(database_name, &d, folder),
+        None => Ok(None),
+    }
+}
+
+
+pub fn locate(database_name: &str, file_list: &[String], zipped_folder: &Path) -> Result<usize, AppError> {
+
+    // Used with -l. Reports which archive holds each named file, using the latest index.
+
+    let catalogue = match read_latest_index(database_name, zipped_folder)? {
+        Some(c) => c,
+        None => {
+            let p = "No catalogue index found for source".to_string();
+            let d = format!("Expected a '{} yymmdd index.json' file in {:?}", database_name, zipped_folder);
+            return Err(AppError::FileSystemError(p, d));
+        }
+    };
+
+    let mut found = 0;
+    for f in file_list {
+        let wanted = normalise_file_name(f);
+        let hits = catalogue.find(&wanted);
+        if hits.is_empty() {
+            println!("{}: {} not found in archive set {}", database_name, wanted, catalogue.set_date);
+        }
+        for h in hits {
+            println!("{}: {} is in '{}' (offset {}, size {}, crc32 {:08x})",
+                        database_name, h.name, h.archive, h.offset, h.size, h.crc32);
+            found += 1;
+        }
+    }
+    Ok(found)
+}
+
+
+pub fn normalise_file_name(f: &str) -> String {
+
+    // Trial ids are turned into file names by adding the json extension.
+
+    let f = f.trim().replace('\\', "/");
+    if Path::new(&f).extension().is_some() { f } else { format!("{}.json", f) }
+}
+
+
+pub fn index_path(database_name: &str, set_date: &str, folder: &Path) -> PathBuf {
+    folder.join(format!("{} {} index.json", database_name, set_date))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_catalogue() -> Catalogue {
+        let entry = |name: &str, archive: &str| CatalogueEntry {
+            name: name.to_string(), archive: archive.to_string(),
+            offset: 0, size: 10, compressed_size: 5, crc32: 0,
+        };
+        Catalogue {
+            database_name: "ctg".to_string(),
+            set_date: "250101".to_string(),
+            created_at: "2025-01-01 00:00:00".to_string(),
+            entries: vec![entry("NCT0000/NCT00000102.json", "ctg 250101 NCT0000 to NCT0001.zip"),
+                          entry("NCT0001/NCT00010104.json", "ctg 250101 NCT0000 to NCT0001.zip"),
+                          entry("NCT0002/NCT00020101.json", "ctg 250101 NCT0002 to NCT0003.zip")],
+        }
+    }
+
+    #[test]
+    fn check_trial_ids_become_file_names() {
+        assert_eq!(normalise_file_name(" NCT00000102 "), "NCT00000102.json");
+        assert_eq!(normalise_file_name("NCT00000102.json"), "NCT00000102.json");
+        assert_eq!(normalise_file_name("NCT0000\\NCT00000102"), "NCT0000/NCT00000102.json");
+    }
+
+    #[test]
+    fn check_entries_found_by_file_or_full_name() {
+        let c = test_catalogue();
+        assert_eq!(c.find("NCT00020101.json")[0].archive, "ctg 250101 NCT0002 to NCT0003.zip");
+        assert_eq!(c.find("NCT0001/NCT00010104.json")[0].archive, "ctg 250101 NCT0000 to NCT0001.zip");
+        assert!(c.find("NCT99999999.json").is_empty());
+    }
+}
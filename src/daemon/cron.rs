@@ -0,0 +1,216 @@
+/***************************************************************************
+ * Parses cron expressions, of the usual five fields (minute, hour, day of
+ * month, month, day of week), and finds the next time each is due. Fields
+ * may be '*', values, ranges and lists, with optional steps ('0-59/15',
+ * '1-5', '0,30'). Month and day names (jan, mon) and the '@daily' style
+ * shortcuts are also accepted. As in cron, if both the day of month and
+ * day of week are restricted a day matching either is used.
+ ***************************************************************************/
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    pub expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl CronSchedule {
+
+    pub fn parse(expression: &str) -> Result<CronSchedule, String> {
+
+        let expanded = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            e => e,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("'{}' should have 5 fields (minute hour day month weekday)", expression));
+        }
+
+        // Day of week 7 is accepted for Sunday, and folded onto 0.
+
+        let mut weekdays = parse_field(fields[4], 0, 7, &DAY_NAMES)?;
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        weekdays.truncate(7);
+
+        Ok(CronSchedule {
+            expression: expression.trim().to_string(),
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+
+        // Local times skipped by a daylight saving change are passed over.
+
+        let mut t = after.naive_local();
+        loop {
+            let next = self.next_naive_after(t)?;
+            if let Some(local) = Local.from_local_datetime(&next).earliest() {
+                return Some(local);
+            }
+            t = next;
+        }
+    }
+
+
+    fn next_naive_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+
+        // Starts at the next whole minute, and moves forward by the largest step that
+        // cannot skip a match. Gives up after 5 years (e.g. for '0 0 30 2 *').
+
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = after + Duration::days(5 * 366);
+
+        while t <= limit {
+            if !self.months[t.month() as usize] {
+                let (y, m) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = NaiveDate::from_ymd_opt(y, m, 1)?.and_hms_opt(0, 0, 0)?;
+            }
+            else if !self.day_matches(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            }
+            else if !self.hours[t.hour() as usize] {
+                t = t.with_minute(0)? + Duration::hours(1);
+            }
+            else if !self.minutes[t.minute() as usize] {
+                t += Duration::minutes(1);
+            }
+            else {
+                return Some(t);
+            }
+        }
+        None
+    }
+
+
+    fn day_matches(&self, d: NaiveDate) -> bool {
+        let day = self.days[d.day() as usize];
+        let weekday = self.weekdays[d.weekday().num_days_from_sunday() as usize];
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<bool>, String> {
+
+    // Returns a flag for each value from 0 to max.
+
+    let mut allowed = vec![false; max as usize + 1];
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().ok().filter(|s| *s > 0)
+                                    .ok_or_else(|| format!("invalid step in '{}'", item))?),
+            None => (item, 1),
+        };
+        let (from, to) = if range == "*" {
+            (min, max)
+        }
+        else if let Some((a, b)) = range.split_once('-') {
+            (parse_value(a, names)?, parse_value(b, names)?)
+        }
+        else {
+            let v = parse_value(range, names)?;
+            (v, if step > 1 { max } else { v })
+        };
+        if from < min || to > max || from > to {
+            return Err(format!("'{}' is outside the range {}-{}", item, min, max));
+        }
+        for v in (from..=to).step_by(step as usize) {
+            allowed[v as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+
+fn parse_value(value: &str, names: &[&str]) -> Result<u32, String> {
+
+    // Names are given as their first three letters, and are numbered from 1 for
+    // months and from 0 for days.
+
+    let lower = value.to_lowercase();
+    if let Some(i) = names.iter().position(|n| lower == *n) {
+        return Ok(i as u32 + if names.len() == 12 { 1 } else { 0 });
+    }
+    value.parse::<u32>().map_err(|_| format!("'{}' is not a valid value", value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn check_daily_and_stepped_schedules() {
+        let c = CronSchedule::parse("30 2 * * *").unwrap();
+        assert_eq!(c.next_naive_after(at("2025-01-01 01:00")), Some(at("2025-01-01 02:30")));
+        assert_eq!(c.next_naive_after(at("2025-01-01 02:30")), Some(at("2025-01-02 02:30")));
+
+        let c = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(c.next_naive_after(at("2025-01-01 10:07")), Some(at("2025-01-01 10:15")));
+        assert_eq!(c.next_naive_after(at("2025-01-01 10:50")), Some(at("2025-01-01 11:00")));
+    }
+
+    #[test]
+    fn check_names_weekdays_and_shortcuts() {
+        let c = CronSchedule::parse("0 22 * * mon-fri").unwrap();
+        // 2025-01-04 is a Saturday
+        assert_eq!(c.next_naive_after(at("2025-01-04 12:00")), Some(at("2025-01-06 22:00")));
+
+        let c = CronSchedule::parse("0 0 1 jan,jul *").unwrap();
+        assert_eq!(c.next_naive_after(at("2025-02-01 00:00")), Some(at("2025-07-01 00:00")));
+
+        let c = CronSchedule::parse("@weekly").unwrap();
+        assert_eq!(c.next_naive_after(at("2025-01-01 00:00")), Some(at("2025-01-05 00:00")));
+    }
+
+    #[test]
+    fn check_day_or_weekday_matched_when_both_given() {
+        let c = CronSchedule::parse("0 0 15 * 7").unwrap();
+        assert_eq!(c.next_naive_after(at("2025-01-01 00:00")), Some(at("2025-01-05 00:00")));
+        assert_eq!(c.next_naive_after(at("2025-01-13 00:00")), Some(at("2025-01-15 00:00")));
+    }
+
+    #[test]
+    fn check_invalid_expressions_rejected() {
+        assert!(CronSchedule::parse("0 2 * *").is_err());
+        assert!(CronSchedule::parse("60 2 * * *").is_err());
+        assert!(CronSchedule::parse("0 2 * * */0").is_err());
+        assert!(CronSchedule::parse("0 2 * foo *").is_err());
+        assert!(CronSchedule::parse("0 0 30 2 *").unwrap().next_naive_after(at("2025-01-01 00:00")).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+pub mod cron;
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
+use sqlx::{Pool, Postgres};
+use tokio::task::JoinHandle;
+use crate::err::AppError;
+use crate::setup;
+use crate::cancel::CancelToken;
+use crate::setup::config_reader::SchedulePars;
+
+// In daemon mode the program stays running, and carries out each of the [[schedules]] in the
+// configuration when its cron expression is due. Each scheduled command is processed exactly as
+// it would be on the command line, and is recorded as a separate run. A schedule that is due while
+// its previous run is still in progress is skipped, rather than run twice at once. On SIGTERM (or
+// SIGINT) no further runs are started, those in progress are cancelled as a command line run would
+// be, and the daemon stops once they have finished (a second signal stops it at once).
+
+pub async fn run_daemon(schedules: Vec<SchedulePars>, config_string: String, pool: Pool<Postgres>) -> Result<(), AppError> {
+
+    // All the scheduled commands are checked before the daemon starts.
+
+    let mut next_due: Vec<DateTime<Local>> = Vec::new();
+    for s in &schedules {
+        setup::get_command_params(&s.command, &config_string)?;
+        match s.cron.next_after(Local::now()) {
+            Some(t) => next_due.push(t),
+            None => return Err(AppError::ConfigurationError(format!("Schedule '{}' is never due.", s.name),
+                                format!("Cron expression is: {}", s.cron.expression))),
+        }
+    }
+    for (s, t) in schedules.iter().zip(&next_due) {
+        info!("Schedule '{}' ({}): '{}', next due at {}", s.name, s.cron.expression, s.command.join(" "), t.format("%Y-%m-%d %H:%M"));
+    }
+
+    let cancel = CancelToken::on_signal();
+    let config_string = Arc::new(config_string);
+    let running: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    while !cancel.is_cancelled() {
+
+        let now = Local::now();
+        for (s, due) in schedules.iter().zip(next_due.iter_mut()) {
+            if *due > now {
+                continue;
+            }
+            *due = s.cron.next_after(now).unwrap_or(now + chrono::Duration::days(36500));
+
+            if !running.lock().unwrap().insert(s.name.clone()) {
+                warn!("Schedule '{}' skipped at {}: its previous run is still in progress", s.name, now.format("%H:%M"));
+                continue;
+            }
+
+            let (schedule, config_string, pool, running) = (s.clone(), config_string.clone(), pool.clone(), running.clone());
+            let cancel = cancel.clone();
+            handles.push(tokio::spawn(async move {
+                info!("Schedule '{}' started: {}", schedule.name, schedule.command.join(" "));
+                match run_scheduled(&schedule, &config_string, &cancel, &pool).await {
+                    Ok(_) => info!("Schedule '{}' completed", schedule.name),
+                    Err(e) => error!("Schedule '{}' failed: {}", schedule.name, e),
+                }
+                running.lock().unwrap().remove(&schedule.name);
+            }));
+        }
+
+        handles.retain(|h| !h.is_finished());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    info!("Shutdown requested - waiting for {} scheduled run(s) in progress", handles.len());
+    for h in handles {
+        let _ = h.await;
+    }
+    info!("Daemon stopped");
+    Ok(())
+}
+
+
+async fn run_scheduled(schedule: &SchedulePars, config_string: &str, cancel: &CancelToken, pool: &Pool<Postgres>) -> Result<(), AppError> {
+    let params = setup::get_command_params(&schedule.command, config_string)?;
+    crate::execute(&params, cancel, pool).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::cancel;
+    use crate::daemon::cron::CronSchedule;
+
+    #[tokio::test]
+    async fn check_scheduled_run_stopped_by_signal() {
+        let folder = std::env::temp_dir().join(format!("zipper_daemon_test_{}", std::process::id()));
+        fs::create_dir_all(folder.join("files")).unwrap();
+        fs::write(folder.join("files").join("a.json"), "{}").unwrap();
+        let archive = folder.join("zipped").join("files.zip");
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+"#;
+        let schedule = SchedulePars {
+            name: "nightly".to_string(),
+            cron: CronSchedule::parse("0 2 * * *").unwrap(),
+            command: ["zip", "-f", "-t", "--fz", &archive.to_string_lossy(), "--fu", &folder.join("files").to_string_lossy()]
+                        .iter().map(|s| s.to_string()).collect(),
+        };
+
+        // A test run (-t) of a folder is not recorded, so the pool is never connected.
+
+        let pool = sqlx::postgres::PgPoolOptions::new().connect_lazy("postgres://user_name@localhost/none").unwrap();
+        let token = CancelToken::on_signal();
+        cancel::set_signal_received(true);
+        let res = run_scheduled(&schedule, config, &token, &pool).await;
+        cancel::set_signal_received(false);
+
+        assert!(matches!(res, Err(AppError::Cancelled)));
+        assert!(!archive.exists());
+        run_scheduled(&schedule, config, &token, &pool).await.unwrap();
+        assert!(archive.exists());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
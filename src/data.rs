@@ -1,6 +1,8 @@
 use crate::AppError;
-use crate::SourceDetails;
+use crate::{ArchiveSummary, SourceDetails};
 use sqlx::{Pool, Postgres};
+use chrono::{DateTime, Local};
+use log::error;
 
 
 pub async fn get_all_ids(pool: &Pool<Postgres>) -> Result<Vec<i32>, AppError> {
@@ -13,6 +15,17 @@ pub async fn get_all_ids(pool: &Pool<Postgres>) -> Result<Vec<i32>, AppError> {
   }
 
 
+pub async fn get_source_catalogue(pool: &Pool<Postgres>) -> Result<Vec<(i32, String)>, AppError> {
+
+    // All sources, with no filtering, so that any source can be selected explicitly.
+
+    let sql = r#"select id, database_name from source.parameters
+    order by id"#;
+    sqlx::query_as(sql).fetch_all(pool).await
+              .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
+
+
 pub async fn get_source_details(source_id: i32, pool: &Pool<Postgres>) -> Result<SourceDetails, AppError> {
 
     let sql = r#"select id, database_name, local_folder, 
@@ -24,5 +37,129 @@ pub async fn get_source_details(source_id: i32, pool: &Pool<Postgres>) -> Result
 }
 
 
+pub async fn apply_migrations(pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    // Migrations (in the migrations folder) are embedded at compile time, and any 
+    // not yet applied to the database are run here. At present they create the
+    // zipping schema audit tables.
+
+    sqlx::migrate!().run(pool).await
+            .map_err(AppError::MigrationError)
+}
+
+
+pub async fn start_run_record(operation: &str, pool: &Pool<Postgres>) -> Result<i32, AppError> {
+
+    let sql = r#"insert into zipping.runs (started_at, operation)
+    values ($1, $2) returning id"#;
+    sqlx::query_scalar(sql).bind(Local::now()).bind(operation).fetch_one(pool).await
+             .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
+
+
+pub async fn end_run_record(run_id: i32, outcome: &str, error_text: Option<String>, pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    let sql = r#"update zipping.runs 
+    set ended_at = $2, outcome = $3, error_text = $4
+    where id = $1"#;
+    sqlx::query(sql).bind(run_id).bind(Local::now()).bind(outcome).bind(error_text)
+             .execute(pool).await
+             .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(())
+}
+
+
+pub async fn record_source_run(run_id: i32, source_id: Option<i32>, operation: &str, started_at: DateTime<Local>, 
+                     res: &Result<ArchiveSummary, AppError>, pool: &Pool<Postgres>) -> Result<(), AppError> {
+    insert_run_source(run_id, source_id, None, operation, started_at, res, pool).await
+}
+
 
-       
\ No newline at end of file
+pub async fn record_job_run(run_id: i32, job_name: &str, operation: &str, started_at: DateTime<Local>, 
+                     res: &Result<ArchiveSummary, AppError>, pool: &Pool<Postgres>) -> Result<(), AppError> {
+    insert_run_source(run_id, None, Some(job_name), operation, started_at, res, pool).await
+}
+
+
+async fn insert_run_source(run_id: i32, source_id: Option<i32>, job_name: Option<&str>, operation: &str, started_at: DateTime<Local>, 
+                     res: &Result<ArchiveSummary, AppError>, pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    // A failed (or cancelled) operation is recorded with its error text and no archive details.
+
+    let empty = ArchiveSummary::default();
+    let (summary, outcome, error_text) = match res {
+        Ok(s) => (s, "succeeded", None),
+        Err(e @ AppError::Cancelled) => (&empty, "cancelled", Some(e.to_string())),
+        Err(e) => (&empty, "failed", Some(e.to_string())),
+    };
+
+    let sql = r#"insert into zipping.run_sources (run_id, source_id, job_name, started_at, ended_at, operation,
+    archive_names, file_count, bytes_in, bytes_out, outcome, error_text)
+    values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)"#;
+    let recorded = sqlx::query(sql).bind(run_id).bind(source_id).bind(job_name).bind(started_at).bind(Local::now()).bind(operation)
+             .bind(summary.archive_names()).bind(summary.file_count as i64)
+             .bind(summary.bytes_in as i64).bind(summary.bytes_out as i64)
+             .bind(outcome).bind(error_text)
+             .execute(pool).await
+             .map(|_| ())
+             .map_err(|e| AppError::SqlxError(e, sql.to_string()));
+
+    // If the operation itself failed its error is the one to report, so a failure to record
+    // that outcome is only logged.
+
+    match (recorded, res) {
+        (Err(e), Err(_)) => {
+            error!("Unable to record the failed {} in the run history: {}", operation, e);
+            Ok(())
+        },
+        (recorded, _) => recorded,
+    }
+}
+
+
+pub async fn save_zip_state(source_id: i32, summary: &ArchiveSummary, pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    // Records the most recent archive set made for the source, replacing any earlier record.
+    // last_zipped_at is the time the set was started, as used by --changed-only: a file changed
+    // while the set was being made may not be in it, so must count as a later change.
+
+    let sql = r#"insert into zipping.source_zip_state (source_id, last_zipped_at, last_zip_set_name, 
+    last_zip_archive_count, last_zip_file_count, last_zip_bytes_in, last_zip_bytes_out)
+    values ($1, $2, $3, $4, $5, $6, $7)
+    on conflict (source_id) do update set 
+    last_zipped_at = excluded.last_zipped_at,
+    last_zip_set_name = excluded.last_zip_set_name,
+    last_zip_archive_count = excluded.last_zip_archive_count,
+    last_zip_file_count = excluded.last_zip_file_count,
+    last_zip_bytes_in = excluded.last_zip_bytes_in,
+    last_zip_bytes_out = excluded.last_zip_bytes_out"#;
+    sqlx::query(sql).bind(source_id).bind(summary.started_at.or(summary.completed_at).unwrap_or_else(Local::now))
+             .bind(&summary.set_name).bind(summary.archives.len() as i32).bind(summary.file_count as i64)
+             .bind(summary.bytes_in as i64).bind(summary.bytes_out as i64)
+             .execute(pool).await
+             .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(())
+}
+
+
+pub async fn get_last_zipped_at(source_id: i32, pool: &Pool<Postgres>) -> Result<Option<DateTime<Local>>, AppError> {
+
+    let sql = r#"select last_zipped_at from zipping.source_zip_state
+    where source_id = $1"#;
+    sqlx::query_scalar(sql).bind(source_id).fetch_optional(pool).await
+             .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
+
+
+pub async fn record_source_skip(run_id: i32, source_id: i32, operation: &str, reason: &str, pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    // Sources not processed (e.g. as unchanged under --changed-only) are recorded with the reason.
+
+    let sql = r#"insert into zipping.run_sources (run_id, source_id, started_at, ended_at, operation,
+    outcome, error_text)
+    values ($1, $2, $3, $3, $4, 'skipped', $5)"#;
+    sqlx::query(sql).bind(run_id).bind(source_id).bind(Local::now()).bind(operation).bind(reason)
+             .execute(pool).await
+             .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(())
+}
@@ -13,15 +13,15 @@ pub enum AppError {
     #[error("Error in configuration file: {0:?} {1:?} ")]
     ConfigurationError(String, String),
 
-    #[error("Database Parameters Unavailable")]
-    MissingDBParameters(),
-
     #[error("The parameter '{0}' is required, but has not been supplied")]
     MissingProgramParameter(String),
 
     #[error("The parameters provided are inconsistent or incompatible")]
     InconsistentProgramParameter(String),
 
+    #[error("Invalid source selection: {0} {1:?}")]
+    InvalidSources(String, Vec<String>),
+
     #[error("couldn't read file {1:?}")]
     IoReadErrorWithPath(#[source] std::io::Error, std::path::PathBuf,),
 
@@ -34,9 +34,27 @@ pub enum AppError {
     #[error("couldn't extract archive file {1:?}")]
     UnzipError(#[source] ZipError, std::path::PathBuf,),
 
+    #[error("Archive failed verification: {0:?}")]
+    CorruptArchive(String, String),
+
     #[error("Problem accessing folder or file")]
     FileSystemError(String, String),
 
+    #[error("Folders or files needed are missing or inaccessible: {0:?}")]
+    InvalidPaths(Vec<String>),
+
+    #[error("Problem with archive storage: {0} {1}")]
+    StorageError(String, String),
+
+    #[error("Another run holds the lock {0:?}: {1}")]
+    LockHeld(std::path::PathBuf, String),
+
+    #[error("{0} of {1} source(s) or job(s) failed: {2:?}")]
+    PartialSuccess(usize, usize, Vec<String>),
+
+    #[error("The run was cancelled before it was completed")]
+    Cancelled,
+
     #[error("Error when setting up log configuration: {0:?} {1:?}")]
     LogSetupError(String, String),
 
@@ -52,22 +70,77 @@ pub enum AppError {
     #[error("Error when processing sql: {0:?}")]
     SqlxError(#[source] sqlx::Error, String),
 
+    #[error("Error when applying database migrations: {0:?}")]
+    MigrationError(#[source] sqlx::migrate::MigrateError),
+
     #[error("Error during IO operation: {0:?}")]
     IoError(#[from] std::io::Error),
 }
 
 
-pub fn report_error(e: AppError) -> () {
+// The process exit code for each class of error. These are relied on by schedulers and 
+// scripts, so existing values should not be changed. Clap's own help and version 'errors' exit 0.
+
+pub const EXIT_OTHER_ERROR: u8 = 1;
+pub const EXIT_ARGUMENT_ERROR: u8 = 2;
+pub const EXIT_CONFIGURATION_ERROR: u8 = 3;
+pub const EXIT_DATABASE_ERROR: u8 = 4;
+pub const EXIT_FILESYSTEM_ERROR: u8 = 5;
+pub const EXIT_ARCHIVE_ERROR: u8 = 6;
+pub const EXIT_LOCK_HELD: u8 = 7;
+pub const EXIT_PARTIAL_SUCCESS: u8 = 8;
+
+// As used by shells for a command ended by Ctrl-C (128 + SIGINT), whichever signal cancelled the run.
+
+pub const EXIT_CANCELLED: u8 = 130;
+
+impl AppError {
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::ClapError(e) if is_clap_display(e) => 0,
+
+            AppError::ClapError(_) | AppError::MissingProgramParameter(_) 
+                | AppError::InconsistentProgramParameter(_) | AppError::InvalidSources(_, _) => EXIT_ARGUMENT_ERROR,
+
+            AppError::ConfigurationError(_, _) | AppError::LogSetupError(_, _) => EXIT_CONFIGURATION_ERROR,
+
+            AppError::DBPoolError(_, _) | AppError::SqlxError(_, _) | AppError::MigrationError(_) => EXIT_DATABASE_ERROR,
+
+            AppError::IoReadErrorWithPath(_, _) | AppError::IoWriteErrorWithPath(_, _) 
+                | AppError::FileSystemError(_, _) | AppError::InvalidPaths(_) 
+                | AppError::ZipError(_, _) | AppError::StorageError(_, _) => EXIT_FILESYSTEM_ERROR,
+
+            AppError::CorruptArchive(_, _) | AppError::UnzipError(_, _) => EXIT_ARCHIVE_ERROR,
+
+            AppError::LockHeld(_, _) => EXIT_LOCK_HELD,
+
+            AppError::PartialSuccess(_, _, _) => EXIT_PARTIAL_SUCCESS,
+
+            AppError::Cancelled => EXIT_CANCELLED,
+
+            AppError::SerdeError(_) | AppError::IoError(_) => EXIT_OTHER_ERROR,
+        }
+    }
+}
+
+
+fn is_clap_display(e: &clap::Error) -> bool {
+    matches!(e.kind(), clap::error::ErrorKind::DisplayHelp | clap::error::ErrorKind::DisplayVersion 
+                        | clap::error::ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand)
+}
+
+
+pub fn report_error(e: AppError) {
 
     match e {
+        AppError::ClapError(e) if is_clap_display(&e) => { let _ = e.print(); },
+
         AppError::ConfigurationError(p, d) => print_error (p, d, "CONFIGURATION ERROR"),
 
         AppError::ClapError(e) => print_error ("Error occureed when parsing CLI argumants".to_string(), 
                     e.to_string(), "CLAP ERROR"),
 
-        AppError::MissingDBParameters() => print_error ("Unable to obtain database parameters.".to_string(),
-                    "Attempting to read OnceLock<DB_PARS>".to_string(), "DB PARAMETERS ERROR"),            
-
         AppError::MissingProgramParameter(p) =>  print_error (
                   "A required parameter is neither in the config file nor the command line arguments".to_string(), 
                   format!("Parameter is: {}", p), "MISSING PARAMETER"),
@@ -76,6 +149,9 @@ pub fn report_error(e: AppError) -> () {
                  "The parameters provided are inconsistent or incompatible".to_string(), 
                  s, "INCONSISTENT PARAMETERS"),
 
+        AppError::InvalidSources(p, items) => print_error (p, 
+                 format!("Offending item(s): {}", items.join(", ")), "INVALID SOURCE SELECTION"),
+
         AppError::LogSetupError(p, d) => print_error (p, d, "LOG SETUP ERROR"),
 
         AppError::IoReadErrorWithPath(e, p) => print_error (e.to_string(), 
@@ -90,7 +166,25 @@ pub fn report_error(e: AppError) -> () {
         AppError::UnzipError(e, p) => print_error (e.to_string(), 
                   "Path was: ".to_string() + p.to_str().unwrap(), "UNZIPPING PROBLEM"),
 
+        AppError::CorruptArchive(p, d) => print_error (p, d, "ARCHIVE VERIFICATION PROBLEM"),
+
         AppError::FileSystemError(p, d) => print_error (p, d, "FILE SYSTEM PROBLEM"),
+
+        AppError::InvalidPaths(problems) => print_error (
+                  "Folders or files needed for this operation are missing or cannot be used".to_string(), 
+                  problems.join("\n"), "PATH PROBLEMS"),
+
+        AppError::StorageError(p, d) => print_error (p, d, "STORAGE PROBLEM"),
+
+        AppError::LockHeld(p, d) => print_error (format!("Another run is using these folders (lock file {:?})", p), 
+                  d, "LOCK HELD"),
+
+        AppError::PartialSuccess(n, total, items) => print_error (
+                  format!("{} of {} source(s) or job(s) failed - the others were completed (--keep-going)", n, total),
+                  format!("Failed: {}", items.join(", ")), "PARTIAL SUCCESS"),
+
+        AppError::Cancelled => print_error ("The run was cancelled by a signal (or the caller) before it was completed".to_string(),
+                  "Files already processed were kept, but an incomplete archive set was removed".to_string(), "RUN CANCELLED"),
         
         AppError::SerdeError(e) => print_error ("Error occureed when parsing JSON file".to_string(), 
                     e.to_string(), "SERDE JSON ERROR"),
@@ -100,6 +194,9 @@ pub fn report_error(e: AppError) -> () {
         AppError::SqlxError(e, s) => print_error (e.to_string(), 
                         format!("SQL was: {}", s),  "SQLX ERROR"),
   
+        AppError::MigrationError(e) => print_error ("Error occurred when applying database migrations".to_string(), 
+                        e.to_string(), "MIGRATION ERROR"),
+
         AppError::IoError(e) => print_simple_error (e.to_string(), "IO ERROR"),
     }
 }
@@ -107,7 +204,7 @@ pub fn report_error(e: AppError) -> () {
 
 fn print_error(description: String, details: String, header: &str) {
     let star_num = 100;
-    let hdr_line = get_header_line (star_num, &header);
+    let hdr_line = get_header_line (star_num, header);
     let starred_line = str::repeat("*", star_num);
     let err_output = format!("\n{}\n{}\n{}\n{}\n\n", hdr_line, description, details, starred_line);
     output_error(err_output);
@@ -115,7 +212,7 @@ fn print_error(description: String, details: String, header: &str) {
 
 fn print_simple_error(msg: String, header: &str) {
     let star_num = 100;
-    let hdr_line = get_header_line (star_num, &header);
+    let hdr_line = get_header_line (star_num, header);
     let starred_line = str::repeat("*", star_num);
     let err_output = format!("\n{}\n{}\n{}\n\n", hdr_line, msg, starred_line);
     output_error(err_output);
@@ -124,7 +221,7 @@ fn print_simple_error(msg: String, header: &str) {
 fn get_header_line (star_num: usize, header: &str) -> String {
     let hdr_len = header.len();
     let mut spacer = "";
-    if hdr_len % 2 != 0  {
+    if !hdr_len.is_multiple_of(2)  {
         spacer = " ";
     }
     let star_batch_num = (star_num - 2 - hdr_len) / 2;
@@ -146,4 +243,31 @@ fn output_error (err_output: String) {
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_exit_codes_by_error_class() {
+        let path = std::path::PathBuf::from("a.zip");
+        assert_eq!(AppError::InconsistentProgramParameter("x".to_string()).exit_code(), EXIT_ARGUMENT_ERROR);
+        assert_eq!(AppError::ConfigurationError("x".to_string(), "y".to_string()).exit_code(), EXIT_CONFIGURATION_ERROR);
+        assert_eq!(AppError::DBPoolError("x".to_string(), sqlx::Error::PoolTimedOut).exit_code(), EXIT_DATABASE_ERROR);
+        assert_eq!(AppError::FileSystemError("x".to_string(), "y".to_string()).exit_code(), EXIT_FILESYSTEM_ERROR);
+        assert_eq!(AppError::UnzipError(ZipError::FileNotFound, path.clone()).exit_code(), EXIT_ARCHIVE_ERROR);
+        assert_eq!(AppError::LockHeld(path, "pid 1".to_string()).exit_code(), EXIT_LOCK_HELD);
+        assert_eq!(AppError::PartialSuccess(1, 3, vec!["ctg".to_string()]).exit_code(), EXIT_PARTIAL_SUCCESS);
+        assert_eq!(AppError::Cancelled.exit_code(), EXIT_CANCELLED);
+    }
+
+    #[test]
+    fn check_help_exits_with_zero() {
+        let args : Vec<&str> = vec!["zipper", "--help"];
+        let e = crate::setup::cli_reader::fetch_valid_arguments(args.iter().map(|a| a.into()).collect()).unwrap_err();
+        assert_eq!(e.exit_code(), 0);
 
+        let args : Vec<&str> = vec!["zipper", "zip", "-m", "-f"];
+        let e = crate::setup::cli_reader::fetch_valid_arguments(args.iter().map(|a| a.into()).collect()).unwrap_err();
+        assert_eq!(e.exit_code(), EXIT_ARGUMENT_ERROR);
+    }
+}
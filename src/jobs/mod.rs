@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+use std::fs;
+use chrono::Local;
+use log::info;
+use crate::err::AppError;
+use crate::setup::cli_reader::Operation;
+use crate::setup::config_reader::JobPars;
+use crate::{zipper, unzipper, ArchiveSummary};
+use crate::api::ArchiveObserver;
+use crate::cancel::CancelToken;
+
+// Jobs are defined in the [[jobs]] array of the configuration, and are used for folders other than
+// the MDR source folders. A zip job writes an archive named '{job name} {yymmdd}.zip' to its
+// destination folder (a second run on the same day replaces it, once complete), and may keep only the most
+// recent archives. An unzip job's source may be an archive, or a folder holding the job's archives,
+// in which case the most recent is used.
+
+pub fn run_job(job: &JobPars, observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
+
+    info!("Running job '{}': {} {:?} to {:?}", job.name, job.operation.name(), job.source, job.destination);
+
+    let filter = |name: &str| is_selected(name, &job.include, &job.exclude);
+
+    if job.operation == Operation::Zip {
+
+        if !job.destination.is_dir() {
+            fs::create_dir_all(&job.destination)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, job.destination.to_owned()))?;
+        }
+        let today = Local::now().format("%y%m%d").to_string();
+        let archive_path = job.destination.join(format!("{} {}.zip", job.name, today));
+
+        let mut summary = zipper::zip_filtered_folder(&job.source, &archive_path, job.compression,
+                                                      job.compression_level, &filter, observer, cancel)?;
+        summary.set_name = Some(format!("{} {}", job.name, today));
+        summary.completed_at = Some(Local::now());
+
+        if let Some(keep) = job.keep {
+            prune_job_archives(&job.name, &job.destination, keep)?;
+        }
+        Ok(summary)
+    }
+    else {
+
+        let archive_path = if job.source.is_dir() {
+            match get_job_archives(&job.name, &job.source)?.pop() {
+                Some(a) => a,
+                None => {
+                    let p = "There is a problem accessing a designated folder or file".to_string();
+                    let d = format!("No archives for job '{}' found in {:?}", job.name, job.source);
+                    return Err(AppError::FileSystemError(p, d));
+                }
+            }
+        } else {
+            job.source.clone()
+        };
+        unzipper::unzip_filtered_archive(&archive_path, &job.destination, &filter, observer, cancel)
+    }
+}
+
+
+pub fn lock_folder(job: &JobPars) -> PathBuf {
+
+    // The folder holding the job's archives, where the job's lock is taken: the destination of a
+    // zip job, and the source of an unzip job (or, if that is an archive, the folder holding it).
+
+    if job.operation == Operation::Zip {
+        job.destination.clone()
+    }
+    else if job.source.is_dir() {
+        job.source.clone()
+    }
+    else {
+        job.source.parent().filter(|p| !p.as_os_str().is_empty()).map(|p| p.to_owned()).unwrap_or_else(|| PathBuf::from("."))
+    }
+}
+
+
+fn get_job_archives(job_name: &str, folder: &Path) -> Result<Vec<PathBuf>, AppError> {
+
+    // Returns the job's archives, oldest first.
+
+    let entries = fs::read_dir(folder)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, folder.to_owned()))?;
+
+    let mut archives: Vec<(String, PathBuf)> = entries
+        .filter_map(|e| Some(e.ok()?.path()))
+        .filter(|p| p.is_file())
+        .filter_map(|p| Some((job_archive_date(job_name, p.file_name()?.to_str()?)?, p)))
+        .collect();
+    archives.sort();
+    Ok(archives.into_iter().map(|(_, p)| p).collect())
+}
+
+
+fn job_archive_date(job_name: &str, file_name: &str) -> Option<String> {
+    let date = file_name.strip_prefix(job_name)?.strip_prefix(' ')?.strip_suffix(".zip")?;
+    if date.len() == 6 && date.chars().all(|c| c.is_ascii_digit()) { Some(date.to_string()) } else { None }
+}
+
+
+fn prune_job_archives(job_name: &str, folder: &Path, keep: usize) -> Result<(), AppError> {
+
+    let archives = get_job_archives(job_name, folder)?;
+    let prune_num = archives.len().saturating_sub(keep);
+
+    for a in archives.into_iter().take(prune_num) {
+        fs::remove_file(&a)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, a.to_owned()))?;
+        info!("{:?} deleted (job '{}' keeps {} archives)", a, job_name, keep);
+    }
+    Ok(())
+}
+
+
+pub fn is_selected(name: &str, include: &[String], exclude: &[String]) -> bool {
+
+    // A file is selected if it matches any include pattern (or there are none),
+    // and matches no exclude pattern.
+
+    (include.is_empty() || include.iter().any(|p| wildcard_match(p, name)))
+        && !exclude.iter().any(|p| wildcard_match(p, name))
+}
+
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+
+    // '*' matches any sequence of characters (including '/'), '?' any single character.
+    // On a mismatch after a '*', the match is retried with the '*' covering one more character.
+
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        }
+        else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        }
+        else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        }
+        else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_wildcards_matched() {
+        assert!(wildcard_match("*.rrf", "meta/mrconso.rrf"));
+        assert!(wildcard_match("META/*", "META/MRCONSO.RRF"));
+        assert!(!wildcard_match("MR????.RRF", "MRCONSO.RRF"));
+        assert!(wildcard_match("MR?ONSO.RRF", "MRCONSO.RRF"));
+        assert!(!wildcard_match("*.tmp", "notes.tmp.txt"));
+    }
+
+    #[test]
+    fn check_include_and_exclude_filters() {
+        let include = vec!["*.json".to_string(), "*.csv".to_string()];
+        let exclude = vec!["tmp/*".to_string()];
+        assert!(is_selected("data/a.json", &include, &exclude));
+        assert!(!is_selected("tmp/a.json", &include, &exclude));
+        assert!(!is_selected("data/a.txt", &include, &exclude));
+        assert!(is_selected("data/a.txt", &[], &exclude));
+    }
+
+    #[test]
+    fn check_earlier_archive_kept_if_zip_cancelled() {
+        let folder = std::env::temp_dir().join(format!("zipper_job_partial_test_{}", std::process::id()));
+        fs::create_dir_all(folder.join("src")).unwrap();
+        fs::create_dir_all(folder.join("dest")).unwrap();
+        fs::write(folder.join("src").join("a.json"), "{}").unwrap();
+
+        let job = JobPars { name: "umls".to_string(), operation: Operation::Zip, source: folder.join("src"),
+                            destination: folder.join("dest"), compression: zip::CompressionMethod::Deflated,
+                            compression_level: None, include: Vec::new(), exclude: Vec::new(), keep: None };
+        let archive_path = folder.join("dest").join(format!("umls {}.zip", Local::now().format("%y%m%d")));
+        fs::write(&archive_path, "earlier archive").unwrap();
+
+        // A cancelled run leaves the archive made earlier the same day, and no partial file.
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(run_job(&job, &crate::api::NoObserver, &cancel), Err(AppError::Cancelled)));
+        assert_eq!(fs::read_to_string(&archive_path).unwrap(), "earlier archive");
+        assert_eq!(fs::read_dir(folder.join("dest")).unwrap().count(), 1);
+
+        // A completed run replaces it.
+
+        let summary = run_job(&job, &crate::api::NoObserver, &CancelToken::new()).unwrap();
+        assert_eq!(summary.file_count, 1);
+        assert!(zip::ZipArchive::new(fs::File::open(&archive_path).unwrap()).is_ok());
+        assert_eq!(fs::read_dir(folder.join("dest")).unwrap().count(), 1);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_job_archive_names_parsed() {
+        assert_eq!(job_archive_date("umls", "umls 250101.zip"), Some("250101".to_string()));
+        assert_eq!(job_archive_date("umls", "umls-full 250101.zip"), None);
+        assert_eq!(job_archive_date("umls", "umls 2501.zip"), None);
+    }
+}
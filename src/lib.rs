@@ -1,14 +1,42 @@
 pub mod setup;
 pub mod err;
+pub mod api;
 mod data;
+mod catalogue;
+mod archive_sets;
 mod zipper;
 mod unzipper;
+mod jobs;
+mod daemon;
+mod watcher;
+mod locks;
+mod report;
+mod progress;
+mod cancel;
+mod storage;
+mod runner;
 
 use setup::cli_reader;
+use setup::cli_reader::Operation;
+use setup::source_selector;
+use setup::InitParams;
+use setup::log_helper::LogContext;
+use locks::{LockScope, RunLock};
 use err::AppError;
 use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use sqlx::{Pool, Postgres};
+use chrono::{DateTime, Local};
+use zip::ZipArchive;
+use serde::Serialize;
+use report::RunReport;
+use progress::Progress;
+use cancel::CancelToken;
+use api::SourceOutcome;
+use log::{info, warn};
 
 #[derive(sqlx::FromRow)]
 pub struct SourceDetails {
@@ -19,54 +47,360 @@ pub struct SourceDetails {
     pub grouping_range_by_id: Option<i32>
 }
 
+// Summary of the archives created or read by a zip or unzip operation,
+// with the number of files involved and the bytes read and written.
+// For MDR zips the set name ('{db name} {yymmdd}') and its start and completion times are included.
+// The start time is taken before the source folder is read, so any file written while the set is
+// being made is later than it.
+// Warnings (e.g. files not found when extracting) are logged, and kept for the run report.
+
+#[derive(Debug, Default, Clone)]
+pub struct ArchiveSummary {
+    pub set_name: Option<String>,
+    pub started_at: Option<DateTime<Local>>,
+    pub completed_at: Option<DateTime<Local>>,
+    pub archives: Vec<ArchiveDetails>,
+    pub file_count: usize,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveDetails {
+    pub name: String,
+    pub file_count: usize,
+    pub archive_bytes: u64,
+}
+
+impl ArchiveDetails {
+
+    pub(crate) fn new(archive_path: &Path, file_count: usize) -> ArchiveDetails {
+        ArchiveDetails {
+            name: archive_path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string(),
+            file_count,
+            archive_bytes: fs::metadata(archive_path).map(|m| m.len()).unwrap_or_default(),
+        }
+    }
+}
+
+impl ArchiveSummary {
+
+    pub(crate) fn add_archive(&mut self, archive_path: &Path, zipping: bool) -> Result<(), AppError> {
+
+        // Totals are taken from the archive's central directory, so the archive 
+        // must be complete. When zipping the uncompressed sizes are the bytes read,
+        // when unzipping they are the bytes written.
+
+        let archive_len = fs::metadata(archive_path)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, archive_path.to_owned()))?.len();
+        let file = File::open(archive_path)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, archive_path.to_owned()))?;
+        let mut archive = ZipArchive::new(file)
+                .map_err(|e| AppError::UnzipError(e, archive_path.to_owned()))?;
+
+        let mut uncompressed = 0;
+        for i in 0..archive.len() {
+            uncompressed += archive.by_index_raw(i)
+                    .map_err(|e| AppError::UnzipError(e, archive_path.to_owned()))?.size();
+        }
+
+        let archive_name = archive_path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        self.archives.push(ArchiveDetails {
+            name: archive_name.to_string(),
+            file_count: archive.len(),
+            archive_bytes: archive_len,
+        });
+        self.file_count += archive.len();
+        if zipping {
+            self.bytes_in += uncompressed;
+            self.bytes_out += archive_len;
+        } 
+        else {
+            self.bytes_in += archive_len;
+            self.bytes_out += uncompressed;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn merge(&mut self, other: ArchiveSummary) {
+        self.archives.extend(other.archives);
+        self.file_count += other.file_count;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.warnings.extend(other.warnings);
+    }
+
+    pub(crate) fn add_warning(&mut self, warning: String) {
+        warn!("{}", warning);
+        self.warnings.push(warning);
+    }
+
+    pub fn archive_names(&self) -> Vec<String> {
+        self.archives.iter().map(|a| a.name.clone()).collect()
+    }
+}
+
+
 pub async fn run(args: Vec<OsString>) -> Result<(), AppError> {
 
     // Establish program parameters, and thus tasks to do
 
-    let cli_pars: cli_reader::CliPars;
-    cli_pars = cli_reader::fetch_valid_arguments(args)?;
+    let cli_pars: cli_reader::CliPars = cli_reader::fetch_valid_arguments(args)?;
 
-    let config_file = PathBuf::from("./app_config.toml");
-    let config_string: String = fs::read_to_string(&config_file)
-                    .map_err(|e| AppError::IoReadErrorWithPath(e, config_file))?;
+    let (config_string, config_files) = setup::config_reader::get_config_string(&cli_pars.config_files)?;
     
-    let params = setup::get_params(cli_pars, &config_string)?;
-    let flags = params.flags;
-    let test_run = flags.test_run;
+    let mut params = setup::get_params(cli_pars, &config_string)?;
+    setup::path_checker::check_paths(&mut params)?;
+    let test_run = params.flags.test_run;
 
     if !test_run {
         setup::establish_log(&params)?;
+        info!("Configuration read from: {:?}", config_files);
     }
     
-    let pool = setup::get_db_pool().await?;
+    let pool = setup::get_db_pool(&params.db_pars).await?;
+    if params.flags.operation.needs_migrations() {
+        data::apply_migrations(&pool).await?;
+    }
+
+    match params.flags.operation {
+        Operation::Daemon => daemon::run_daemon(params.schedules, config_string, pool).await?,
+        Operation::Watch => {
+            let source_list = get_source_list(&params, &pool).await?;
+            watcher::run_watch(&params, source_list, &config_string, &pool).await?
+        },
+        _ => execute(&params, &CancelToken::on_signal(), &pool).await?,
+    }
+
+    // With --output json the report is the only output written to stdout.
+
+    if !params.flags.json_output {
+        println!("Done!");
+    }
+    Ok(())
+}
+
+
+pub(crate) async fn execute(params: &InitParams, cancel: &CancelToken, pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    // Each run, and each source processed within it, is recorded in the zipping audit tables,
+    // and in the run report. The report is written (if requested) even if the run fails or is cancelled.
+    // Read only operations and test runs (-t) are only reported, not recorded.
+
+    let operation = get_operation_name(params);
+    let run_id = match params.flags.test_run || params.flags.operation.is_read_only() {
+        true => None,
+        false => Some(data::start_run_record(operation, pool).await?),
+    };
+    let mut report = RunReport::new(run_id, operation);
+
+    let res = match lock_folder(params, LockScope::Global, &params.mdr_zipped) {
+        Ok(_lock) => process_params(params, run_id, &mut report, cancel, pool).await,
+        Err(e) => Err(e),
+    };
+
+    let error_text = res.as_ref().err().map(|e| e.to_string());
+    let outcome = match &res {
+        Ok(_) => "succeeded",
+        Err(AppError::PartialSuccess(..)) => "partial",
+        Err(AppError::Cancelled) => "cancelled",
+        Err(_) => "failed",
+    };
+    if outcome == "cancelled" {
+        warn!("{} cancelled - {} source(s) or job(s) completed", run_id.map_or("Run".to_string(), |id| format!("Run {}", id)), report.items.iter().filter(|i| i.outcome == "succeeded").count());
+    }
+    if let Some(run_id) = run_id {
+        data::end_run_record(run_id, outcome, error_text.clone(), pool).await?;
+    }
+    report.finish(outcome, error_text);
 
-    if flags.use_folder {
+    if let Some(report_path) = &params.report_path {
+        report.write_to_file(report_path)?;
+    }
+    if params.flags.json_output {
+        report.write_to_stdout()?;
+    }
+
+    res
+}
+
+
+async fn process_params(params: &InitParams, run_id: Option<i32>, report: &mut RunReport, cancel: &CancelToken,
+                        pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    let flags = params.flags;
+    let operation = get_operation_name(params);
+
+    if flags.operation == Operation::Run {
+
+        let progress = Arc::new(Progress::new(params.progress, params.jobs.len()));
+        let run = runner::JobRun {
+            keep_going: flags.keep_going,
+            locking: takes_lock(params, LockScope::Source),
+            run_id,
+            pool: Some(pool),
+            observer: progress.clone(),
+            cancel: cancel.clone(),
+        };
+        let result = runner::run_jobs(&run, &params.jobs, &mut |job, j, started_at| {
+            let res = match &j.outcome {
+                SourceOutcome::Completed(summary) => Ok(summary),
+                SourceOutcome::Failed(e) => Err(e),
+                SourceOutcome::Skipped(_) => return,
+            };
+            report.add_result(None, &j.name, job.operation.name(), started_at, res);
+        }).await?;
+        drop(progress);
+        runner::into_run_result(result, flags.keep_going)?;
+    }
+    else if flags.use_folder {
          
          // call the appropriate zip or unzip fuunction with the folders concerned
          
-         if flags.do_zip {
-             zipper::zip_folder(&params.fdr_unzipped, &params.fdr_zipped)?;
-         } else {
-             unzipper::unzip_folder(&params.fdr_zipped, &params.fdr_unzipped)?;
+         let started_at = Local::now();
+         let archive_folder = params.fdr_zipped.parent().unwrap_or(Path::new("."));
+         let progress = Arc::new(Progress::new(params.progress, 1));
+         progress.start_source(&params.fdr_zipped.to_string_lossy());
+         let res = match lock_folder(params, LockScope::Source, archive_folder) {
+             Err(e) => Err(e),
+             Ok(_lock) => {
+                 let (zipped, unzipped) = (params.fdr_zipped.clone(), params.fdr_unzipped.clone());
+                 let (progress, cancel) = (progress.clone(), cancel.clone());
+                 api::run_blocking(move || {
+                     let _ctx = LogContext::new().with("source", zipped.display());
+                     match flags.operation {
+                         Operation::Zip => zipper::zip_folder(&unzipped, &zipped, progress.as_ref(), &cancel),
+                         Operation::Verify => unzipper::verify_folder(&zipped, progress.as_ref()),
+                         Operation::Diff => archive_sets::diff_folder(&zipped, &unzipped),
+                         _ => unzipper::unzip_folder(&zipped, &unzipped, progress.as_ref(), &cancel),
+                     }
+                 }).await
+             },
+         };
+         drop(progress);
+         if let Some(run_id) = run_id {
+             data::record_source_run(run_id, None, operation, started_at, &res, pool).await?;
          }
+         report.add_result(None, &params.fdr_zipped.to_string_lossy(), operation, started_at, res.as_ref());
+         res?;
     }
     else {
 
-        let mut source_list = params.source_list;
-        if flags.all_mdr {
-            source_list = data::get_all_ids(&pool).await?;  // get all ids
-        }
+        let source_list = get_source_list(params, pool).await?;
+        info!("Sources to be processed: {:?}", source_list);
+        let storage = storage::open_storage(&params.storage, &params.mdr_zipped)?;
+
+        if flags.operation == Operation::Locate || flags.operation == Operation::List {
+
+            // Report where each listed file is, from each source's catalogue index,
+            // or the archive sets held for each source. Neither is recorded per source.
 
-        for source_id in source_list.clone() {
-            let source_dets = data::get_source_details(source_id, &pool).await?;
-            if flags.do_zip {
-                zipper::zip_mdr_folder(source_dets, &params.mdr_unzipped, &params.mdr_zipped)?;
+            for source_id in source_list {
+                let source_dets = data::get_source_details(source_id, pool).await?;
+                if flags.operation == Operation::Locate {
+                    let zipped_folder = params.mdr_zipped.join(&source_dets.database_name);
+                    catalogue::locate(&source_dets.database_name, &params.file_list, &zipped_folder)?;
+                }
+                else {
+                    archive_sets::list_mdr_archives(&source_dets, &params.mdr_zipped)?;
+                    storage::list_stored_sets(storage.as_ref(), &source_dets, &params.mdr_zipped)?;
+                }
             }
-            else {
-                unzipper::unzip_mdr_folder(source_dets, &params.mdr_zipped, &params.mdr_unzipped)?;
+            return Ok(());
+        }
+
+        let mut sources: Vec<SourceDetails> = Vec::new();
+        for source_id in source_list {
+            sources.push(data::get_source_details(source_id, pool).await?);
+        }
+        let progress = Arc::new(Progress::new(params.progress, sources.len()));
+        let run = runner::SourceRun {
+            operation: flags.operation,
+            operation_name: operation,
+            pars: runner::SourcePars {
+                parent_zipped: params.mdr_zipped.clone(),
+                parent_unzipped: params.mdr_unzipped.clone(),
+                file_list: params.file_list.clone(),
+                keep: params.keep,
+                dry_run: flags.dry_run,
+                storage,
+                keep_local: params.storage.keep_local,
+            },
+            changed_only: flags.changed_only,
+            keep_going: flags.keep_going,
+            locking: takes_lock(params, LockScope::Source),
+            run_id,
+            pool: Some(pool),
+            observer: progress.clone(),
+            cancel: cancel.clone(),
+        };
+
+        let result = runner::run_sources(&run, sources, &mut |s, started_at| {
+            let source_id = s.source_id.unwrap_or_default();
+            match &s.outcome {
+                SourceOutcome::Skipped(reason) => report.add_skip(source_id, &s.name, operation, reason),
+                SourceOutcome::Completed(summary) => report.add_result(Some(source_id), &s.name, operation, started_at, Ok(summary)),
+                SourceOutcome::Failed(e) => report.add_result(Some(source_id), &s.name, operation, started_at, Err(e)),
             }
-        }      
+        }).await?;
+        drop(progress);
+
+        if flags.changed_only {
+            let ids = |skipped: bool| result.sources.iter()
+                .filter(|s| matches!(s.outcome, SourceOutcome::Skipped(_)) == skipped)
+                .filter_map(|s| s.source_id).collect::<Vec<i32>>();
+            let (processed, skipped) = (ids(false), ids(true));
+            info!("Run summary: {} source(s) zipped {:?}, {} source(s) skipped as unchanged {:?}", 
+                            processed.len(), processed, skipped.len(), skipped);
+        }
+        runner::into_run_result(result, flags.keep_going)?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+
+async fn get_source_list(params: &InitParams, pool: &Pool<Postgres>) -> Result<Vec<i32>, AppError> {
+
+    // The selected sources are all checked against the source catalogue before any work starts.
+
+    if params.flags.all_mdr {
+        data::get_all_ids(pool).await  // get all ids
+    }
+    else {
+        let catalogue = data::get_source_catalogue(pool).await?;
+        let all_ids = if params.source_selection.include.is_empty() {
+            data::get_all_ids(pool).await?
+        } else {
+            Vec::new()
+        };
+        source_selector::resolve_sources(&params.source_selection, &catalogue, &all_ids)
+    }
+}
+
+
+fn lock_folder(params: &InitParams, scope: LockScope, folder: &Path) -> Result<Option<RunLock>, AppError> {
+    if takes_lock(params, scope) { locks::acquire_lock(folder).map(Some) } else { Ok(None) }
+}
+
+
+fn takes_lock(params: &InitParams, scope: LockScope) -> bool {
+
+    // Only operations that write or delete files take a lock, and only at the configured scope.
+    // Read only operations (verify, diff, list, locate) can run alongside others.
+
+    let writes = !params.flags.operation.is_read_only();
+    writes && params.lock_scope == scope
+}
+
+
+fn get_operation_name(params: &InitParams) -> &'static str {
+    let operation = params.flags.operation;
+    if operation == Operation::Unzip && !params.file_list.is_empty() {
+        "extract"
+    }
+    else {
+        operation.name()
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use chrono::Local;
+use log::warn;
+use crate::err::AppError;
+
+// Advisory lock files, to stop two runs writing the same archives at the same time. The lock is an
+// exclusive OS lock (flock, or LockFileEx on Windows) on the lock file, held by the run while it has
+// the file open. The OS releases the lock when the process ends, however it ends, so a lock left by a
+// crashed run never needs to be replaced, and two runs can never both take the same lock. The file is
+// left in place; while locked it holds the PID of the run, and the time it was taken, which are only
+// used in messages. With 'source' scope (the default) a lock is taken in each source's zipped folder
+// while that source is processed; with 'global' scope a single lock, in the parent zipped folder, is
+// held for the whole run.
+
+pub const LOCK_FILE_NAME: &str = "zipper.lock";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockScope {
+    Source,
+    Global,
+}
+
+#[derive(Debug)]
+pub struct RunLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+
+        // The holder's details are cleared before the lock is released (when the file is closed).
+
+        if let Err(e) = self.file.set_len(0) {
+            warn!("Unable to clear lock file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+
+pub fn acquire_lock(folder: &Path) -> Result<RunLock, AppError> {
+
+    if !folder.is_dir() {
+        fs::create_dir_all(folder)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, folder.to_owned()))?;
+    }
+    let path = folder.join(LOCK_FILE_NAME);
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, path.to_owned()))?;
+
+    match file.try_lock() {
+        Ok(()) => {
+            file.set_len(0)
+                .and_then(|_| file.seek(SeekFrom::Start(0)))
+                .and_then(|_| writeln!(file, "{}\n{}", std::process::id(), Local::now().format("%Y-%m-%d %H:%M:%S")))
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, path.to_owned()))?;
+            Ok(RunLock { path, file })
+        },
+        Err(TryLockError::WouldBlock) => {
+            let contents = fs::read_to_string(&path).unwrap_or_default();
+            let mut lines = contents.lines();
+            let details = match (lines.next().and_then(|l| l.trim().parse::<u32>().ok()), lines.next()) {
+                (Some(pid), Some(since)) => format!("Held by process {} since {}", pid, since),
+                _ => "Held by another run, which has not yet recorded its details".to_string(),
+            };
+            Err(AppError::LockHeld(path, details))
+        },
+        Err(TryLockError::Error(e)) => Err(AppError::IoWriteErrorWithPath(e, path)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("zipper_lock_test_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn check_lock_held_until_dropped() {
+        let folder = test_folder("held");
+        let lock = acquire_lock(&folder).unwrap();
+        match acquire_lock(&folder) {
+            Err(AppError::LockHeld(_, d)) => assert!(d.contains(&std::process::id().to_string())),
+            _ => panic!("lock should be held"),
+        }
+        drop(lock);
+        assert_eq!(fs::read_to_string(folder.join(LOCK_FILE_NAME)).unwrap(), "");
+        let res = acquire_lock(&folder);
+        assert!(res.is_ok());
+        drop(res);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_lock_held_by_other_handle_refused() {
+
+        // Another open handle holding the OS lock stands in for another run. The details left in
+        // the file by a run that has ended, with the file unlocked, do not stop the lock being taken.
+
+        let folder = test_folder("other");
+        let lock_path = folder.join(LOCK_FILE_NAME);
+        fs::write(&lock_path, "4242\n2025-01-01 00:00:00\n").unwrap();
+        let other = OpenOptions::new().read(true).write(true).open(&lock_path).unwrap();
+        other.try_lock().unwrap();
+        match acquire_lock(&folder) {
+            Err(AppError::LockHeld(_, d)) => assert_eq!(d, "Held by process 4242 since 2025-01-01 00:00:00"),
+            _ => panic!("lock held by the other handle should be refused"),
+        }
+        drop(other);
+
+        let lock = acquire_lock(&folder).unwrap();
+        assert!(fs::read_to_string(&lock_path).unwrap().starts_with(&std::process::id().to_string()));
+        let other = OpenOptions::new().read(true).write(true).open(&lock_path).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+
+        // Once the run's lock is dropped, the other handle can take it.
+
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+        drop(other);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn check_only_one_of_many_takes_lock() {
+        let folder = test_folder("race");
+        let barrier = std::sync::Barrier::new(8);
+        let taken: Vec<RunLock> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8).map(|_| s.spawn(|| {
+                barrier.wait();
+                acquire_lock(&folder).ok()
+            })).collect();
+            handles.into_iter().filter_map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(taken.len(), 1);
+        drop(taken);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
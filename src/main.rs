@@ -2,14 +2,21 @@
 use zipper::err;
 use zipper::run;
 use std::env;
+use std::process::ExitCode;
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() {
+async fn main() -> ExitCode {
+
+    // The exit code reflects the class of any error (see AppError::exit_code), so schedulers can detect failures.
 
     let args: Vec<_> = env::args_os().collect();
     match run(args).await
     {
-      Ok(_) => println!("Done!"),
-      Err(e) => err::report_error(e),
-    };
+      Ok(_) => ExitCode::SUCCESS,
+      Err(e) => {
+        let code = e.exit_code();
+        err::report_error(e);
+        ExitCode::from(code)
+      },
+    }
 }
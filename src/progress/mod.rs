@@ -0,0 +1,389 @@
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use log::info;
+use log4rs::append::Append;
+use log4rs::append::console::ConsoleAppender;
+use crate::api::ArchiveObserver;
+use crate::err::AppError;
+use crate::ArchiveSummary;
+
+// Progress of a run is shown, on an interactive terminal, as a single status line on stderr that is
+// redrawn as files are processed: the source (and its position in the run), files and bytes processed,
+// the current archive, throughput and the estimated time remaining, for the source and for the run.
+// When stderr is not a terminal, or the run was started by the daemon or watch mode, the same line
+// is logged periodically instead. Totals are set (where they can be found cheaply) as each source starts;
+// where they are unknown no ETA is given.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    Bar,
+    Log,
+    Off,
+}
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+// The status line currently drawn, if any. The console log appender clears it before writing
+// a record, and redraws it afterwards, so that log lines and the status line do not collide.
+
+static STATUS_LINE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn default_mode() -> ProgressMode {
+    if std::io::stderr().is_terminal() {
+        ProgressMode::Bar
+    }
+    else {
+        ProgressMode::Log
+    }
+}
+
+
+pub struct Progress {
+    mode: ProgressMode,
+    state: Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    source_total: usize,
+    sources_done: usize,
+    source_name: String,
+    archive_name: String,
+    files_total: usize,
+    files_done: usize,
+    bytes_total: u64,
+    bytes_done: u64,
+    run_started: Instant,
+    source_started: Instant,
+    last_shown: Instant,
+}
+
+impl Progress {
+
+    pub fn new(mode: ProgressMode, source_total: usize) -> Progress {
+        let now = Instant::now();
+        Progress {
+            mode,
+            state: Mutex::new(ProgressState {
+                source_total,
+                sources_done: 0,
+                source_name: String::new(),
+                archive_name: String::new(),
+                files_total: 0,
+                files_done: 0,
+                bytes_total: 0,
+                bytes_done: 0,
+                run_started: now,
+                source_started: now,
+                last_shown: now,
+            }),
+        }
+    }
+
+    pub fn start_source(&self, name: &str) {
+        let mut state = self.lock();
+        let now = Instant::now();
+        state.source_name = name.to_string();
+        state.archive_name.clear();
+        state.files_total = 0;
+        state.files_done = 0;
+        state.bytes_total = 0;
+        state.bytes_done = 0;
+        state.source_started = now;
+        state.last_shown = now;
+        if self.mode == ProgressMode::Bar {
+            draw_status_line(Some(state.status_line()));
+        }
+    }
+
+
+    pub fn set_totals(&self, files_total: usize, bytes_total: u64) {
+        let mut state = self.lock();
+        state.files_total = files_total;
+        state.bytes_total = bytes_total;
+    }
+
+
+    pub fn start_archive(&self, archive_name: &str) {
+        let mut state = self.lock();
+        state.archive_name = archive_name.to_string();
+        self.show(&mut state, true);
+    }
+
+
+    pub fn add(&self, files: usize, bytes: u64) {
+        let mut state = self.lock();
+        state.files_done += files;
+        state.bytes_done += bytes;
+        self.show(&mut state, false);
+    }
+
+
+    pub fn finish_source(&self) {
+        let mut state = self.lock();
+        state.sources_done += 1;
+        if self.mode == ProgressMode::Bar {
+            draw_status_line(None);
+        }
+    }
+
+
+    fn show(&self, state: &mut ProgressState, forced: bool) {
+
+        // The status line is redrawn at most every REDRAW_INTERVAL (unless the archive changes),
+        // while the logged line is only written every LOG_INTERVAL.
+
+        let since_shown = state.last_shown.elapsed();
+        match self.mode {
+            ProgressMode::Bar if forced || since_shown >= REDRAW_INTERVAL => {
+                draw_status_line(Some(state.status_line()));
+                state.last_shown = Instant::now();
+            },
+            ProgressMode::Log if since_shown >= LOG_INTERVAL => {
+                info!("Progress: {}", state.status_line());
+                state.last_shown = Instant::now();
+            },
+            _ => {},
+        }
+    }
+
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ProgressState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Progress follows the sources, and the files processed by the zipper and unzipper functions.
+// Sources that are skipped or fail also count towards the run. (Jobs and single folders, which
+// are not run as sources, are started and finished by the caller.)
+
+impl ArchiveObserver for Progress {
+
+    fn source_started(&self, name: &str) {
+        self.start_source(name);
+    }
+
+    fn totals_found(&self, file_count: usize, bytes: u64) {
+        self.set_totals(file_count, bytes);
+    }
+
+    fn archive_opened(&self, archive_name: &str) {
+        self.start_archive(archive_name);
+    }
+
+    fn file_processed(&self, _file_name: &str, bytes: u64) {
+        self.add(1, bytes);
+    }
+
+    fn source_finished(&self, _summary: &ArchiveSummary) {
+        self.finish_source();
+    }
+
+    fn source_skipped(&self, _name: &str, _reason: &str) {
+        self.finish_source();
+    }
+
+    fn error(&self, _error: &AppError) {
+        self.finish_source();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.mode == ProgressMode::Bar {
+            draw_status_line(None);
+        }
+    }
+}
+
+
+impl ProgressState {
+
+    fn source_fraction(&self) -> Option<f64> {
+
+        // Bytes give a better measure of the work done than files, if their total is known.
+
+        if self.bytes_total > 0 {
+            Some((self.bytes_done as f64 / self.bytes_total as f64).min(1.0))
+        }
+        else if self.files_total > 0 {
+            Some((self.files_done as f64 / self.files_total as f64).min(1.0))
+        }
+        else {
+            None
+        }
+    }
+
+    fn status_line(&self) -> String {
+
+        let mut parts: Vec<String> = Vec::new();
+        let position = if self.source_total > 1 {
+            format!("[{}/{}] ", (self.sources_done + 1).min(self.source_total), self.source_total)
+        } else {
+            String::new()
+        };
+        parts.push(format!("{}{}", position, self.source_name));
+
+        parts.push(match self.files_total {
+            0 => format!("{} files", self.files_done),
+            n => format!("{}/{} files", self.files_done, n),
+        });
+        parts.push(match self.bytes_total {
+            0 => format_bytes(self.bytes_done),
+            n => format!("{}/{}", format_bytes(self.bytes_done), format_bytes(n)),
+        });
+
+        let elapsed = self.source_started.elapsed();
+        if elapsed.as_secs_f64() >= 1.0 {
+            parts.push(format!("{}/s", format_bytes((self.bytes_done as f64 / elapsed.as_secs_f64()) as u64)));
+        }
+
+        let fraction = self.source_fraction();
+        if let Some(eta) = fraction.and_then(|f| estimate_remaining(elapsed, f)) {
+            parts.push(format!("ETA {}", format_duration(eta)));
+        }
+        if self.source_total > 1 {
+            let overall = (self.sources_done as f64 + fraction.unwrap_or(0.0)) / self.source_total as f64;
+            if let Some(eta) = estimate_remaining(self.run_started.elapsed(), overall) {
+                parts.push(format!("run ETA {}", format_duration(eta)));
+            }
+        }
+        if !self.archive_name.is_empty() {
+            parts.push(self.archive_name.clone());
+        }
+        parts.join(", ")
+    }
+}
+
+
+fn estimate_remaining(elapsed: Duration, fraction: f64) -> Option<Duration> {
+
+    // Estimates are not given until there is at least a second's progress to go on.
+
+    if fraction <= 0.0 || elapsed < Duration::from_secs(1) {
+        return None;
+    }
+    let remaining = elapsed.as_secs_f64() * (1.0 - fraction.min(1.0)) / fraction;
+    Some(Duration::from_secs_f64(remaining))
+}
+
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    }
+    else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+
+fn draw_status_line(line: Option<String>) {
+
+    // Lines are cut to the terminal's width, as a wrapped line cannot be cleared with '\r'.
+
+    let mut current = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut stderr = std::io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[K");
+    if let Some(l) = &line {
+        let width = terminal_width().saturating_sub(1);
+        let _ = write!(stderr, "{}", l.chars().take(width).collect::<String>());
+    }
+    let _ = stderr.flush();
+    *current = line;
+}
+
+
+fn terminal_width() -> usize {
+    match terminal_size::terminal_size_of(std::io::stderr()) {
+        Some((terminal_size::Width(w), _)) if w > 0 => w as usize,
+        _ => 80,
+    }
+}
+
+
+// Used in place of the plain stderr appender, so that log records are written above the status line.
+
+#[derive(Debug)]
+pub struct ProgressAwareConsole(pub ConsoleAppender);
+
+impl Append for ProgressAwareConsole {
+
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        let current = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stderr = std::io::stderr().lock();
+        if current.is_some() {
+            let _ = write!(stderr, "\r\x1b[K");
+        }
+        let res = self.0.append(record);
+        self.0.flush();
+        if let Some(l) = current.as_ref() {
+            let width = terminal_width().saturating_sub(1);
+            let _ = write!(stderr, "{}", l.chars().take(width).collect::<String>());
+            let _ = stderr.flush();
+        }
+        res
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_remaining_time_estimated() {
+        assert_eq!(estimate_remaining(Duration::from_secs(60), 0.25), Some(Duration::from_secs(180)));
+        assert_eq!(estimate_remaining(Duration::from_secs(60), 0.0), None);
+        assert_eq!(estimate_remaining(Duration::from_millis(500), 0.5), None);
+        assert_eq!(estimate_remaining(Duration::from_secs(60), 1.0), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn check_sizes_and_durations_formatted() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GB");
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(100)), "1m 40s");
+        assert_eq!(format_duration(Duration::from_secs(7500)), "2h 05m");
+    }
+
+    #[test]
+    fn check_status_line_content() {
+        let progress = Progress::new(ProgressMode::Off, 3);
+        progress.start_source("ctg");
+        progress.set_totals(20000, 4096);
+        progress.start_archive("ctg 250101 1 to 10000.zip");
+        progress.add(4512, 1024);
+        let line = progress.lock().status_line();
+        assert!(line.starts_with("[1/3] ctg, 4512/20000 files, 1.0 KB/4.0 KB"));
+        assert!(line.ends_with("ctg 250101 1 to 10000.zip"));
+
+        progress.finish_source();
+        progress.start_source("euctr");
+        assert!(progress.lock().status_line().starts_with("[2/3] euctr, 0 files, 0 B"));
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Local, SecondsFormat};
+use log::info;
+use serde::Serialize;
+use crate::err::AppError;
+use crate::{ArchiveDetails, ArchiveSummary};
+
+// The run report is a JSON summary of a run, written with --report <path> and / or, with
+// --output json, to stdout. It has an item for each source (or job, or single folder) processed,
+// giving the archives created or read, file counts, byte sizes, duration, warnings and any error.
+// Sources not reached because an earlier one failed do not appear. Runs that are not recorded in the
+// database (read only operations, and test runs) have no run_id.
+
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub run_id: Option<i32>,
+    pub operation: String,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    pub duration_secs: f64,
+    pub outcome: String,
+    pub error: Option<String>,
+    pub items: Vec<ReportItem>,
+    #[serde(skip)]
+    start: DateTime<Local>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportItem {
+    pub source_id: Option<i32>,
+    pub name: String,
+    pub operation: String,
+    pub outcome: String,
+    pub started_at: String,
+    pub duration_secs: f64,
+    pub set_name: Option<String>,
+    pub archives: Vec<ArchiveDetails>,
+    pub file_count: usize,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl RunReport {
+
+    pub fn new(run_id: Option<i32>, operation: &str) -> RunReport {
+        let now = Local::now();
+        RunReport {
+            run_id,
+            operation: operation.to_string(),
+            started_at: timestamp(now),
+            ended_at: None,
+            duration_secs: 0.0,
+            outcome: "running".to_string(),
+            error: None,
+            items: Vec::new(),
+            start: now,
+        }
+    }
+
+
+    pub fn add_result(&mut self, source_id: Option<i32>, name: &str, operation: &str,
+                      started_at: DateTime<Local>, res: Result<&ArchiveSummary, &AppError>) {
+
+        let mut item = ReportItem {
+            source_id,
+            name: name.to_string(),
+            operation: operation.to_string(),
+            outcome: "succeeded".to_string(),
+            started_at: timestamp(started_at),
+            duration_secs: seconds_since(started_at),
+            set_name: None,
+            archives: Vec::new(),
+            file_count: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            warnings: Vec::new(),
+            error: None,
+        };
+        match res {
+            Ok(summary) => {
+                item.set_name = summary.set_name.clone();
+                item.archives = summary.archives.clone();
+                item.file_count = summary.file_count;
+                item.bytes_in = summary.bytes_in;
+                item.bytes_out = summary.bytes_out;
+                item.warnings = summary.warnings.clone();
+            },
+            Err(e) => {
+                item.outcome = if matches!(e, AppError::Cancelled) { "cancelled" } else { "failed" }.to_string();
+                item.error = Some(e.to_string());
+            },
+        }
+        self.items.push(item);
+    }
+
+
+    pub fn add_skip(&mut self, source_id: i32, name: &str, operation: &str, reason: &str) {
+
+        // The reason a source was skipped is given as its only warning.
+
+        let now = Local::now();
+        self.items.push(ReportItem {
+            source_id: Some(source_id),
+            name: name.to_string(),
+            operation: operation.to_string(),
+            outcome: "skipped".to_string(),
+            started_at: timestamp(now),
+            duration_secs: 0.0,
+            set_name: None,
+            archives: Vec::new(),
+            file_count: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            warnings: vec![reason.to_string()],
+            error: None,
+        });
+    }
+
+
+    pub fn finish(&mut self, outcome: &str, error: Option<String>) {
+        self.duration_secs = seconds_since(self.start);
+        self.ended_at = Some(timestamp(Local::now()));
+        self.outcome = outcome.to_string();
+        self.error = error;
+    }
+
+
+    pub fn write_to_file(&self, report_path: &Path) -> Result<(), AppError> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(report_path, json)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, report_path.to_owned()))?;
+        info!("Run report written to {:?}", report_path);
+        Ok(())
+    }
+
+
+    pub fn write_to_stdout(&self) -> Result<(), AppError> {
+        println!("{}", serde_json::to_string_pretty(self)?);
+        Ok(())
+    }
+}
+
+
+fn timestamp(t: DateTime<Local>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+fn seconds_since(t: DateTime<Local>) -> f64 {
+    let ms = (Local::now() - t).num_milliseconds().max(0);
+    ms as f64 / 1000.0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_report_items_from_results() {
+        let mut report = RunReport::new(Some(12), "zip");
+
+        let mut summary = ArchiveSummary { set_name: Some("ctg 250101".to_string()), ..Default::default() };
+        summary.archives.push(ArchiveDetails { name: "ctg 250101 1 to 5000.zip".to_string(), file_count: 5000, archive_bytes: 2048 });
+        summary.file_count = 5000;
+        summary.bytes_in = 10240;
+        summary.bytes_out = 2048;
+        summary.warnings.push("a warning".to_string());
+
+        report.add_result(Some(100120), "ctg", "zip", Local::now(), Ok(&summary));
+        report.add_skip(100121, "euctr", "zip", "No changes since last zipped");
+        let err = AppError::MissingProgramParameter("sources".to_string());
+        report.add_result(Some(100122), "isrctn", "zip", Local::now(), Err(&err));
+        report.finish("failed", Some("sources missing".to_string()));
+
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["run_id"], 12);
+        assert_eq!(json["outcome"], "failed");
+        assert_eq!(json["items"][0]["archives"][0]["file_count"], 5000);
+        assert_eq!(json["items"][0]["bytes_out"], 2048);
+        assert_eq!(json["items"][0]["warnings"][0], "a warning");
+        assert_eq!(json["items"][1]["outcome"], "skipped");
+        assert_eq!(json["items"][2]["outcome"], "failed");
+        assert!(json["items"][2]["error"].is_string());
+        assert!(json.get("start").is_none());
+
+        let report = RunReport::new(None, "verify");
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert!(json["run_id"].is_null());
+    }
+}
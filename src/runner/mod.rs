@@ -0,0 +1,319 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::{DateTime, Local};
+use log::{error, info};
+use sqlx::{Pool, Postgres};
+use crate::api::{self, ArchiveObserver, JobResult, SourceOutcome, SourceResult};
+use crate::cancel::CancelToken;
+use crate::err::AppError;
+use crate::locks;
+use crate::setup::cli_reader::Operation;
+use crate::setup::config_reader::JobPars;
+use crate::setup::log_helper::LogContext;
+use crate::storage::{self, Storage};
+use crate::{archive_sets, data, jobs, unzipper, zipper, ArchiveSummary, SourceDetails};
+
+// The processing of MDR sources, shared by the command line and by ZipJob and UnzipJob. For each
+// source in turn: with changed_only, a source with no changes since its last archive set is skipped;
+// a lock is taken in the source's zipped folder; the operation is run on one of tokio's blocking
+// threads (with any archives made stored, or the latest stored set fetched, as the storage requires);
+// and, with a pool, the outcome is recorded in the run, along with the new set's state after a zip.
+// With keep_going a failed source is logged and the run moves on to the next; otherwise it stops
+// at the first failure. A cancelled run always stops. The [[jobs]] of a run are processed (by run_jobs)
+// in the same way, without the changed_only check.
+
+#[derive(Clone)]
+pub(crate) struct SourcePars {
+    pub parent_zipped: PathBuf,
+    pub parent_unzipped: PathBuf,
+    pub file_list: Vec<String>,
+    pub keep: usize,
+    pub dry_run: bool,
+    pub storage: Arc<dyn Storage>,
+    pub keep_local: bool,
+}
+
+pub(crate) struct SourceRun<'a> {
+    pub operation: Operation,
+    pub operation_name: &'a str,
+    pub pars: SourcePars,
+    pub changed_only: bool,
+    pub keep_going: bool,
+    pub locking: bool,
+    pub run_id: Option<i32>,
+    pub pool: Option<&'a Pool<Postgres>>,
+    pub observer: Arc<dyn ArchiveObserver>,
+    pub cancel: CancelToken,
+}
+
+pub(crate) struct JobRun<'a> {
+    pub keep_going: bool,
+    pub locking: bool,
+    pub run_id: Option<i32>,
+    pub pool: Option<&'a Pool<Postgres>>,
+    pub observer: Arc<dyn ArchiveObserver>,
+    pub cancel: CancelToken,
+}
+
+
+pub(crate) async fn run_sources(run: &SourceRun<'_>, sources: Vec<SourceDetails>,
+                on_result: &mut (dyn FnMut(&SourceResult, DateTime<Local>) + Send)) -> Result<JobResult, AppError> {
+
+    // Each source's result is passed to on_result (with the time it started) as soon as it is known.
+    // Errors in recording the run, rather than in processing a source, are returned at once.
+
+    let mut result = JobResult { run_id: run.run_id, sources: Vec::new(), cancelled: false };
+
+    for source in sources {
+        if run.cancel.is_cancelled() {
+            result.cancelled = true;
+            break;
+        }
+        let (source_id, name) = (source.id, source.database_name.clone());
+
+        if let (true, Some(pool)) = (run.changed_only, run.pool) {
+            if let Some(reason) = get_unchanged_reason(&source, &run.pars.parent_unzipped, pool).await? {
+                info!("Source {} ({}) skipped: {}", source_id, name, reason);
+                if let Some(run_id) = run.run_id {
+                    data::record_source_skip(run_id, source_id, run.operation_name, &reason, pool).await?;
+                }
+                run.observer.source_skipped(&name, &reason);
+                let skipped = SourceResult { source_id: Some(source_id), name, outcome: SourceOutcome::Skipped(reason) };
+                on_result(&skipped, Local::now());
+                result.sources.push(skipped);
+                continue;
+            }
+            info!("Source {} ({}) has changed since it was last zipped, or has no recorded archive set", source_id, name);
+        }
+
+        let started_at = Local::now();
+        run.observer.source_started(&name);
+        let lock_folder = run.pars.parent_zipped.join(&name);
+        let res = match if run.locking { locks::acquire_lock(&lock_folder).map(Some) } else { Ok(None) } {
+            Err(e) => Err(e),
+            Ok(_lock) => {
+                let (operation, pars, observer, cancel) = (run.operation, run.pars.clone(), run.observer.clone(), run.cancel.clone());
+                api::run_blocking(move || process_source(operation, &pars, source, observer.as_ref(), &cancel)).await
+            },
+        };
+        match &res {
+            Ok(summary) => run.observer.source_finished(summary),
+            Err(e) => run.observer.error(e),
+        }
+
+        if let (Some(pool), Some(run_id)) = (run.pool, run.run_id) {
+            data::record_source_run(run_id, Some(source_id), run.operation_name, started_at, &res, pool).await?;
+            if let Ok(summary) = &res {
+
+                // The state of the newly created archive set is kept for each source.
+
+                if run.operation == Operation::Zip && summary.set_name.is_some() {
+                    data::save_zip_state(source_id, summary, pool).await?;
+                }
+            }
+        }
+
+        let source_result = SourceResult { source_id: Some(source_id), name, outcome: to_outcome(res) };
+        on_result(&source_result, started_at);
+        if !add_result(&mut result, source_result, run.keep_going) {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+
+pub(crate) async fn run_jobs(run: &JobRun<'_>, jobs: &[JobPars],
+                on_result: &mut (dyn FnMut(&JobPars, &SourceResult, DateTime<Local>) + Send)) -> Result<JobResult, AppError> {
+
+    // As run_sources, for the [[jobs]] of the configuration. Each job is recorded by name, with the job's own operation.
+
+    let mut result = JobResult { run_id: run.run_id, sources: Vec::new(), cancelled: false };
+
+    for job in jobs {
+        if run.cancel.is_cancelled() {
+            result.cancelled = true;
+            break;
+        }
+
+        let started_at = Local::now();
+        run.observer.source_started(&job.name);
+        let res = match if run.locking { locks::acquire_lock(&jobs::lock_folder(job)).map(Some) } else { Ok(None) } {
+            Err(e) => Err(e),
+            Ok(_lock) => {
+                let (job, observer, cancel) = (job.clone(), run.observer.clone(), run.cancel.clone());
+                api::run_blocking(move || {
+                    let _ctx = LogContext::new().with("job", &job.name);
+                    jobs::run_job(&job, observer.as_ref(), &cancel)
+                }).await
+            },
+        };
+        match &res {
+            Ok(summary) => run.observer.source_finished(summary),
+            Err(e) => run.observer.error(e),
+        }
+
+        if let (Some(pool), Some(run_id)) = (run.pool, run.run_id) {
+            data::record_job_run(run_id, &job.name, job.operation.name(), started_at, &res, pool).await?;
+        }
+
+        let job_result = SourceResult { source_id: None, name: job.name.clone(), outcome: to_outcome(res) };
+        on_result(job, &job_result, started_at);
+        if !add_result(&mut result, job_result, run.keep_going) {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+
+fn add_result(result: &mut JobResult, item: SourceResult, keep_going: bool) -> bool {
+
+    // With keep_going a failed source or job is logged (it has already been recorded) and the run
+    // moves on to the next. Otherwise the run stops at the first failure. A cancelled run always
+    // stops. Returns whether the run should continue.
+
+    result.cancelled = matches!(item.outcome, SourceOutcome::Failed(AppError::Cancelled));
+    let failed = matches!(item.outcome, SourceOutcome::Failed(_));
+    if let (SourceOutcome::Failed(e), true, false) = (&item.outcome, keep_going, result.cancelled) {
+        error!("{} failed, continuing with the remainder: {}", item.name, e);
+    }
+    result.sources.push(item);
+    !(result.cancelled || (failed && !keep_going))
+}
+
+
+fn process_source(operation: Operation, pars: &SourcePars, source: SourceDetails, observer: &dyn ArchiveObserver,
+                  cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
+
+    // Runs on a blocking thread, so the source's log context is set here.
+
+    let _ctx = LogContext::new().with("source_id", source.id).with("source", &source.database_name);
+    let db_name = source.database_name.clone();
+    match operation {
+        Operation::Zip => {
+            let uploader = storage::ArchiveUploader::start(pars.storage.clone(), &db_name, &pars.parent_zipped, observer, cancel);
+            let res = zipper::zip_mdr_folder(source, &pars.parent_unzipped, &pars.parent_zipped, &uploader, cancel);
+            uploader.finish(res, pars.keep_local)
+        },
+        Operation::Prune => {
+            let mut summary = archive_sets::prune_mdr_archives(&source, &pars.parent_zipped, pars.keep, pars.dry_run)?;
+            summary.merge(storage::prune_stored_sets(pars.storage.as_ref(), &db_name, &pars.parent_zipped, pars.keep, pars.dry_run)?);
+            Ok(summary)
+        },
+        _ => {
+
+            // Sets are always read in the zipped folder, so the latest stored set is fetched first, if need be.
+
+            storage::fetch_latest_set(pars.storage.as_ref(), &db_name, &pars.parent_zipped)?;
+            match operation {
+                Operation::Unzip if !pars.file_list.is_empty() =>
+                    unzipper::extract_mdr_files(source, &pars.file_list, &pars.parent_zipped, &pars.parent_unzipped, observer, cancel),
+                Operation::Verify => unzipper::verify_mdr_folder(source, &pars.parent_zipped, observer, cancel),
+                Operation::Diff => archive_sets::diff_mdr_folder(&source, &pars.parent_zipped, &pars.parent_unzipped),
+                _ => unzipper::unzip_mdr_folder(source, &pars.parent_zipped, &pars.parent_unzipped, observer, cancel),
+            }
+        },
+    }
+}
+
+
+pub(crate) async fn get_unchanged_reason(source: &SourceDetails, parent_unzipped: &Path, pool: &Pool<Postgres>) -> Result<Option<String>, AppError> {
+
+    // Used with changed_only. A source is unchanged if none of its files is later than the start of its
+    // last recorded archive set. Files written while that set was being made are therefore counted as changes.
+    // Returns the reason for skipping the source, if it is unchanged.
+
+    let last_zipped_at = data::get_last_zipped_at(source.id, pool).await?;
+    let latest_change = zipper::get_latest_change(source, parent_unzipped)?;
+    Ok(match (last_zipped_at, latest_change) {
+        (Some(zipped), Some(changed)) if changed < zipped =>
+            Some(format!("No changes since the last archive set was started at {}", zipped.format("%Y-%m-%d %H:%M:%S"))),
+        _ => None,
+    })
+}
+
+
+pub(crate) fn run_outcome(result: &JobResult, keep_going: bool) -> &'static str {
+
+    // A run is only a partial success if, with keep_going, some sources failed but at least one
+    // was completed. Without keep_going the run stopped at its failure, and so has failed.
+
+    let failed = result.failures().len();
+    match failed {
+        _ if result.cancelled => "cancelled",
+        0 => "succeeded",
+        _ if keep_going && !result.summaries().is_empty() => "partial",
+        _ => "failed",
+    }
+}
+
+
+pub(crate) fn into_run_result(result: JobResult, keep_going: bool) -> Result<(), AppError> {
+
+    // The run's result, as an error for any outcome but success: PartialSuccess, listing the
+    // sources that failed, or the (first) failure itself.
+
+    let outcome = run_outcome(&result, keep_going);
+    let attempted = result.summaries().len() + result.failures().len();
+    let mut failed: Vec<(String, AppError)> = result.sources.into_iter()
+        .filter_map(|s| match s.outcome { SourceOutcome::Failed(e) => Some((s.name, e)), _ => None })
+        .collect();
+    match outcome {
+        "succeeded" => Ok(()),
+        "cancelled" => Err(AppError::Cancelled),
+        "partial" => {
+            let names: Vec<String> = failed.into_iter().map(|(name, _)| name).collect();
+            Err(AppError::PartialSuccess(names.len(), attempted, names))
+        },
+        _ => Err(failed.remove(0).1),
+    }
+}
+
+
+pub(crate) fn to_outcome(res: Result<ArchiveSummary, AppError>) -> SourceOutcome {
+    match res {
+        Ok(summary) => SourceOutcome::Completed(summary),
+        Err(e) => SourceOutcome::Failed(e),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_partial_success_needs_a_success() {
+        let completed = |name: &str| SourceResult { source_id: None, name: name.to_string(), outcome: SourceOutcome::Completed(ArchiveSummary::default()) };
+        let failed = |name: &str| SourceResult { source_id: None, name: name.to_string(),
+                                                 outcome: SourceOutcome::Failed(AppError::LockHeld(PathBuf::from(name), String::new())) };
+        let job_result = |sources: Vec<SourceResult>| JobResult { run_id: None, sources, cancelled: false };
+
+        assert!(into_run_result(job_result(vec![completed("ctg"), completed("isrctn")]), true).is_ok());
+        assert!(matches!(into_run_result(job_result(vec![completed("ctg"), failed("isrctn")]), true), Err(AppError::PartialSuccess(1, 2, _))));
+        match into_run_result(job_result(vec![failed("ctg"), failed("isrctn")]), true) {
+            Err(AppError::LockHeld(p, _)) => assert_eq!(p, PathBuf::from("ctg")),
+            _ => panic!("the first failure should be returned"),
+        }
+
+        // Without keep_going the run stopped at its failure, so has failed.
+
+        assert_eq!(run_outcome(&job_result(vec![completed("ctg"), failed("isrctn")]), false), "failed");
+    }
+
+    #[test]
+    fn check_run_stops_unless_keep_going() {
+        let failed = || SourceResult { source_id: None, name: "umls".to_string(), outcome: SourceOutcome::Failed(AppError::Cancelled) };
+        let mut result = JobResult { run_id: None, sources: Vec::new(), cancelled: false };
+        assert!(!add_result(&mut result, failed(), true));
+        assert!(result.cancelled);
+
+        let lock_failure = || SourceResult { source_id: None, name: "umls".to_string(),
+                                             outcome: SourceOutcome::Failed(AppError::LockHeld(PathBuf::from("umls"), String::new())) };
+        let mut result = JobResult { run_id: None, sources: Vec::new(), cancelled: false };
+        assert!(add_result(&mut result, lock_failure(), true));
+        assert!(!add_result(&mut result, lock_failure(), false));
+        assert!(!result.cancelled);
+    }
+}
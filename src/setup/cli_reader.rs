@@ -1,4 +1,5 @@
-use clap::{command, Arg, ArgMatches};
+use clap::{command, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use clap::parser::ValueSource;
 use crate::err::AppError;
 use std::ffi::OsString;
 use std::path::PathBuf;
@@ -6,135 +7,547 @@ use std::path::PathBuf;
 #[derive(Debug)]
 pub struct CliPars {
     pub source_list: String,
+    pub file_list: String,
     pub fz_folder: PathBuf,
     pub fu_folder: PathBuf,
+    pub keep: usize,
+    pub config_files: Vec<PathBuf>,
+    pub job_names: Vec<String>,
+    pub quiet_period: Option<u64>,
+    pub report_path: Option<PathBuf>,
+    pub verbosity: i8,
     pub flags: Flags, 
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Flags {
+    pub operation: Operation,
     pub do_zip: bool,
     pub do_unzip: bool,
+    pub do_locate: bool,
     pub all_mdr: bool,
     pub use_folder: bool,
+    pub changed_only: bool,
+    pub dry_run: bool,
+    pub all_jobs: bool,
+    pub json_output: bool,
+    pub keep_going: bool,
     pub test_run: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Zip,
+    Unzip,
+    Locate,
+    Verify,
+    List,
+    Prune,
+    Diff,
+    Run,
+    Daemon,
+    Watch,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Zip => "zip",
+            Operation::Unzip => "unzip",
+            Operation::Locate => "locate",
+            Operation::Verify => "verify",
+            Operation::List => "list",
+            Operation::Prune => "prune",
+            Operation::Diff => "diff",
+            Operation::Run => "run",
+            Operation::Daemon => "daemon",
+            Operation::Watch => "watch",
+        }
+    }
+
+    // Operations that only read archives and folders. They take no lock, and are not recorded.
+
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, Operation::Locate | Operation::Verify | Operation::List | Operation::Diff)
+    }
+
+    // Operations whose runs (or, for daemon and watch, whose triggered runs) are recorded in the
+    // zipping schema tables, which any pending migrations must therefore first create or update.
+
+    pub fn needs_migrations(&self) -> bool {
+        !self.is_read_only()
+    }
+}
+
 pub fn fetch_valid_arguments(args: Vec<OsString>) -> Result<CliPars, AppError>
 { 
     let parse_result = parse_args(args)?;
 
-    // These parameters guaranteed to unwrap OK as all have a default value of "".
+    // Operations are normally given as subcommands, whose arguments are checked by clap.
+    // The original flags (-z, -u, -l with -m, -s, -f) are retained, hidden, for existing 
+    // scheduled scripts - their combinations have to be checked here.
+
+    let (operation, matches) = match parse_result.subcommand() {
+        Some((name, sub_matches)) => {
+            check_no_legacy_flags(&parse_result)?;
+            (get_operation(name), sub_matches)
+        },
+        None => (get_legacy_operation(&parse_result)?, &parse_result),
+    };
+
+    // Missing string values default to "", missing flags to false.
+
+    let source_list = get_string(matches, "source_list");
+    let file_list = get_string(matches, "file_list");
+    let fz_folder = get_string(matches, "fz_folder");
+    let fu_folder = get_string(matches, "fu_folder");
+    // Config files may be given before and / or after the subcommand, and are kept in order.
+
+    let mut config_files = get_paths(&parse_result, "config_file");
+    if parse_result.subcommand().is_some() {
+        config_files.extend(get_paths(matches, "config_file"));
+    }
+    let keep = matches.try_get_one::<u64>("keep").ok().flatten().copied().unwrap_or(1) as usize;
+    let job_names: Vec<String> = matches.try_get_many::<String>("job_names").ok().flatten()
+        .map(|v| v.cloned().collect())
+        .unwrap_or_default();
+    let quiet_period = matches.try_get_one::<u64>("quiet_period").ok().flatten().copied();
 
-    let source_list = parse_result.get_one::<String>("source_list").unwrap();
-    let fz_folder = parse_result.get_one::<String>("fz_folder").unwrap();
-    let fu_folder = parse_result.get_one::<String>("fu_folder").unwrap();
+    // --report and --output are global, so may also be given before or after the subcommand.
 
-    // Flag values are false if not present, true if present.
+    let report_path = matches.try_get_one::<String>("report_path").ok().flatten().map(PathBuf::from);
+    let json_output = get_string(matches, "output") == "json";
+    let verbosity = get_count(matches, "verbose") as i8 - get_count(matches, "quiet") as i8;
 
-    let z_flag = parse_result.get_flag("z_flag");
-    let u_flag = parse_result.get_flag("u_flag");
+    let flags = Flags {
+        operation,
+        do_zip: operation == Operation::Zip,
+        do_unzip: operation == Operation::Unzip,
+        do_locate: operation == Operation::Locate,
+        all_mdr: get_flag(matches, "m_flag"),
+        use_folder: get_flag(matches, "f_flag"),
+        changed_only: get_flag(matches, "changed_only"),
+        dry_run: get_flag(matches, "dry_run"),
+        all_jobs: get_flag(matches, "all_jobs"),
+        json_output,
+        keep_going: get_flag(matches, "keep_going"),
+        test_run: get_flag(&parse_result, "t_flag") || get_flag(matches, "t_flag"),
+        };
 
-    let m_flag = parse_result.get_flag("m_flag");
+    if parse_result.subcommand().is_none() {
+        check_legacy_combinations(&flags, &source_list, &file_list)?;
+    }
 
-    let f_flag = parse_result.get_flag("f_flag");
-    let t_flag = parse_result.get_flag("t_flag");
+    Ok(CliPars {
+        source_list,
+        file_list,
+        fz_folder: PathBuf::from(fz_folder),
+        fu_folder: PathBuf::from(fu_folder),
+        keep,
+        config_files,
+        job_names,
+        quiet_period,
+        report_path,
+        verbosity,
+        flags,
+    })
+}
+
+
+fn get_operation(subcommand: &str) -> Operation {
+    match subcommand {
+        "zip" => Operation::Zip,
+        "unzip" => Operation::Unzip,
+        "locate" => Operation::Locate,
+        "verify" => Operation::Verify,
+        "list" => Operation::List,
+        "prune" => Operation::Prune,
+        "run" => Operation::Run,
+        "daemon" => Operation::Daemon,
+        "watch" => Operation::Watch,
+        _ => Operation::Diff,
+    }
+}
+
+
+fn check_no_legacy_flags(parse_result: &ArgMatches) -> Result<(), AppError> {
+
+    // The original flags cannot be combined with a subcommand (global options such as --config can).
+
+    let legacy_ids = ["z_flag", "u_flag", "l_flag", "source_list", "m_flag", "f_flag", 
+                      "fz_folder", "fu_folder", "file_list", "changed_only"];
+    if legacy_ids.iter().any(|id| parse_result.value_source(id) == Some(ValueSource::CommandLine)) {
+        let msg = "The original flags (e.g. -z, -u, -m, -s) cannot be used with a subcommand!".to_string();
+        return Result::Err(AppError::InconsistentProgramParameter(msg));
+    }
+    Ok(())
+}
+
+
+fn get_legacy_operation(parse_result: &ArgMatches) -> Result<Operation, AppError> {
+
+    let z_flag = get_flag(parse_result, "z_flag");
+    let u_flag = get_flag(parse_result, "u_flag");
+    let l_flag = get_flag(parse_result, "l_flag");
 
     if z_flag && u_flag {   // both set - do nothing and report as error
         let msg = "Both zip and unzip have been requested at the same time!".to_string();
         return Result::Err(AppError::InconsistentProgramParameter(msg));
     }
 
-    if !z_flag && !u_flag {   // neither set  - do nothing and report as error
-        let msg = "Neither zip or unzip have been requested!".to_string();
+    if l_flag && (z_flag || u_flag) {   // locate is a separate operation
+        let msg = "Locate has been requested at the same time as zip or unzip!".to_string();
         return Result::Err(AppError::InconsistentProgramParameter(msg));
     }
 
-    let flags = Flags {
-        do_zip: z_flag,
-        do_unzip: u_flag,
-        all_mdr: m_flag,
-        use_folder: f_flag,
-        test_run: t_flag,
-        };
+    if z_flag {
+        Ok(Operation::Zip)
+    }
+    else if u_flag {
+        Ok(Operation::Unzip)
+    }
+    else if l_flag {
+        Ok(Operation::Locate)
+    }
+    else {   // none set  - do nothing and report as error
+        let msg = "No operation (zip, unzip, locate, verify, list, prune, diff, run, daemon or watch) has been requested!".to_string();
+        Result::Err(AppError::InconsistentProgramParameter(msg))
+    }
+}
 
-    Ok(CliPars {
-        source_list: source_list.clone(),
-        fz_folder: PathBuf::from(fz_folder.clone()),
-        fu_folder: PathBuf::from(fu_folder.clone()),
-        flags: flags,
-    })
+
+fn check_legacy_combinations(flags: &Flags, source_list: &str, file_list: &str) -> Result<(), AppError> {
+
+    let has_sources = !source_list.trim().is_empty();
+
+    if flags.use_folder && (flags.all_mdr || has_sources) {   
+        let msg = "Both folder and mdr processing requested at the same time!".to_string();
+        return Result::Err(AppError::InconsistentProgramParameter(msg));
+    }
+ 
+    if !flags.all_mdr && !flags.use_folder && !has_sources {   
+        let msg = "No source type has been identified for zipping or unzipping!".to_string();
+        return Result::Err(AppError::InconsistentProgramParameter(msg));
+    }
+
+    if flags.changed_only && (!flags.do_zip || flags.use_folder) {
+        let msg = "Changed only (--changed-only) can only be used when zipping mdr sources!".to_string();
+        return Result::Err(AppError::InconsistentProgramParameter(msg));
+    }
+
+    if flags.do_locate {
+        if flags.use_folder {
+            let msg = "Files can only be located within mdr archives, not with -f!".to_string();
+            return Result::Err(AppError::InconsistentProgramParameter(msg));
+        }
+        if file_list.trim().is_empty() {
+            return Result::Err(AppError::MissingProgramParameter("extract (-x) list of files to locate".to_string()));
+        }
+    }
+    else if !file_list.trim().is_empty() && (!flags.do_unzip || flags.all_mdr || flags.use_folder) {
+        let msg = "Extraction of selected files requires an unzip (-u) of a single source (-s)!".to_string();
+        return Result::Err(AppError::InconsistentProgramParameter(msg));
+    }
+
+    Ok(())
+}
+
+
+fn get_string(matches: &ArgMatches, id: &str) -> String {
+    matches.try_get_one::<String>(id).ok().flatten().cloned().unwrap_or_default()
+}
+
+fn get_flag(matches: &ArgMatches, id: &str) -> bool {
+    matches.try_get_one::<bool>(id).ok().flatten().copied().unwrap_or(false)
+}
+
+fn get_count(matches: &ArgMatches, id: &str) -> u8 {
+    matches.try_get_one::<u8>(id).ok().flatten().copied().unwrap_or(0)
+}
+
+fn get_paths(matches: &ArgMatches, id: &str) -> Vec<PathBuf> {
+    matches.try_get_many::<String>(id).ok().flatten()
+        .map(|v| v.map(PathBuf::from).collect())
+        .unwrap_or_default()
 }
 
 
 fn parse_args(args: Vec<OsString>) -> Result<ArgMatches, AppError> {
 
     command!()
-        .about("Imports data from ROR json file (v2) and imports it into a database")
-        .arg(
-            Arg::new("source_list")
-           .short('s')
-           .long("sources")
-           .required(false)
-           .help("A string with a list of integer ids of the mdr sources")
-           .default_value("")
-         )
+        .about("Zips and unzips MDR source data folders, and other designated folders")
+        .subcommand(
+            Command::new("zip")
+            .about("Zips the designated mdr sources, or a single folder")
+            .arg(config_arg())
+            .args(target_args(true))
+            .arg(fz_arg().requires("f_flag"))
+            .arg(fu_arg().requires("f_flag"))
+            .arg(
+                Arg::new("changed_only")
+                .long("changed-only")
+                .help("Only zip mdr sources with files changed since their last recorded archive set")
+                .conflicts_with("f_flag")
+                .action(ArgAction::SetTrue)
+            )
+            .group(target_group(true))
+        )
+        .subcommand(
+            Command::new("unzip")
+            .about("Unzips the archives of the designated mdr sources, or a single archive")
+            .arg(config_arg())
+            .args(target_args(true))
+            .arg(fz_arg().requires("f_flag"))
+            .arg(fu_arg().requires("f_flag"))
+            .arg(
+                files_arg()
+                .help("A list of file names or trial ids, to be extracted from a single source's archives")
+                .requires("source_list")
+                .conflicts_with_all(["m_flag", "f_flag"])
+            )
+            .group(target_group(true))
+        )
+        .subcommand(
+            Command::new("locate")
+            .about("Reports the archives holding the listed files, using the catalogue index")
+            .arg(config_arg())
+            .args(target_args(false))
+            .arg(files_arg().required(true).help("A list of file names or trial ids to be located"))
+            .group(target_group(false))
+        )
+        .subcommand(
+            Command::new("verify")
+            .about("Reads each entry of the latest archive set of each source, or of a single archive, checking its checksum")
+            .arg(config_arg())
+            .args(target_args(true))
+            .arg(fz_arg().requires("f_flag"))
+            .group(target_group(true))
+        )
+        .subcommand(
+            Command::new("list")
+            .about("Lists the archive sets held for each source")
+            .arg(config_arg())
+            .args(target_args(false))
+            .group(target_group(false))
+        )
+        .subcommand(
+            Command::new("prune")
+            .about("Deletes all but the most recent archive sets of each source")
+            .arg(config_arg())
+            .args(target_args(false))
+            .arg(
+                Arg::new("keep")
+                .long("keep")
+                .help("The number of archive sets to retain for each source")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("1")
+            )
+            .arg(
+                Arg::new("dry_run")
+                .long("dry-run")
+                .help("Report the archive sets that would be deleted, without deleting them")
+                .action(ArgAction::SetTrue)
+            )
+            .group(target_group(false))
+        )
+        .subcommand(
+            Command::new("diff")
+            .about("Compares the latest archive set of each source, or a single archive, with the unzipped files")
+            .arg(config_arg())
+            .args(target_args(true))
+            .arg(fz_arg().requires("f_flag"))
+            .arg(fu_arg().requires("f_flag"))
+            .group(target_group(true))
+        )
+        .subcommand(
+            Command::new("run")
+            .about("Runs the named jobs defined in the configuration file(s), or all of them")
+            .arg(config_arg())
+            .arg(
+                Arg::new("job_names")
+                .help("The name(s) of the job(s) to run")
+                .num_args(1..)
+                .action(ArgAction::Append)
+            )
+            .arg(
+                Arg::new("all_jobs")
+                .short('a')
+                .long("all")
+                .help("Run all the jobs defined in the configuration")
+                .action(ArgAction::SetTrue)
+            )
+            .group(ArgGroup::new("jobs").args(["job_names", "all_jobs"]).required(true).multiple(false))
+        )
+        .subcommand(
+            Command::new("watch")
+            .about("Watches mdr source folders, zipping each source once its files stop changing")
+            .arg(config_arg())
+            .args(target_args(false))
+            .arg(
+                Arg::new("quiet_period")
+                .long("quiet-period")
+                .help("The number of seconds without changes after which a source is zipped")
+                .value_parser(clap::value_parser!(u64).range(1..))
+            )
+            .group(ArgGroup::new("target").args(["source_list", "m_flag"]).multiple(false))
+        )
+        .subcommand(
+            Command::new("daemon")
+            .about("Keeps running, carrying out the [[schedules]] in the configuration when each is due")
+            .arg(config_arg())
+        )
+        .arg(config_arg())
         .arg(
-             Arg::new("fz_folder")
-            .long("fz")
+            Arg::new("t_flag")
+            .short('t')
+            .long("test")
             .required(false)
-            .help("A string with the folder path of the zipped folder")
-            .default_value("")
+            .global(true)
+            .help("A flag signifying that this is part of an integration test run - suppresses logs")
+            .action(ArgAction::SetTrue)
         )
         .arg(
-            Arg::new("fu_folder")
-           .long("fu")
-           .required(false)
-           .help("A string with the folder path of the unzipped folder")
-           .default_value("")
-         )
-         .arg(
+            Arg::new("report_path")
+            .long("report")
+            .global(true)
+            .help("The path of a file to which a JSON report of the run is written")
+        )
+        .arg(
+            Arg::new("output")
+            .long("output")
+            .global(true)
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .help("With 'json' the JSON report of the run is written to stdout, in place of 'Done!'")
+        )
+        .arg(
+            Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .global(true)
+            .help("Log in more detail (-v for debug, -vv for trace)")
+            .action(ArgAction::Count)
+        )
+        .arg(
+            Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .global(true)
+            .help("Log less (-q for warnings and errors only, -qq for errors only)")
+            .action(ArgAction::Count)
+        )
+        .arg(
+            Arg::new("keep_going")
+            .long("keep-going")
+            .global(true)
+            .help("Continue with the remaining sources or jobs after one fails, exiting with the partial success code")
+            .action(ArgAction::SetTrue)
+        )
+
+        // The original flag based arguments, retained but hidden.
+
+        .args(target_args(true).into_iter().map(|a| a.hide(true)))
+        .arg(fz_arg().hide(true))
+        .arg(fu_arg().hide(true))
+        .arg(files_arg().hide(true))
+        .arg(
             Arg::new("z_flag")
            .short('z')
            .long("zip")
-           .required(false)
+           .hide(true)
            .help("A flag signifying perform a zip on the designated folders")
-           .action(clap::ArgAction::SetTrue)
+           .action(ArgAction::SetTrue)
          )
         .arg(
             Arg::new("u_flag")
            .short('u')
            .long("unzip")
-           .required(false)
+           .hide(true)
            .help("A flag signifying perform an unzip on the designated folders")
-           .action(clap::ArgAction::SetTrue)
+           .action(ArgAction::SetTrue)
         )
         .arg(
-            Arg::new("m_flag")
-            .short('m')
-            .long("mdr")
-            .required(false)
-            .help("A flag signifying that the default mdr folders should be used, for all mdr files")
-            .action(clap::ArgAction::SetTrue)
-       )
-        .arg(
-             Arg::new("f_flag")
-            .short('f')
-            .long("folder")
-            .required(false)
-            .help("A flag signifying use the -fz, -fu paths for zipped and unzipped files, not the mdr defaults")
-            .action(clap::ArgAction::SetTrue)
+            Arg::new("l_flag")
+           .short('l')
+           .long("locate")
+           .hide(true)
+           .help("A flag signifying report the archives holding the files listed in -x, using the catalogue index")
+           .action(ArgAction::SetTrue)
         )
-       .arg(
-            Arg::new("t_flag")
-            .short('t')
-            .long("test")
-            .required(false)
-            .help("A flag signifying that this is part of an integration test run - suppresses logs")
-            .action(clap::ArgAction::SetTrue)
+        .arg(
+            Arg::new("changed_only")
+            .long("changed-only")
+            .hide(true)
+            .help("A flag signifying only zip mdr sources with files changed since their last recorded archive set")
+            .action(ArgAction::SetTrue)
        )
        .try_get_matches_from(args)
-       .map_err(|e| AppError::ClapError(e))
+       .map_err(AppError::ClapError)
+}
+
+
+// Argument definitions shared by the subcommands (and the hidden original flags).
+
+fn target_args(include_folder: bool) -> Vec<Arg> {
+
+    let mut args = vec![
+        Arg::new("source_list")
+        .short('s')
+        .long("sources")
+        .help("A comma separated list of mdr sources: ids, id ranges (100120-100140) or database names, each optionally preceded by '!' to exclude it"),
+        Arg::new("m_flag")
+        .short('m')
+        .long("mdr")
+        .help("Use the default mdr folders, for all mdr sources")
+        .action(ArgAction::SetTrue),
+    ];
+    if include_folder {
+        args.push(
+            Arg::new("f_flag")
+            .short('f')
+            .long("folder")
+            .help("Use the --fz, --fu paths (or the fdr paths in the config file) rather than the mdr folders")
+            .action(ArgAction::SetTrue)
+        );
+    }
+    args
+}
+
+fn target_group(include_folder: bool) -> ArgGroup {
+
+    // Exactly one of the targets must be given.
+
+    let mut ids = vec!["source_list", "m_flag"];
+    if include_folder {
+        ids.push("f_flag");
+    }
+    ArgGroup::new("target").args(ids).required(true).multiple(false)
+}
+
+fn config_arg() -> Arg {
+    Arg::new("config_file")
+    .long("config")
+    .action(ArgAction::Append)
+    .help("The path of a configuration file. May be repeated, later files overriding earlier ones")
+}
+
+fn fz_arg() -> Arg {
+    Arg::new("fz_folder")
+    .long("fz")
+    .help("A string with the file path of the zipped archive")
+}
+
+fn fu_arg() -> Arg {
+    Arg::new("fu_folder")
+    .long("fu")
+    .help("A string with the folder path of the unzipped folder")
+}
+
+fn files_arg() -> Arg {
+    Arg::new("file_list")
+    .short('x')
+    .long("extract")
+    .help("A string with a list of file names or trial ids")
 }
 
 
@@ -147,18 +560,225 @@ mod tests {
     #[test]
     fn check_cli_no_minimal_params() {
         let target = "dummy target";  
-        let args : Vec<&str> = vec![target, "-z"];   // one of z or u essential
+        let args : Vec<&str> = vec![target, "zip", "-f"];   // an operation and one of m, s, f essential
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
         let res = fetch_valid_arguments(test_args).unwrap();
         assert_eq!(res.source_list, "");
         assert_eq!(res.fz_folder, PathBuf::new());
         assert_eq!(res.fu_folder, PathBuf::new());
-        assert_eq!(res.flags.do_zip, true);
-        assert_eq!(res.flags.do_unzip, false);
-        assert_eq!(res.flags.all_mdr, false);
-        assert_eq!(res.flags.use_folder, false);
-        assert_eq!(res.flags.test_run, false);
+        assert_eq!(res.flags.operation, Operation::Zip);
+        assert!(res.flags.do_zip);
+        assert!(!res.flags.do_unzip);
+        assert!(!res.flags.all_mdr);
+        assert!(res.flags.use_folder);
+        assert!(!res.flags.test_run);
+
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_with_z_flag_and_no_target() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-z"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let _res = fetch_valid_arguments(test_args).unwrap();
+    }
+
+    #[test]
+    fn check_cli_with_unzip_subcommand_and_files() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "unzip", "-s", "100120", "--extract", "NCT00000102", "-t"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.operation, Operation::Unzip);
+        assert_eq!(res.source_list, "100120");
+        assert_eq!(res.file_list, "NCT00000102");
+        assert!(res.flags.do_unzip);
+        assert!(res.flags.test_run);
+    }
+
+    #[test]
+    fn check_cli_with_prune_subcommand() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "prune", "-m", "--keep", "3", "--dry-run"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.operation, Operation::Prune);
+        assert_eq!(res.keep, 3);
+        assert!(res.flags.all_mdr);
+        assert!(res.flags.dry_run);
+        assert!(!res.flags.do_zip);
+    }
+
+    #[test]
+    fn check_cli_with_verify_and_diff_subcommands() {
+        let target = "dummy target";
+        for (sub, op) in [("verify", Operation::Verify), ("diff", Operation::Diff), ("list", Operation::List)] {
+            let args : Vec<&str> = vec![target, sub, "-s", "100120"];
+            let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+            let res = fetch_valid_arguments(test_args).unwrap();
+            assert_eq!(res.flags.operation, op);
+            assert_eq!(res.source_list, "100120");
+            assert!(op.is_read_only() && !op.needs_migrations());
+        }
+        for op in [Operation::Zip, Operation::Unzip, Operation::Prune, Operation::Run, Operation::Daemon, Operation::Watch] {
+            assert!(!op.is_read_only() && op.needs_migrations());
+        }
+    }
+
+    #[test]
+    fn check_cli_with_run_subcommand() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "run", "umls", "ontologies"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.operation, Operation::Run);
+        assert_eq!(res.job_names, vec!["umls", "ontologies"]);
+        assert!(!res.flags.all_jobs);
+
+        let args : Vec<&str> = vec![target, "run", "--all"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert!(res.flags.all_jobs);
+        assert!(res.job_names.is_empty());
+    }
+
+    #[test]
+    fn check_cli_with_daemon_subcommand() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "daemon", "--config", "schedules.toml"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.operation, Operation::Daemon);
+        assert_eq!(res.config_files, vec![PathBuf::from("schedules.toml")]);
+    }
+
+    #[test]
+    fn check_cli_with_watch_subcommand() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "watch", "-s", "100120, ctg", "--quiet-period", "600"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.operation, Operation::Watch);
+        assert_eq!(res.source_list, "100120, ctg");
+        assert_eq!(res.quiet_period, Some(600));
+
+        let args : Vec<&str> = vec![target, "watch"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.quiet_period, None);
+    }
+
+    #[test]
+    fn check_cli_with_report_options() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "zip", "-m", "--report", "reports/run.json"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.report_path, Some(PathBuf::from("reports/run.json")));
+        assert!(!res.flags.json_output);
+
+        let args : Vec<&str> = vec![target, "--output", "json", "verify", "-s", "100120"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.report_path, None);
+        assert!(res.flags.json_output);
+        assert!(!res.flags.keep_going);
+
+        let args : Vec<&str> = vec![target, "-z", "-m", "--output", "json", "--keep-going"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert!(res.flags.json_output);
+        assert!(res.flags.keep_going);
+    }
+
+    #[test]
+    fn check_cli_verbosity_counted() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-v", "zip", "-m"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.verbosity, 1);
+
+        let args : Vec<&str> = vec![target, "zip", "-m", "-vv"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.verbosity, 2);
+
+        let args : Vec<&str> = vec![target, "verify", "-s", "100120", "-qq"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.verbosity, -2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_with_unknown_output_format() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "zip", "-m", "--output", "xml"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let _res = fetch_valid_arguments(test_args).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_with_run_subcommand_and_no_jobs() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "run"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let _res = fetch_valid_arguments(test_args).unwrap();
+    }
+
+    #[test]
+    fn check_cli_with_config_files() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "--config", "machine.toml", "zip", "-m", "--config", "job.toml"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.config_files, vec![PathBuf::from("machine.toml"), PathBuf::from("job.toml")]);
+
+        let args : Vec<&str> = vec![target, "zip", "-m"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert!(res.config_files.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_with_zip_subcommand_and_two_targets() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "zip", "-m", "-f"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let _res = fetch_valid_arguments(test_args).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_with_unzip_subcommand_files_and_all_sources() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "unzip", "-m", "-x", "NCT00000102"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let _res = fetch_valid_arguments(test_args).unwrap();
+    }
 
+    #[test]
+    #[should_panic]
+    fn should_panic_with_legacy_flags_and_subcommand() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-z", "zip", "-m"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let _res = fetch_valid_arguments(test_args).unwrap();
     }
   
     #[test]
@@ -171,11 +791,11 @@ mod tests {
         assert_eq!(res.source_list, "");
         assert_eq!(res.fz_folder, PathBuf::new());
         assert_eq!(res.fu_folder, PathBuf::new());
-        assert_eq!(res.flags.do_zip, true);
-        assert_eq!(res.flags.do_unzip, false);
-        assert_eq!(res.flags.all_mdr, true);
-        assert_eq!(res.flags.use_folder, false);
-        assert_eq!(res.flags.test_run, false);
+        assert!(res.flags.do_zip);
+        assert!(!res.flags.do_unzip);
+        assert!(res.flags.all_mdr);
+        assert!(!res.flags.use_folder);
+        assert!(!res.flags.test_run);
 
     }
 
@@ -189,11 +809,11 @@ mod tests {
         assert_eq!(res.source_list, "");
         assert_eq!(res.fz_folder, PathBuf::new());
         assert_eq!(res.fu_folder, PathBuf::new());
-        assert_eq!(res.flags.do_zip, false);
-        assert_eq!(res.flags.do_unzip, true);
-        assert_eq!(res.flags.all_mdr, false);
-        assert_eq!(res.flags.use_folder, true);
-        assert_eq!(res.flags.test_run, false);
+        assert!(!res.flags.do_zip);
+        assert!(res.flags.do_unzip);
+        assert!(!res.flags.all_mdr);
+        assert!(res.flags.use_folder);
+        assert!(!res.flags.test_run);
 
     }
 
@@ -207,11 +827,11 @@ mod tests {
         assert_eq!(res.source_list, "100101, 100102, 100103, 100104");
         assert_eq!(res.fz_folder, PathBuf::new());
         assert_eq!(res.fu_folder, PathBuf::new());
-        assert_eq!(res.flags.do_zip, true);
-        assert_eq!(res.flags.do_unzip, false);
-        assert_eq!(res.flags.all_mdr, false);
-        assert_eq!(res.flags.use_folder, false);
-        assert_eq!(res.flags.test_run, false);
+        assert!(res.flags.do_zip);
+        assert!(!res.flags.do_unzip);
+        assert!(!res.flags.all_mdr);
+        assert!(!res.flags.use_folder);
+        assert!(!res.flags.test_run);
     }
 
 
@@ -225,11 +845,62 @@ mod tests {
         assert_eq!(res.source_list, "");
         assert_eq!(res.fz_folder, PathBuf::from("data\\zipped folder"));
         assert_eq!(res.fu_folder, PathBuf::from("data\\unzipped folder"));
-        assert_eq!(res.flags.do_zip, false);
-        assert_eq!(res.flags.do_unzip, true);
-        assert_eq!(res.flags.all_mdr, false);
-        assert_eq!(res.flags.use_folder, true);
-        assert_eq!(res.flags.test_run, false);
+        assert!(!res.flags.do_zip);
+        assert!(res.flags.do_unzip);
+        assert!(!res.flags.all_mdr);
+        assert!(res.flags.use_folder);
+        assert!(!res.flags.test_run);
+    }
+
+    #[test]
+    fn check_cli_with_u_flag_s_list_and_x_list() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-u", "-s", "100120", "-x", "NCT00000102, NCT00000104.json"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.source_list, "100120");
+        assert_eq!(res.file_list, "NCT00000102, NCT00000104.json");
+        assert!(!res.flags.do_zip);
+        assert!(res.flags.do_unzip);
+        assert!(!res.flags.all_mdr);
+        assert!(!res.flags.use_folder);
+    }
+
+    #[test]
+    fn check_cli_with_l_flag_s_list_and_x_list() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-l", "-s", "100120", "-x", "NCT00000102"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.source_list, "100120");
+        assert_eq!(res.file_list, "NCT00000102");
+        assert!(!res.flags.do_zip);
+        assert!(!res.flags.do_unzip);
+        assert!(res.flags.do_locate);
+    }
+
+    #[test]
+    fn check_cli_with_z_m_and_changed_only_flags() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-z", "-m", "--changed-only"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert!(res.flags.do_zip);
+        assert!(res.flags.all_mdr);
+        assert!(res.flags.changed_only);
+    }
+
+    #[test]
+    #[should_panic]
+    fn should_panic_with_l_and_z_flags() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-l", "-z", "-m"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let _res = fetch_valid_arguments(test_args).unwrap();
     }
 
     #[test]
@@ -253,11 +924,11 @@ mod tests {
         assert_eq!(res.source_list, "");
         assert_eq!(res.fz_folder, PathBuf::new());
         assert_eq!(res.fu_folder, PathBuf::new());
-        assert_eq!(res.flags.do_zip, true);
-        assert_eq!(res.flags.do_unzip, false);
-        assert_eq!(res.flags.all_mdr, true);
-        assert_eq!(res.flags.use_folder, false);
-        assert_eq!(res.flags.test_run, true);
+        assert!(res.flags.do_zip);
+        assert!(!res.flags.do_unzip);
+        assert!(res.flags.all_mdr);
+        assert!(!res.flags.use_folder);
+        assert!(res.flags.test_run);
     }
 
 }
@@ -1,19 +1,36 @@
 
-use std::sync::OnceLock;
 use toml;
+use toml::{Table, Value};
 use serde::Deserialize;
 use crate::err::AppError;
-use std::path::PathBuf;
+use crate::setup::cli_reader::Operation;
+use crate::daemon::cron::CronSchedule;
+use crate::locks::LockScope;
+use crate::storage;
+use crate::setup::log_helper::{LogFormat, LogMode};
+use log::LevelFilter;
+use zip::CompressionMethod;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-// Following 3 structs used in the reading of the toml config file
+// Following 9 structs used in the reading of the toml config file
 
 #[derive(Debug, Deserialize)]
 pub struct TomlConfig {
     pub folders: Option<TomlFolderPars>, 
     pub database: Option<TomlDBPars>,
+    pub jobs: Option<Vec<TomlJob>>,
+    pub schedules: Option<Vec<TomlSchedule>>,
+    pub watch: Option<TomlWatchPars>,
+    pub locking: Option<TomlLockingPars>,
+    pub logging: Option<TomlLoggingPars>,
+    pub storage: Option<TomlStoragePars>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct TomlFolderPars {
     pub mdr_zipped: Option<String>,
     pub mdr_unzipped: Option<String>,
@@ -22,20 +39,87 @@ pub struct TomlFolderPars {
     pub log_folder_path: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct TomlDBPars {
     pub db_host: Option<String>,
     pub db_user: Option<String>,
     pub db_password: Option<String>,
+    pub db_password_file: Option<String>,
     pub db_port: Option<String>,
     pub db_name: Option<String>,
 }
 
-// Following 3 structs used in the generation of the config parameters
+#[derive(Debug, Deserialize)]
+pub struct TomlJob {
+    pub name: Option<String>,
+    pub operation: Option<String>,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub compression: Option<String>,
+    pub compression_level: Option<i64>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub keep: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TomlSchedule {
+    pub name: Option<String>,
+    pub cron: Option<String>,
+    pub command: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TomlWatchPars {
+    pub sources: Option<String>,
+    pub quiet_period_secs: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TomlLockingPars {
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TomlLoggingPars {
+    pub format: Option<String>,
+    pub level: Option<String>,
+    pub mode: Option<String>,
+    pub rotate_size_mb: Option<i64>,
+    pub rotate_age_hours: Option<i64>,
+    pub max_files: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TomlStoragePars {
+    pub kind: Option<String>,
+    pub root: Option<String>,
+    pub endpoint: Option<String>,
+    pub bucket: Option<String>,
+    pub region: Option<String>,
+    pub prefix: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub key_file: Option<String>,
+    pub key_passphrase: Option<String>,
+    pub known_hosts: Option<String>,
+    pub keep_local: Option<bool>,
+}
+
+// Following 9 structs (and the lock scope) used in the generation of the config parameters
 
 pub struct Config {
     pub folders: FolderPars, 
     pub db_pars: DBPars,
+    pub jobs: Vec<JobPars>,
+    pub schedules: Vec<SchedulePars>,
+    pub watch: WatchPars,
+    pub lock_scope: LockScope,
+    pub logging: LoggingPars,
+    pub storage: StoragePars,
 }
 
 pub struct FolderPars {
@@ -46,7 +130,7 @@ pub struct FolderPars {
     pub log_folder_path: PathBuf,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct DBPars {
     pub db_host: String,
     pub db_user: String,
@@ -55,16 +139,249 @@ pub struct DBPars {
     pub db_name: String,
 }
 
-// OnceLock used to provide a static singleton resource with database connection parameters
+#[derive(Debug, Clone)]
+pub struct JobPars {
+    pub name: String,
+    pub operation: Operation,
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub compression: CompressionMethod,
+    pub compression_level: Option<i64>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub keep: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulePars {
+    pub name: String,
+    pub cron: CronSchedule,
+    pub command: Vec<String>,
+}
+
+pub struct WatchPars {
+    pub sources: String,
+    pub quiet_period_secs: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggingPars {
+    pub format: LogFormat,
+    pub level: LevelFilter,
+    pub mode: LogMode,
+    pub rotate_size_mb: Option<u64>,
+    pub rotate_age_hours: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+// Where the MDR archive sets are stored (see storage). With no [storage] section they are only
+// kept in mdr_zipped. Otherwise mdr_zipped is where sets are made, and fetched to, and keep_local
+// says whether a set's archives stay there once stored.
+
+#[derive(Debug, Clone)]
+pub struct StoragePars {
+    pub backend: StorageBackend,
+    pub keep_local: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum StorageBackend {
+    Local(Option<PathBuf>),
+    S3(S3Pars),
+    Sftp(SftpPars),
+}
+
+#[derive(Clone)]
+pub struct S3Pars {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+#[derive(Clone)]
+pub struct SftpPars {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub key_file: PathBuf,
+    pub key_passphrase: Option<String>,
+    pub known_hosts: PathBuf,
+    pub root: String,
+}
+
+impl StoragePars {
+    pub fn is_default(&self) -> bool {
+        matches!(self.backend, StorageBackend::Local(None))
+    }
+}
+
+impl fmt::Debug for S3Pars {
+
+    // As with the database password, the secret key is never shown.
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Pars")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .field("access_key", &self.access_key)
+            .field("secret_key", &"********")
+            .finish()
+    }
+}
+
+impl fmt::Debug for SftpPars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SftpPars")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("key_file", &self.key_file)
+            .field("key_passphrase", &self.key_passphrase.as_ref().map(|_| "********"))
+            .field("known_hosts", &self.known_hosts)
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl fmt::Debug for DBPars {
+
+    // The password is never shown, so that the parameters can be safely logged.
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DBPars")
+            .field("db_host", &self.db_host)
+            .field("db_user", &self.db_user)
+            .field("db_password", &"********")
+            .field("db_port", &self.db_port)
+            .field("db_name", &self.db_name)
+            .finish()
+    }
+}
+
+// The configuration file(s) are found by searching, in order: the --config argument(s), 
+// the ZIPPER_CONFIG environment variable (which may hold a list of paths), the user's 
+// config folder (XDG_CONFIG_HOME or ~/.config, /zipper/app_config.toml), and the current 
+// folder (app_config.toml). The first of these that is present is used. 
+// Where several files are given, later files override earlier ones, value by value. A file
+// may also name a 'base' file (e.g. a machine-level file) which it overrides in the same way.
+
+pub fn get_config_string(cli_files: &[PathBuf]) -> Result<(String, Vec<PathBuf>), AppError> {
+
+    let config_files = find_config_files(cli_files, env::var_os("ZIPPER_CONFIG"), user_config_dir())?;
+
+    let mut merged = Table::new();
+    for f in &config_files {
+        let mut visited = Vec::new();
+        let layer = load_config_layer(f, &mut visited)?;
+        merge_tables(&mut merged, layer);
+    }
+
+    let config_string = toml::to_string(&merged)
+        .map_err(|e| AppError::ConfigurationError("Unable to combine configuration files.".to_string(), e.to_string()))?;
+    Ok((config_string, config_files))
+}
+
+
+fn find_config_files(cli_files: &[PathBuf], env_value: Option<OsString>, user_dir: Option<PathBuf>) -> Result<Vec<PathBuf>, AppError> {
+
+    if !cli_files.is_empty() {
+        return Ok(cli_files.to_vec());
+    }
+
+    if let Some(v) = env_value.filter(|v| !v.is_empty()) {
+        return Ok(env::split_paths(&v).filter(|p| !p.as_os_str().is_empty()).collect());
+    }
+
+    let user_file = user_dir.map(|d| d.join("zipper").join("app_config.toml"));
+    if let Some(f) = user_file.as_ref().filter(|f| f.is_file()) {
+        return Ok(vec![f.to_owned()]);
+    }
+
+    let cwd_file = PathBuf::from("./app_config.toml");
+    if cwd_file.is_file() {
+        return Ok(vec![cwd_file]);
+    }
+
+    let searched = match user_file {
+        Some(f) => format!("--config, ZIPPER_CONFIG, {:?}, ./app_config.toml", f),
+        None => "--config, ZIPPER_CONFIG, ./app_config.toml".to_string(),
+    };
+    Err(AppError::ConfigurationError("No configuration file found.".to_string(), format!("Searched {}", searched)))
+}
+
+
+fn user_config_dir() -> Option<PathBuf> {
+
+    let non_empty = |k: &str| env::var_os(k).filter(|v| !v.is_empty()).map(PathBuf::from);
+    non_empty("XDG_CONFIG_HOME")
+        .or_else(|| if cfg!(windows) { non_empty("APPDATA") } else { None })
+        .or_else(|| non_empty("HOME").map(|h| h.join(".config")))
+}
+
+
+fn load_config_layer(config_file: &Path, visited: &mut Vec<PathBuf>) -> Result<Table, AppError> {
+
+    // A relative base path is taken as relative to the folder of the file naming it.
+
+    if visited.iter().any(|v| v == config_file) {
+        return Err(AppError::ConfigurationError("Configuration files refer to each other in a loop.".to_string(),
+                        format!("{:?} is its own base", config_file)));
+    }
+    visited.push(config_file.to_owned());
+
+    let config_string = fs::read_to_string(config_file)
+        .map_err(|e| AppError::IoReadErrorWithPath(e, config_file.to_owned()))?;
+    let mut table = config_string.parse::<Table>()
+        .map_err(|e| AppError::ConfigurationError(format!("Unable to parse config file {:?}.", config_file), e.to_string()))?;
+
+    match table.remove("base") {
+        Some(Value::String(b)) => {
+            let base_file = config_file.parent().unwrap_or(Path::new("")).join(b);
+            let mut base = load_config_layer(&base_file, visited)?;
+            merge_tables(&mut base, table);
+            Ok(base)
+        },
+        Some(_) => Err(AppError::ConfigurationError(format!("Invalid 'base' value in {:?}.", config_file),
+                        "The base should be the path of another configuration file.".to_string())),
+        None => Ok(table),
+    }
+}
+
+
+fn merge_tables(base: &mut Table, over: Table) {
+
+    // Sections are merged key by key, other values replaced.
+
+    for (k, v) in over {
+        match (base.get_mut(&k), v) {
+            (Some(Value::Table(b)), Value::Table(o)) => merge_tables(b, o),
+            (_, v) => { base.insert(k, v); },
+        }
+    }
+}
+
+
+pub fn populate_config_vars(config_string: &str) -> Result<Config, AppError> {
+    read_config_vars(config_string, |k| env::var(k).ok(), pgpass_path().as_deref())
+}
+
 
-pub static DB_PARS: OnceLock<DBPars> = OnceLock::new();
+pub(crate) fn read_config_vars(config_string: &str, lookup: impl Fn(&str) -> Option<String>,
+                pgpass_file: Option<&Path>) -> Result<Config, AppError> {
 
-pub fn populate_config_vars(config_string: &String) -> Result<Config, AppError> {
+    // The environment and the .pgpass file are passed in, so that tests do not depend on either.
     
-    let toml_config = toml::from_str::<TomlConfig>(&config_string)
+    let mut toml_config = toml::from_str::<TomlConfig>(config_string)
         .map_err(|_| {AppError::ConfigurationError("Unable to parse config file.".to_string(),
                                        "File (app_config.toml) may be malformed.".to_string())})?;
 
+    apply_env_overrides(&mut toml_config, &lookup);
+
     let toml_database = match toml_config.database {
         Some(d) => d,
         None => {return Result::Err(AppError::ConfigurationError("Missing or misspelt configuration section.".to_string(),
@@ -78,17 +395,70 @@ pub fn populate_config_vars(config_string: &String) -> Result<Config, AppError>
     };
    
     let config_folders = verify_file_parameters(toml_folders)?;
-    let config_db_pars = verify_db_parameters(toml_database)?;
+    let config_db_pars = verify_db_parameters(toml_database, pgpass_file)?;
+    let config_jobs = verify_job_parameters(toml_config.jobs.unwrap_or_default())?;
+    let config_schedules = verify_schedule_parameters(toml_config.schedules.unwrap_or_default())?;
+    let config_watch = verify_watch_parameters(toml_config.watch)?;
+    let lock_scope = match toml_config.locking.and_then(|l| l.scope).map(|s| s.trim().to_lowercase()).as_deref() {
+        None | Some("") | Some("source") => LockScope::Source,
+        Some("global") => LockScope::Global,
+        Some(s) => return Err(AppError::ConfigurationError("Invalid [locking] scope.".to_string(),
+                        format!("Scope '{}' should be 'source' or 'global'.", s))),
+    };
+    let config_logging = verify_logging_parameters(toml_config.logging)?;
+    let config_storage = verify_storage_parameters(toml_config.storage, &lookup)?;
+    storage::check_backend_built(&config_storage.backend)?;
    
-    let _ = DB_PARS.set(config_db_pars.clone());
-
     Ok(Config{
         folders: config_folders,
         db_pars: config_db_pars,
+        jobs: config_jobs,
+        schedules: config_schedules,
+        watch: config_watch,
+        lock_scope,
+        logging: config_logging,
+        storage: config_storage,
     })
 }
 
 
+fn apply_env_overrides(toml_config: &mut TomlConfig, lookup: impl Fn(&str) -> Option<String>) {
+
+    // Any [folders] or [database] value can be overridden by an environment variable named 
+    // 'ZIPPER_' plus the upper-cased key, e.g. ZIPPER_DB_PASSWORD. Empty values are ignored.
+    // A section missing from the file is created if a value for it is in the environment.
+
+    let env_value = |key: &str| lookup(&format!("ZIPPER_{}", key.to_uppercase()))
+                                    .filter(|v| !v.trim().is_empty());
+
+    let folder_keys = ["mdr_zipped", "mdr_unzipped", "fdr_zipped", "fdr_unzipped", "log_folder_path"];
+    if toml_config.folders.is_none() && folder_keys.iter().any(|k| env_value(k).is_some()) {
+        toml_config.folders = Some(TomlFolderPars::default());
+    }
+    if let Some(f) = toml_config.folders.as_mut() {
+        let fields = [&mut f.mdr_zipped, &mut f.mdr_unzipped, &mut f.fdr_zipped, &mut f.fdr_unzipped, &mut f.log_folder_path];
+        for (key, field) in folder_keys.iter().zip(fields) {
+            if let Some(v) = env_value(key) {
+                *field = Some(v);
+            }
+        }
+    }
+
+    let db_keys = ["db_host", "db_user", "db_password", "db_password_file", "db_port", "db_name"];
+    if toml_config.database.is_none() && db_keys.iter().any(|k| env_value(k).is_some()) {
+        toml_config.database = Some(TomlDBPars::default());
+    }
+    if let Some(d) = toml_config.database.as_mut() {
+        let fields = [&mut d.db_host, &mut d.db_user, &mut d.db_password, &mut d.db_password_file, &mut d.db_port, &mut d.db_name];
+        for (key, field) in db_keys.iter().zip(fields) {
+            if let Some(v) = env_value(key) {
+                *field = Some(v);
+            }
+        }
+    }
+}
+
+
 fn verify_file_parameters(toml_folders: TomlFolderPars) -> Result<FolderPars, AppError> {
 
     let mdr_zipped_string = check_essential_string (toml_folders.mdr_zipped, "mdr zipped folder", "mdr_zipped")?; 
@@ -107,6 +477,220 @@ fn verify_file_parameters(toml_folders: TomlFolderPars) -> Result<FolderPars, Ap
 }
 
 
+fn verify_job_parameters(toml_jobs: Vec<TomlJob>) -> Result<Vec<JobPars>, AppError> {
+
+    // Each [[jobs]] entry needs a unique name, a source and a destination. The operation defaults 
+    // to zip, and the compression to deflated. Filters and retention are optional.
+
+    let mut jobs: Vec<JobPars> = Vec::new();
+
+    for (i, j) in toml_jobs.into_iter().enumerate() {
+
+        let name = check_essential_string (j.name, &format!("job {} name", i + 1), "name")?;
+        let job_error = |d: String| AppError::ConfigurationError(format!("Invalid definition of job '{}'.", name), d);
+
+        if jobs.iter().any(|other| other.name == name) {
+            return Err(job_error("Job names must be unique.".to_string()));
+        }
+        let operation = match j.operation.as_deref().map(|o| o.trim().to_lowercase()).as_deref() {
+            None | Some("zip") => Operation::Zip,
+            Some("unzip") => Operation::Unzip,
+            Some(o) => return Err(job_error(format!("Operation '{}' should be 'zip' or 'unzip'.", o))),
+        };
+        let source = check_essential_string (j.source, &format!("job '{}' source", name), "source")?;
+        let destination = check_essential_string (j.destination, &format!("job '{}' destination", name), "destination")?;
+        let compression = match j.compression.as_deref().map(|c| c.trim().to_lowercase()).as_deref() {
+            None | Some("deflated") => CompressionMethod::Deflated,
+            Some("stored") => CompressionMethod::Stored,
+            Some("bzip2") => CompressionMethod::Bzip2,
+            Some("zstd") => CompressionMethod::Zstd,
+            Some(c) => return Err(job_error(format!("Compression '{}' should be 'stored', 'deflated', 'bzip2' or 'zstd'.", c))),
+        };
+        let keep = match j.keep {
+            Some(k) if k < 1 => return Err(job_error("The number of archives to keep must be at least 1.".to_string())),
+            k => k.map(|k| k as usize),
+        };
+
+        jobs.push(JobPars {
+            operation,
+            source: PathBuf::from(source),
+            destination: PathBuf::from(destination),
+            compression,
+            compression_level: j.compression_level,
+            include: j.include.unwrap_or_default(),
+            exclude: j.exclude.unwrap_or_default(),
+            keep,
+            name,
+        });
+    }
+    Ok(jobs)
+}
+
+
+fn verify_schedule_parameters(toml_schedules: Vec<TomlSchedule>) -> Result<Vec<SchedulePars>, AppError> {
+
+    // Each [[schedules]] entry needs a unique name, a cron expression and the command to run,
+    // written as it would be on the command line (without the program name).
+
+    let mut schedules: Vec<SchedulePars> = Vec::new();
+
+    for (i, s) in toml_schedules.into_iter().enumerate() {
+
+        let name = check_essential_string (s.name, &format!("schedule {} name", i + 1), "name")?;
+        let schedule_error = |d: String| AppError::ConfigurationError(format!("Invalid definition of schedule '{}'.", name), d);
+
+        if schedules.iter().any(|other| other.name == name) {
+            return Err(schedule_error("Schedule names must be unique.".to_string()));
+        }
+        let cron_string = check_essential_string (s.cron, &format!("schedule '{}' cron expression", name), "cron")?;
+        let cron = CronSchedule::parse(&cron_string).map_err(schedule_error)?;
+        let command = check_essential_string (s.command, &format!("schedule '{}' command", name), "command")?;
+
+        schedules.push(SchedulePars {
+            cron,
+            command: split_command(&command),
+            name,
+        });
+    }
+    Ok(schedules)
+}
+
+
+fn verify_watch_parameters(toml_watch: Option<TomlWatchPars>) -> Result<WatchPars, AppError> {
+
+    // The [watch] section is optional. The quiet period defaults to 15 minutes.
+
+    let (sources, quiet_period) = match toml_watch {
+        Some(w) => (w.sources.unwrap_or_default(), w.quiet_period_secs),
+        None => (String::new(), None),
+    };
+    let quiet_period_secs = match quiet_period {
+        Some(q) if q < 1 => return Err(AppError::ConfigurationError("Invalid [watch] quiet period.".to_string(),
+                                "quiet_period_secs must be at least 1.".to_string())),
+        Some(q) => q as u64,
+        None => 900,
+    };
+    Ok(WatchPars { sources, quiet_period_secs })
+}
+
+
+fn verify_logging_parameters(toml_logging: Option<TomlLoggingPars>) -> Result<LoggingPars, AppError> {
+
+    let toml_logging = toml_logging.unwrap_or_default();
+    let lower = |v: Option<String>| v.map(|s| s.trim().to_lowercase()).unwrap_or_default();
+    let invalid = |key: &str, detail: String| AppError::ConfigurationError(format!("Invalid [logging] {}.", key), detail);
+
+    let format = match lower(toml_logging.format).as_str() {
+        "" | "pattern" => LogFormat::Pattern,
+        "json" => LogFormat::Json,
+        s => return Err(invalid("format", format!("Format '{}' should be 'pattern' or 'json'.", s))),
+    };
+    let level = match lower(toml_logging.level).as_str() {
+        "" | "info" => LevelFilter::Info,
+        "error" => LevelFilter::Error,
+        "warn" => LevelFilter::Warn,
+        "debug" => LevelFilter::Debug,
+        "trace" => LevelFilter::Trace,
+        s => return Err(invalid("level", format!("Level '{}' should be 'error', 'warn', 'info', 'debug' or 'trace'.", s))),
+    };
+    let mode = match lower(toml_logging.mode).as_str() {
+        "" | "run" => LogMode::PerRun,
+        "daily" => LogMode::Daily,
+        s => return Err(invalid("mode", format!("Mode '{}' should be 'run' or 'daily'.", s))),
+    };
+
+    // Sizes, ages and counts must all be at least 1 if given.
+
+    let positive = |key: &str, v: Option<i64>| -> Result<Option<u64>, AppError> {
+        match v {
+            Some(n) if n < 1 => Err(invalid(key, format!("{} must be at least 1.", key))),
+            _ => Ok(v.map(|n| n as u64)),
+        }
+    };
+    Ok(LoggingPars {
+        format,
+        level,
+        mode,
+        rotate_size_mb: positive("rotate_size_mb", toml_logging.rotate_size_mb)?,
+        rotate_age_hours: positive("rotate_age_hours", toml_logging.rotate_age_hours)?,
+        max_files: positive("max_files", toml_logging.max_files)?.map(|n| n as usize),
+    })
+}
+
+
+fn verify_storage_parameters(toml_storage: Option<TomlStoragePars>, lookup: impl Fn(&str) -> Option<String>) -> Result<StoragePars, AppError> {
+
+    // The [storage] section is optional. S3 keys may instead be given by the environment, as
+    // ZIPPER_S3_ACCESS_KEY and ZIPPER_S3_SECRET_KEY, or the usual AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY.
+    // SFTP uses key based authentication only, with the host's key checked against a known_hosts file
+    // (by default the user's own). A passphrase for the key may be given as ZIPPER_SFTP_KEY_PASSPHRASE.
+
+    let toml_storage = toml_storage.unwrap_or_default();
+    let invalid = |detail: String| AppError::ConfigurationError("Invalid [storage] section.".to_string(), detail);
+    let value = |v: Option<String>| v.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    let env_value = |keys: [&str; 2]| keys.iter().find_map(|k| value(lookup(k)));
+
+    let backend = match value(toml_storage.kind).map(|k| k.to_lowercase()).as_deref() {
+        None | Some("local") => StorageBackend::Local(value(toml_storage.root).map(PathBuf::from)),
+        Some("s3") => {
+            let required = |v: Option<String>, key: &str| value(v).ok_or_else(|| invalid(format!("S3 storage needs a value for {}.", key)));
+            StorageBackend::S3(S3Pars {
+                endpoint: required(toml_storage.endpoint, "endpoint")?.trim_end_matches('/').to_string(),
+                bucket: required(toml_storage.bucket, "bucket")?,
+                region: value(toml_storage.region).unwrap_or("us-east-1".to_string()),
+                prefix: value(toml_storage.prefix).unwrap_or_default().trim_matches('/').to_string(),
+                access_key: value(toml_storage.access_key).or_else(|| env_value(["ZIPPER_S3_ACCESS_KEY", "AWS_ACCESS_KEY_ID"]))
+                                .ok_or_else(|| invalid("S3 storage needs an access_key.".to_string()))?,
+                secret_key: value(toml_storage.secret_key).or_else(|| env_value(["ZIPPER_S3_SECRET_KEY", "AWS_SECRET_ACCESS_KEY"]))
+                                .ok_or_else(|| invalid("S3 storage needs a secret_key.".to_string()))?,
+            })
+        },
+        Some("sftp") => {
+            let required = |v: Option<String>, key: &str| value(v).ok_or_else(|| invalid(format!("SFTP storage needs a value for {}.", key)));
+            StorageBackend::Sftp(SftpPars {
+                host: required(toml_storage.host, "host")?,
+                port: toml_storage.port.unwrap_or(22),
+                user: required(toml_storage.user, "user")?,
+                key_file: PathBuf::from(required(toml_storage.key_file, "key_file")?),
+                key_passphrase: value(toml_storage.key_passphrase).or_else(|| value(lookup("ZIPPER_SFTP_KEY_PASSPHRASE"))),
+                known_hosts: PathBuf::from(value(toml_storage.known_hosts).unwrap_or("~/.ssh/known_hosts".to_string())),
+                root: value(toml_storage.root).unwrap_or_default().trim_end_matches('/').to_string(),
+            })
+        },
+        Some(k) => return Err(invalid(format!("Kind '{}' should be 'local', 's3' or 'sftp'.", k))),
+    };
+    Ok(StoragePars { backend, keep_local: toml_storage.keep_local.unwrap_or(true) })
+}
+
+
+fn split_command(command: &str) -> Vec<String> {
+
+    // Splits on white space, except within double quotes (e.g. around paths with spaces).
+
+    let mut args: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+
+    for c in command.chars() {
+        match c {
+            '"' => { in_quotes = !in_quotes; has_arg = true; },
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            },
+            c => { current.push(c); has_arg = true; },
+        }
+    }
+    if has_arg {
+        args.push(current);
+    }
+    args
+}
+
+
 fn check_essential_string (src_name: Option<String>, value_name: &str, config_name: &str) -> Result<String, AppError> {
  
     let s = match src_name {
@@ -114,9 +698,9 @@ fn check_essential_string (src_name: Option<String>, value_name: &str, config_na
         None => "none".to_string(),
     };
 
-    if s == "none".to_string() || s.trim() == "".to_string()
+    if s == "none" || s.trim() == ""
     {
-        return Result::Err(AppError::ConfigurationError("Essential configuration value missing or misspelt.".to_string(),
+        Result::Err(AppError::ConfigurationError("Essential configuration value missing or misspelt.".to_string(),
         format!("Cannot find a value for {} ({}).", value_name, config_name)))
     }
     else {
@@ -131,7 +715,7 @@ fn check_defaulted_string (src_name: Option<String>, value_name: &str, default_n
         None => "none".to_string(),
     };
 
-    if s == "none".to_string() || s.trim() == "".to_string()
+    if s == "none" || s.trim() == ""
     {
         println!("No value found for {} path in config file - 
         using the provided default value ('{}') instead.", value_name, default_name);
@@ -143,22 +727,35 @@ fn check_defaulted_string (src_name: Option<String>, value_name: &str, default_n
 }
 
  
-fn verify_db_parameters(toml_database: TomlDBPars) -> Result<DBPars, AppError> {
+fn verify_db_parameters(toml_database: TomlDBPars, pgpass_file: Option<&Path>) -> Result<DBPars, AppError> {
 
     // Check user name and password first as there are no defaults for these values.
     // They must therefore be present.
 
     let db_user = check_essential_string (toml_database.db_user, "database user name", "db_user")?; 
 
-    let db_password = check_essential_string (toml_database.db_password, "database user password", "db_password")?;
-       
     let db_host = check_defaulted_string (toml_database.db_host, "DB host", "localhost", "localhost");
             
     let db_port_as_string = check_defaulted_string (toml_database.db_port, "DB port", "5432", "5432");
-    let db_port: usize = db_port_as_string.parse().unwrap_or_else(|_| 5432);
+    let db_port: usize = db_port_as_string.parse().unwrap_or(5432);
 
     let db_name = check_defaulted_string (toml_database.db_name, "DB name", "geo", "geo");
 
+    // The password is taken from db_password if given, otherwise from the file named in 
+    // db_password_file, otherwise from a matching entry in the user's .pgpass file.
+
+    let db_password = match toml_database.db_password.filter(|p| !p.trim().is_empty()) {
+        Some(p) => p,
+        None => match toml_database.db_password_file.filter(|f| !f.trim().is_empty()) {
+            Some(f) => read_password_file(Path::new(&f))?,
+            None => pgpass_file
+                .and_then(read_pgpass_file)
+                .and_then(|c| find_pgpass_password(&c, &db_host, db_port, &db_name, &db_user))
+                .ok_or_else(|| AppError::ConfigurationError("Essential configuration value missing or misspelt.".to_string(),
+                    "Cannot find a value for database user password (db_password, db_password_file or a .pgpass entry).".to_string()))?,
+        },
+    };
+
     Ok(DBPars {
         db_host,
         db_user,
@@ -169,32 +766,110 @@ fn verify_db_parameters(toml_database: TomlDBPars) -> Result<DBPars, AppError> {
 }
 
 
-pub fn fetch_db_name() -> Result<String, AppError> {
-    let db_pars = match DB_PARS.get() {
-         Some(dbp) => dbp,
-         None => {
-            return Result::Err(AppError::MissingDBParameters());
-        },
-    };
-    Ok(db_pars.db_name.clone())
+fn read_password_file(password_file: &Path) -> Result<String, AppError> {
+
+    // On unix the file must not be accessible to the group or to others.
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(password_file)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, password_file.to_owned()))?
+            .permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(AppError::ConfigurationError("Database password file is readable by other users.".to_string(),
+                format!("Permissions of {:?} should be restricted to its owner (e.g. chmod 600).", password_file)));
+        }
+    }
+
+    let contents = fs::read_to_string(password_file)
+        .map_err(|e| AppError::IoReadErrorWithPath(e, password_file.to_owned()))?;
+    let password = contents.lines().next().unwrap_or_default().trim_end().to_string();
+    if password.is_empty() {
+        return Err(AppError::ConfigurationError("Database password file is empty.".to_string(),
+            format!("No password found in {:?}.", password_file)));
+    }
+    Ok(password)
 }
 
 
-pub fn fetch_db_conn_string(db_name: &String) -> Result<String, AppError> {
-    let db_pars = match DB_PARS.get() {
-         Some(dbp) => dbp,
-         None => {
-            return Result::Err(AppError::MissingDBParameters());
-        },
-    };
-    Ok(format!("postgres://{}:{}@{}:{}/{}", 
-    db_pars.db_user, db_pars.db_password, db_pars.db_host, db_pars.db_port, db_name))
+fn pgpass_path() -> Option<PathBuf> {
+
+    // As for libpq: PGPASSFILE if set, otherwise ~/.pgpass (%APPDATA%\postgresql\pgpass.conf on Windows).
+
+    if let Some(p) = env::var_os("PGPASSFILE").filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(p));
+    }
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(|a| PathBuf::from(a).join("postgresql").join("pgpass.conf"))
+    }
+    else {
+        env::var_os("HOME").map(|h| PathBuf::from(h).join(".pgpass"))
+    }
+}
+
+
+fn read_pgpass_file(pgpass_file: &Path) -> Option<String> {
+
+    // Like libpq, a .pgpass file that others can read is ignored.
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(pgpass_file).ok()?.permissions().mode();
+        if mode & 0o077 != 0 {
+            println!("Password file {:?} has group or world access and has been ignored.", pgpass_file);
+            return None;
+        }
+    }
+    fs::read_to_string(pgpass_file).ok()
+}
+
+
+fn find_pgpass_password(contents: &str, host: &str, port: usize, db_name: &str, user: &str) -> Option<String> {
+
+    // Each line is 'host:port:database:username:password'. The first four fields may be '*', 
+    // and ':' or '\' within a field are escaped with '\'. The first matching line is used.
+
+    let port = port.to_string();
+    let wanted = [host, port.as_str(), db_name, user];
+
+    for line in contents.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields: Vec<String> = vec![String::new()];
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => if let Some(n) = chars.next() { fields.last_mut()?.push(n) },
+                ':' if fields.len() < 5 => fields.push(String::new()),
+                _ => fields.last_mut()?.push(c),
+            }
+        }
+        if fields.len() != 5 {
+            continue;
+        }
+        if fields.iter().zip(wanted).all(|(f, w)| f == "*" || f == w) {
+            return fields.pop();
+        }
+    }
+    None
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    // Config strings are read without the environment, and with a .pgpass file that does not exist.
+
+    const NO_PGPASS: &str = "/nonexistent/zipper_test/.pgpass";
+
+    fn read_test_config(config_string: &str) -> Result<Config, AppError> {
+        read_config_vars(config_string, |_| None, Some(Path::new(NO_PGPASS)))
+    }
     
     // Ensure the parameters are being correctly extracted from the config file string
     
@@ -217,7 +892,7 @@ db_port="5433"
 db_name="geo"
 "#;
         let config_string = config.to_string();
-        let res = populate_config_vars(&config_string).unwrap();
+        let res = read_test_config(&config_string).unwrap();
         assert_eq!(res.folders.mdr_zipped, PathBuf::from("E:\\MDR\\Zipped source files"));
         assert_eq!(res.folders.mdr_unzipped, PathBuf::from("E:\\MDR\\MDR Source files"));
         assert_eq!(res.folders.fdr_zipped, PathBuf::from("E:\\MDR source data\\UMLS\\zipped data"));
@@ -251,7 +926,7 @@ db_port="5433"
 db_name="geo"
 "#;
         let config_string = config.to_string();
-        let _res = populate_config_vars(&config_string).unwrap();
+        let _res = read_test_config(&config_string).unwrap();
     }
 
     #[test]
@@ -273,9 +948,374 @@ db_port="5433"
 db_name="geo"
 "#;
         let config_string = config.to_string();
-        let _res = populate_config_vars(&config_string).unwrap();
+        let _res = read_test_config(&config_string).unwrap();
+    }
+
+
+    #[test]
+    fn check_later_config_layers_override_earlier() {
+
+        let machine = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+db_name="mon"
+"#;
+        let job = r#"
+[folders]
+mdr_zipped="/backup/zipped"
+
+[database]
+db_name="mdr"
+"#;
+        let mut merged = machine.parse::<Table>().unwrap();
+        merge_tables(&mut merged, job.parse::<Table>().unwrap());
+        let config_string = toml::to_string(&merged).unwrap();
+
+        let res = read_test_config(&config_string).unwrap();
+        assert_eq!(res.folders.mdr_zipped, PathBuf::from("/backup/zipped"));
+        assert_eq!(res.folders.mdr_unzipped, PathBuf::from("/data/unzipped"));
+        assert_eq!(res.db_pars.db_user, "user_name");
+        assert_eq!(res.db_pars.db_name, "mdr");
+    }
+
+    #[test]
+    fn check_config_search_order() {
+
+        let cli = vec![PathBuf::from("job.toml")];
+        let env_value = Some(OsString::from("machine.toml"));
+        let res = find_config_files(&cli, env_value.clone(), None).unwrap();
+        assert_eq!(res, cli);
+
+        let res = find_config_files(&[], env_value, None).unwrap();
+        assert_eq!(res, vec![PathBuf::from("machine.toml")]);
+    }
+
+    #[test]
+    fn check_base_file_is_overridden() {
+
+        let folder = env::temp_dir().join(format!("zipper_config_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("machine.toml"), "[folders]\nmdr_zipped=\"/data/zipped\"\nmdr_unzipped=\"/data/unzipped\"\n").unwrap();
+        fs::write(folder.join("job.toml"), "base=\"machine.toml\"\n[folders]\nmdr_unzipped=\"/job/unzipped\"\n").unwrap();
+
+        let mut visited = Vec::new();
+        let res = load_config_layer(&folder.join("job.toml"), &mut visited);
+        fs::remove_dir_all(&folder).unwrap();
+
+        let folders = res.unwrap()["folders"].as_table().unwrap().clone();
+        assert_eq!(folders["mdr_zipped"].as_str(), Some("/data/zipped"));
+        assert_eq!(folders["mdr_unzipped"].as_str(), Some("/job/unzipped"));
+    }
+
+    #[test]
+    fn check_env_values_override_config() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+"#;
+        let env_vars: std::collections::HashMap<&str, &str> = [("ZIPPER_DB_PASSWORD", "secret"), 
+                ("ZIPPER_MDR_ZIPPED", "/backup/zipped"), ("ZIPPER_DB_NAME", ""), ("DB_HOST", "remote")].into();
+        let mut toml_config = toml::from_str::<TomlConfig>(config).unwrap();
+        apply_env_overrides(&mut toml_config, |k| env_vars.get(k).map(|v| v.to_string()));
+
+        let db_pars = verify_db_parameters(toml_config.database.unwrap(), Some(Path::new(NO_PGPASS))).unwrap();
+        assert_eq!(db_pars.db_password, "secret");
+        assert_eq!(db_pars.db_name, "geo");
+        assert_eq!(db_pars.db_host, "localhost");
+        let folders = verify_file_parameters(toml_config.folders.unwrap()).unwrap();
+        assert_eq!(folders.mdr_zipped, PathBuf::from("/backup/zipped"));
+        assert_eq!(folders.mdr_unzipped, PathBuf::from("/data/unzipped"));
+    }
+
+    #[test]
+    fn check_pgpass_entries_matched() {
+
+        let pgpass = "# comment\n\
+                      otherhost:5432:geo:user_name:wrong\n\
+                      localhost:5432:*:user_name:pass\\:word\n\
+                      *:*:*:*:fallback\n";
+        assert_eq!(find_pgpass_password(pgpass, "localhost", 5432, "mdr", "user_name"), Some("pass:word".to_string()));
+        assert_eq!(find_pgpass_password(pgpass, "localhost", 5433, "mdr", "user_name"), Some("fallback".to_string()));
+        assert_eq!(find_pgpass_password("localhost:5432:geo:user_name:pw", "localhost", 5432, "mdr", "user_name"), None);
+    }
+
+    #[test]
+    fn check_password_not_shown_in_debug_output() {
+        let db_pars = DBPars { db_host: "localhost".to_string(), db_user: "user_name".to_string(), 
+                      db_password: "secret".to_string(), db_port: 5432, db_name: "geo".to_string() };
+        assert!(!format!("{:?}", db_pars).contains("secret"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_password_file_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let pw_file = env::temp_dir().join(format!("zipper_pw_test_{}", std::process::id()));
+        fs::write(&pw_file, "secret\n").unwrap();
+        fs::set_permissions(&pw_file, fs::Permissions::from_mode(0o644)).unwrap();
+        let open_res = read_password_file(&pw_file);
+        fs::set_permissions(&pw_file, fs::Permissions::from_mode(0o600)).unwrap();
+        let private_res = read_password_file(&pw_file);
+        fs::remove_file(&pw_file).unwrap();
+
+        assert!(open_res.is_err());
+        assert_eq!(private_res.unwrap(), "secret");
+    }
+
+    #[test]
+    fn check_jobs_read_with_defaults() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[[jobs]]
+name="umls"
+source="/data/umls"
+destination="/archives/umls"
+compression="zstd"
+compression_level=10
+exclude=["*.tmp"]
+keep=3
+
+[[jobs]]
+name="umls-restore"
+operation="unzip"
+source="/archives/umls"
+destination="/restore/umls"
+"#;
+        let res = read_test_config(config).unwrap();
+        assert_eq!(res.jobs.len(), 2);
+        assert_eq!(res.jobs[0].operation, Operation::Zip);
+        assert_eq!(res.jobs[0].compression, CompressionMethod::Zstd);
+        assert_eq!(res.jobs[0].compression_level, Some(10));
+        assert_eq!(res.jobs[0].exclude, vec!["*.tmp"]);
+        assert_eq!(res.jobs[0].keep, Some(3));
+        assert_eq!(res.jobs[1].operation, Operation::Unzip);
+        assert_eq!(res.jobs[1].compression, CompressionMethod::Deflated);
+        assert!(res.jobs[1].include.is_empty());
+        assert_eq!(res.jobs[1].keep, None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_job_with_invalid_compression_panics() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[[jobs]]
+name="umls"
+source="/data/umls"
+destination="/archives/umls"
+compression="rar"
+"#;
+        let _res = read_test_config(config).unwrap();
+    }
+
+    #[test]
+    fn check_schedules_read_and_commands_split() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[[schedules]]
+name="nightly"
+cron="30 2 * * *"
+command="zip -m --changed-only"
+
+[[schedules]]
+name="umls"
+cron="@weekly"
+command='zip -f --fu "/data/UMLS files" --fz /archives/umls.zip'
+"#;
+        let res = read_test_config(config).unwrap();
+        assert_eq!(res.schedules.len(), 2);
+        assert_eq!(res.schedules[0].cron.expression, "30 2 * * *");
+        assert_eq!(res.schedules[0].command, vec!["zip", "-m", "--changed-only"]);
+        assert_eq!(res.schedules[1].command, vec!["zip", "-f", "--fu", "/data/UMLS files", "--fz", "/archives/umls.zip"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_schedule_with_invalid_cron_panics() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[[schedules]]
+name="nightly"
+cron="30 25 * * *"
+command="zip -m"
+"#;
+        let _res = read_test_config(config).unwrap();
+    }
+
+    #[test]
+    fn check_watch_section_read_with_defaults() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[watch]
+sources="100120, ctg"
+"#;
+        let res = read_test_config(config).unwrap();
+        assert_eq!(res.watch.sources, "100120, ctg");
+        assert_eq!(res.watch.quiet_period_secs, 900);
+        assert_eq!(res.lock_scope, LockScope::Source);
+
+        let config = format!("{}\n[locking]\nscope=\"global\"\n", config);
+        let res = read_test_config(&config).unwrap();
+        assert_eq!(res.lock_scope, LockScope::Global);
     }
 
+    #[test]
+    fn check_logging_format_read() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+"#;
+        let res = read_test_config(config).unwrap();
+        assert_eq!(res.logging.format, LogFormat::Pattern);
+
+        let res = read_test_config(&format!("{}\n[logging]\nformat=\"JSON\"\n", config)).unwrap();
+        assert_eq!(res.logging.format, LogFormat::Json);
+
+        assert!(read_test_config(&format!("{}\n[logging]\nformat=\"xml\"\n", config)).is_err());
+    }
+
+    #[test]
+    fn check_logging_level_mode_and_rotation_read() {
+
+        let config = r#"
+[folders]
+mdr_zipped="/data/zipped"
+mdr_unzipped="/data/unzipped"
+log_folder_path="/var/log/zipper"
+
+[database]
+db_user="user_name"
+db_password="password"
+"#;
+        let res = read_test_config(config).unwrap();
+        assert_eq!(res.logging.level, LevelFilter::Info);
+        assert_eq!(res.logging.mode, LogMode::PerRun);
+        assert_eq!(res.logging.max_files, None);
+
+        let logging = "[logging]\nlevel=\"debug\"\nmode=\"daily\"\nrotate_size_mb=50\nrotate_age_hours=24\nmax_files=30\n";
+        let res = read_test_config(&format!("{}\n{}", config, logging)).unwrap();
+        assert_eq!(res.logging.level, LevelFilter::Debug);
+        assert_eq!(res.logging.mode, LogMode::Daily);
+        assert_eq!(res.logging.rotate_size_mb, Some(50));
+        assert_eq!(res.logging.rotate_age_hours, Some(24));
+        assert_eq!(res.logging.max_files, Some(30));
+
+        assert!(read_test_config(&format!("{}\n[logging]\nlevel=\"loud\"\n", config)).is_err());
+        assert!(read_test_config(&format!("{}\n[logging]\nmax_files=0\n", config)).is_err());
+    }
+
+    #[test]
+    fn check_storage_section_read() {
+
+        let toml = |s: &str| toml::from_str::<TomlStoragePars>(s).unwrap();
+        let no_env = |_: &str| None;
+
+        let res = verify_storage_parameters(None, no_env).unwrap();
+        assert!(res.is_default() && res.keep_local);
+
+        let res = verify_storage_parameters(Some(toml("kind=\"local\"\nroot=\"/mnt/backup\"\nkeep_local=false")), no_env).unwrap();
+        assert!(matches!(res.backend, StorageBackend::Local(Some(ref r)) if r == Path::new("/mnt/backup")));
+        assert!(!res.keep_local);
+
+        let s3 = "kind=\"s3\"\nendpoint=\"http://localhost:9000/\"\nbucket=\"mdr\"\nprefix=\"/zipped/\"";
+        assert!(verify_storage_parameters(Some(toml(s3)), no_env).is_err());
+        let env = |k: &str| match k {
+            "AWS_ACCESS_KEY_ID" => Some("minio".to_string()),
+            "ZIPPER_S3_SECRET_KEY" => Some("minio123".to_string()),
+            _ => None,
+        };
+        match verify_storage_parameters(Some(toml(s3)), env).unwrap().backend {
+            StorageBackend::S3(p) => {
+                assert_eq!(p.endpoint, "http://localhost:9000");
+                assert_eq!(p.prefix, "zipped");
+                assert_eq!(p.region, "us-east-1");
+                assert_eq!(p.access_key, "minio");
+                assert!(!format!("{:?}", p).contains("minio123"));
+            },
+            _ => panic!("S3 storage expected"),
+        }
+
+        let sftp = "kind=\"sftp\"\nhost=\"backup\"\nuser=\"zipper\"\nkey_file=\"~/.ssh/id_ed25519\"\nroot=\"/srv/mdr/\"";
+        assert!(verify_storage_parameters(Some(toml("kind=\"sftp\"\nhost=\"backup\"")), no_env).is_err());
+        let env = |k: &str| (k == "ZIPPER_SFTP_KEY_PASSPHRASE").then(|| "secret phrase".to_string());
+        match verify_storage_parameters(Some(toml(sftp)), env).unwrap().backend {
+            StorageBackend::Sftp(p) => {
+                assert_eq!((p.port, p.root.as_str()), (22, "/srv/mdr"));
+                assert_eq!(p.known_hosts, Path::new("~/.ssh/known_hosts"));
+                assert_eq!(p.key_passphrase.as_deref(), Some("secret phrase"));
+                assert!(!format!("{:?}", p).contains("secret phrase"));
+            },
+            _ => panic!("SFTP storage expected"),
+        }
+
+        assert!(verify_storage_parameters(Some(toml("kind=\"ftp\"")), no_env).is_err());
+    }
 
     #[test]
     fn check_db_defaults_are_supplied() {
@@ -293,7 +1333,7 @@ db_user="user_name"
 db_password="password"
 "#;
         let config_string = config.to_string();
-        let res = populate_config_vars(&config_string).unwrap();
+        let res = read_test_config(&config_string).unwrap();
         assert_eq!(res.db_pars.db_host, "localhost");
         assert_eq!(res.db_pars.db_user, "user_name");
         assert_eq!(res.db_pars.db_password, "password");
@@ -322,7 +1362,7 @@ db_name="geo"
 
 "#;
         let config_string = config.to_string();
-        let res = populate_config_vars(&config_string).unwrap();
+        let res = read_test_config(&config_string).unwrap();
 
         assert_eq!(res.db_pars.db_host, "localhost");
         assert_eq!(res.db_pars.db_user, "user_name");
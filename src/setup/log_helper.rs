@@ -4,56 +4,118 @@
  ***************************************************************************/
 
 use chrono::Local;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 use crate::err::AppError;
 use crate::setup::InitParams;
+use crate::setup::config_reader::LoggingPars;
+use crate::progress::ProgressAwareConsole;
 
-use log::{info, LevelFilter};
+use log::info;
+use log::LevelFilter;
 use log4rs::{
     append::{
+        Append,
         console::{ConsoleAppender, Target},
         file::FileAppender,
+        rolling_file::{LogFile, RollingFileAppender},
+        rolling_file::policy::compound::{CompoundPolicy, roll::fixed_window::FixedWindowRoller, trigger::Trigger},
     },
     config::{Appender, Config, Root},
-    encode::pattern::PatternEncoder,
+    encode::{json::JsonEncoder, pattern::PatternEncoder, Encode},
 };
 
-pub fn setup_log (log_folder: &PathBuf) -> Result<log4rs::Handle, AppError> {
-    let datetime_string = Local::now().format("%m-%d %H%M%S").to_string();
-    let log_file_name = format!("zipper log at {}.log", datetime_string);
-    let log_file_path = [log_folder, &PathBuf::from(log_file_name)].iter().collect();
-    config_log (&log_file_path)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Pattern,
+    Json,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogMode {
+    PerRun,
+    Daily,
+}
+
+const LOG_FILE_PREFIX: &str = "zipper log";
+
+// The number of rotated files kept for the current log file, if max_files is not set.
+
+const DEFAULT_ROTATED_FILES: u32 = 10;
+
+pub fn setup_log (log_folder: &Path, logging: &LoggingPars) -> Result<log4rs::Handle, AppError> {
+
+    // Each run has its own log file, or with mode = "daily" all the runs of a day append to a single file.
 
-fn config_log (log_file_path: &PathBuf) -> Result<log4rs::Handle, AppError> {
+    let log_file_name = match logging.mode {
+        LogMode::PerRun => format!("{} at {}.log", LOG_FILE_PREFIX, Local::now().format("%Y-%m-%d %H%M%S")),
+        LogMode::Daily => format!("{} {}.log", LOG_FILE_PREFIX, Local::now().format("%Y-%m-%d")),
+    };
+    let log_file_path = log_folder.join(log_file_name);
+    if let Some(max_files) = logging.max_files {
+        prune_log_files(log_folder, &log_file_path, max_files)?;
+    }
+    config_log (&log_file_path, logging)
+}
+
+
+fn config_log (log_file_path: &Path, logging: &LoggingPars) -> Result<log4rs::Handle, AppError> {
     
-    // Initially establish a pattern for each log line.
+    // Initially establish a pattern for each log line, or, with format = "json", a JSON object per line.
+    // The JSON lines include the level, module, and the context fields (source_id, source, job, 
+    // archive, file_count) set with a LogContext while the record was logged.
 
-    let log_pattern = "{d(%d/%m %H:%M:%S)}  {h({l})}  {({M}.{L}):>38.48}:  {m}\n";
+    let log_pattern = "{d(%Y-%m-%d %H:%M:%S)}  {h({l})}  {({M}.{L}):>38.48}:  {m}\n";
+    let encoder = || -> Box<dyn Encode> {
+        match logging.format {
+            LogFormat::Pattern => Box::new(PatternEncoder::new(log_pattern)),
+            LogFormat::Json => Box::new(JsonEncoder::new()),
+        }
+    };
 
     // Define a stderr logger, as one of the 'logging' sinks or 'appender's.
+    // It is wrapped so that any progress status line is kept below the records written.
 
-    let stderr = ConsoleAppender::builder().encoder(Box::new(PatternEncoder::new(log_pattern)))
-        .target(Target::Stderr).build();
+    let stderr = ProgressAwareConsole(ConsoleAppender::builder().encoder(encoder())
+        .target(Target::Stderr).build());
 
     // Define a second logging sink or 'appender' - to a log file (provided path will place it in the current data folder).
+    // If rotation is configured the file is rolled over to '{name}.1.log' (and earlier rolls moved up) 
+    // when it exceeds the size, or has been written to for the time, given.
 
-    let logfile = FileAppender::builder().encoder(Box::new(PatternEncoder::new(log_pattern)))
+    let logfile: Box<dyn Append> = if logging.rotate_size_mb.is_some() || logging.rotate_age_hours.is_some() {
+        let trigger = RotationTrigger {
+            max_bytes: logging.rotate_size_mb.map(|mb| mb * 1024 * 1024),
+            max_age: logging.rotate_age_hours.map(|h| Duration::from_secs(h * 3600)),
+            started: Mutex::new(Instant::now()),
+        };
+        let roll_pattern = log_file_path.with_extension("{}.log");
+        let roll_count = logging.max_files.map(|n| n as u32).unwrap_or(DEFAULT_ROTATED_FILES);
+        let roller = FixedWindowRoller::builder().build(&roll_pattern.to_string_lossy(), roll_count)
+            .map_err(|e| AppError::LogSetupError("Error when creating the log roller".to_string(), e.to_string()))?;
+        Box::new(RollingFileAppender::builder().encoder(encoder())
+            .build(log_file_path, Box::new(CompoundPolicy::new(Box::new(trigger), Box::new(roller))))
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, log_file_path.to_owned()))?)
+    }
+    else {
+        Box::new(FileAppender::builder().encoder(encoder())
             .build(log_file_path)
-            .map_err(|e| AppError::IoWriteErrorWithPath(e, log_file_path.to_owned()))?;
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, log_file_path.to_owned()))?)
+    };
 
     // Configure and build log4rs instance, using the two appenders described above
 
     let config = Config::builder()
         .appender(Appender::builder()
-                .build("logfile", Box::new(logfile)),)
+                .build("logfile", logfile),)
         .appender(Appender::builder()
                 .build("stderr", Box::new(stderr)),)
         .build(Root::builder()
                 .appender("logfile")
                 .appender("stderr")
-                .build(LevelFilter::Info),
+                .build(logging.level),
         )
         .map_err(|e| AppError::LogSetupError("Error when creating log4rs configuration".to_string(), e.to_string()))?;
 
@@ -62,6 +124,107 @@ fn config_log (log_file_path: &PathBuf) -> Result<log4rs::Handle, AppError> {
 }
 
 
+// Rolls the log file once it exceeds a size, or once it has been written to for a time (measured 
+// from when the program started, or the last roll) - whichever comes first.
+
+#[derive(Debug)]
+struct RotationTrigger {
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    started: Mutex<Instant>,
+}
+
+impl Trigger for RotationTrigger {
+
+    fn trigger(&self, file: &LogFile) -> anyhow::Result<bool> {
+        let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+        let too_large = self.max_bytes.is_some_and(|b| file.len_estimate() > b);
+        let too_old = self.max_age.is_some_and(|a| started.elapsed() >= a);
+        if too_large || too_old {
+            *started = Instant::now();
+        }
+        Ok(too_large || too_old)
+    }
+
+    fn is_pre_process(&self) -> bool {
+        false
+    }
+}
+
+
+fn prune_log_files(log_folder: &Path, current_log: &Path, max_files: usize) -> Result<(), AppError> {
+
+    // Keeps the most recent log files (including rotated files), so that with the current 
+    // log there are at most max_files. Only files named 'zipper log ...' are considered.
+
+    let entries = match fs::read_dir(log_folder) {
+        Ok(e) => e,
+        Err(_) => return Ok(()),  // the folder is created with the first log
+    };
+    let mut logs: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|e| Some(e.ok()?.path()))
+        .filter(|p| p.is_file() && p != current_log)
+        .filter(|p| p.file_name().and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with(LOG_FILE_PREFIX) && n.ends_with(".log")))
+        .map(|p| (fs::metadata(&p).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH), p))
+        .collect();
+    logs.sort();
+
+    let prune_num = (logs.len() + 1).saturating_sub(max_files);
+    for (_, p) in logs.into_iter().take(prune_num) {
+        fs::remove_file(&p)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, p.to_owned()))?;
+    }
+    Ok(())
+}
+
+
+pub fn shift_level(level: LevelFilter, verbosity: i8) -> LevelFilter {
+
+    // Each -v raises the level by one step (to debug, then trace), each -q lowers it (to warn,
+    // then error). Errors are always logged.
+
+    let levels = [LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
+    let current = levels.iter().position(|l| *l == level).unwrap_or(2) as i8;
+    levels[(current + verbosity).clamp(0, 4) as usize]
+}
+
+
+// Context attached to each record logged while the LogContext exists, rather than being
+// interpolated into the messages. It is held per thread, so should not be kept across an await.
+// The keys are removed when the LogContext is dropped.
+
+#[derive(Default)]
+pub struct LogContext {
+    keys: Vec<&'static str>,
+}
+
+impl LogContext {
+
+    pub fn new() -> LogContext {
+        LogContext::default()
+    }
+
+    pub fn with(mut self, key: &'static str, value: impl ToString) -> LogContext {
+        log_mdc::insert(key, value.to_string());
+        self.keys.push(key);
+        self
+    }
+}
+
+pub fn archive_context(archive_path: &Path, file_count: usize) -> LogContext {
+    let archive_name = archive_path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    LogContext::new().with("archive", archive_name).with("file_count", file_count)
+}
+
+impl Drop for LogContext {
+    fn drop(&mut self) {
+        for key in &self.keys {
+            log_mdc::remove(*key);
+        }
+    }
+}
+
 
 pub fn log_startup_params (ip : &InitParams) {
     
@@ -76,11 +239,82 @@ pub fn log_startup_params (ip : &InitParams) {
     info!("fdr zipped folder: {}", ip.fdr_zipped.display());
     info!("fdr unzipped folder: {}", ip.fdr_unzipped.display());
     info!("log folder: {}", ip.log_folder_path.display());
-    info!("do zip: {}", ip.flags.do_zip);
-    info!("do_unzip: {}", ip.flags.do_unzip);
+    info!("log format: {:?}, level: {}, mode: {:?}", ip.logging.format, ip.logging.level, ip.logging.mode);
+    if !ip.storage.is_default() {
+        info!("archive storage: {:?} (keep local copies: {})", ip.storage.backend, ip.storage.keep_local);
+    }
+    info!("operation: {}", ip.flags.operation.name());
     info!("all_mdr: {}", ip.flags.all_mdr);
     info!("use_folder: {}", ip.flags.use_folder);
+    info!("changed_only: {}", ip.flags.changed_only);
+    if ip.flags.operation == crate::setup::cli_reader::Operation::Prune {
+        info!("sets to keep: {} (dry run: {})", ip.keep, ip.flags.dry_run);
+    }
+    if !ip.jobs.is_empty() {
+        info!("jobs to run: {}", ip.jobs.iter().map(|j| j.name.as_str()).collect::<Vec<&str>>().join(", "));
+    }
+    if !ip.schedules.is_empty() {
+        info!("schedules: {}", ip.schedules.iter().map(|s| s.name.as_str()).collect::<Vec<&str>>().join(", "));
+    }
+    if ip.flags.operation == crate::setup::cli_reader::Operation::Watch {
+        info!("quiet period before zipping: {} seconds", ip.quiet_period_secs);
+    }
+    if let Some(report_path) = &ip.report_path {
+        info!("run report: {}", report_path.display());
+    }
+    if !ip.file_list.is_empty() {
+        info!("files to extract: {}", ip.file_list.join(", "));
+    }
     info!("");
     info!("************************************");
     info!("");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_log_context_added_and_removed() {
+        {
+            let _ctx = LogContext::new().with("source_id", 100120).with("source", "ctg");
+            let _archive = archive_context(Path::new("/zipped/ctg/ctg 250101 1 to 5000.zip"), 5000);
+            assert_eq!(log_mdc::get("source_id", |v| v.map(|s| s.to_string())), Some("100120".to_string()));
+            assert_eq!(log_mdc::get("archive", |v| v.map(|s| s.to_string())), Some("ctg 250101 1 to 5000.zip".to_string()));
+            assert_eq!(log_mdc::get("file_count", |v| v.map(|s| s.to_string())), Some("5000".to_string()));
+        }
+        assert_eq!(log_mdc::get("source", |v| v.map(|s| s.to_string())), None);
+        assert_eq!(log_mdc::get("archive", |v| v.map(|s| s.to_string())), None);
+    }
+
+    #[test]
+    fn check_level_shifted_by_verbosity() {
+        assert_eq!(shift_level(LevelFilter::Info, 0), LevelFilter::Info);
+        assert_eq!(shift_level(LevelFilter::Info, 1), LevelFilter::Debug);
+        assert_eq!(shift_level(LevelFilter::Info, 5), LevelFilter::Trace);
+        assert_eq!(shift_level(LevelFilter::Warn, -1), LevelFilter::Error);
+        assert_eq!(shift_level(LevelFilter::Info, -4), LevelFilter::Error);
+    }
+
+    #[test]
+    fn check_oldest_log_files_pruned() {
+        let folder = std::env::temp_dir().join(format!("zipper_log_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let names = ["zipper log at 2026-10-15 020000.log", "zipper log at 2026-10-16 020000.log", 
+                     "zipper log 2026-10-17.1.log", "notes.log"];
+        for (i, n) in names.iter().enumerate() {
+            let f = fs::File::create(folder.join(n)).unwrap();
+            f.set_modified(SystemTime::now() - Duration::from_secs(3600 * (10 - i as u64))).unwrap();
+        }
+        let current = folder.join("zipper log 2026-10-17.log");
+        fs::write(&current, "").unwrap();
+
+        prune_log_files(&folder, &current, 3).unwrap();
+        assert!(!folder.join(names[0]).exists());
+        assert!(folder.join(names[1]).exists());
+        assert!(folder.join(names[2]).exists());
+        assert!(folder.join(names[3]).exists());
+        assert!(current.exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
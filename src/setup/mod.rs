@@ -1,6 +1,8 @@
 pub mod config_reader;
 pub mod log_helper;
 pub mod cli_reader;
+pub mod source_selector;
+pub mod path_checker;
 
 use crate::err::AppError;
 use sqlx::postgres::{PgPoolOptions, PgConnectOptions, PgPool};
@@ -8,10 +10,12 @@ use std::time::Duration;
 use sqlx::ConnectOptions;
 use std::path::PathBuf;
 
-use std::fs;
-use config_reader::Config;
-use cli_reader::{CliPars, Flags};
-use std::sync::OnceLock;
+use std::ffi::OsString;
+use config_reader::{Config, DBPars, JobPars, LoggingPars, SchedulePars, StoragePars};
+use cli_reader::{CliPars, Flags, Operation};
+use source_selector::SourceSelection;
+use crate::locks::LockScope;
+use crate::progress::{self, ProgressMode};
 
 pub struct InitParams {
     pub mdr_zipped: PathBuf,
@@ -20,28 +24,66 @@ pub struct InitParams {
     pub fdr_unzipped: PathBuf,
     pub log_folder_path: PathBuf,
     pub source_list: Vec<i32>,
+    pub source_selection: SourceSelection,
+    pub file_list: Vec<String>,
+    pub keep: usize,
+    pub jobs: Vec<JobPars>,
+    pub schedules: Vec<SchedulePars>,
+    pub quiet_period_secs: u64,
+    pub lock_scope: LockScope,
+    pub report_path: Option<PathBuf>,
+    pub logging: LoggingPars,
+    pub db_pars: DBPars,
+    pub storage: StoragePars,
+    pub progress: ProgressMode,
     pub flags: Flags,
 }
 
-pub static LOG_RUNNING: OnceLock<bool> = OnceLock::new();
+pub fn get_params(cli_pars: CliPars, config_string: &str) -> Result<InitParams, AppError> {
 
-pub fn get_params(cli_pars: CliPars, config_string: &String) -> Result<InitParams, AppError> {
-
-    let config_file: Config = config_reader::populate_config_vars(&config_string)?; 
+    let config_file: Config = config_reader::populate_config_vars(config_string)?; 
     let folder_pars = config_file.folders;  // guaranteed to exist
     let empty_pb = PathBuf::from("");
   
     let mdr_zipped = folder_pars.mdr_zipped;
     let mdr_unzipped = folder_pars.mdr_unzipped;
 
-    let mut source_list = Vec::new();
-    if cli_pars.source_list.len() > 0
+    // The full selection (ids, ranges, names and exclusions) is checked against the 
+    // source catalogue once the database is available. source_list holds the ids listed explicitly.
+    // With watch, the sources in the [watch] section are used if none are given on the command line.
+
+    let is_watch = cli_pars.flags.operation == Operation::Watch;
+
+    // A daemon or watch carries out many runs, so a run report is requested in each scheduled command instead.
+
+    if (is_watch || cli_pars.flags.operation == Operation::Daemon) && (cli_pars.report_path.is_some() || cli_pars.flags.json_output) {
+        let msg = "A run report (--report, --output json) cannot be requested for daemon or watch!".to_string();
+        return Result::Err(AppError::InconsistentProgramParameter(msg));
+    }
+    let source_string = if is_watch && !cli_pars.flags.all_mdr && cli_pars.source_list.trim().is_empty() {
+        config_file.watch.sources.clone()
+    } else {
+        cli_pars.source_list.clone()
+    };
+    let source_selection = source_selector::parse_source_list(&source_string)?;
+    if is_watch && !cli_pars.flags.all_mdr && source_selection.is_empty() {
+        return Result::Err(AppError::MissingProgramParameter("sources to watch (-s, -m, or sources in the [watch] section)".to_string()));
+    }
+    let quiet_period_secs = cli_pars.quiet_period.unwrap_or(config_file.watch.quiet_period_secs);
+
+    // The configured log level is raised by each -v, and lowered by each -q.
+
+    let mut logging = config_file.logging;
+    logging.level = log_helper::shift_level(logging.level, cli_pars.verbosity);
+    let source_list = source_selection.listed_ids();
+
+    let mut file_list = Vec::new();
+    if !cli_pars.file_list.is_empty()
     {
-        let ids_as_strs: Vec<&str> = cli_pars.source_list.split(',').collect(); 
-        for sid in ids_as_strs {
-            match sid.trim().parse::<i32>() {
-                Ok(id) => source_list.push(id),
-                Err(_) => {}   // do nothing with this id, whatever the error
+        for f in cli_pars.file_list.split(',') {
+            let f = f.trim();
+            if !f.is_empty() {
+                file_list.push(f.to_string());
             }
         }
     }
@@ -58,7 +100,7 @@ pub fn get_params(cli_pars: CliPars, config_string: &String) -> Result<InitParam
         fdr_unzipped = folder_pars.fdr_unzipped;
     }
 
-    if cli_pars.flags.all_mdr || source_list.len() > 0 {
+    if cli_pars.flags.all_mdr || !source_selection.is_empty() {
 
         // if -a or -s flag check mdr zipping  folders exist.
        
@@ -79,80 +121,138 @@ pub fn get_params(cli_pars: CliPars, config_string: &String) -> Result<InitParam
         {
             return Result::Err(AppError::MissingProgramParameter("fdr_zipped".to_string()));
         }
-        if fdr_unzipped == empty_pb
+        if fdr_unzipped == empty_pb && cli_pars.flags.operation != Operation::Verify
         {
             return Result::Err(AppError::MissingProgramParameter("fdr_unzipped".to_string()));
         }
     }
 
-    if cli_pars.flags.use_folder && (cli_pars.flags.all_mdr || source_list.len() > 0) {   
-        let msg = "Both folder and mdr processing requested at the same time!".to_string();
-        return Result::Err(AppError::InconsistentProgramParameter(msg));
-    }
- 
-    if !cli_pars.flags.all_mdr && !cli_pars.flags.use_folder && source_list.len() == 0 {   
-        let msg = "No source type has been identified for zipping or unzipping!".to_string();
+    // Combinations of arguments are checked when the command line is read. Only the 
+    // restriction of selective extraction to a single source depends on the parsed list.
+
+    if !file_list.is_empty() && cli_pars.flags.do_unzip && !source_selection.is_single_source() {
+        let msg = "Extraction of selected files requires an unzip of a single source!".to_string();
         return Result::Err(AppError::InconsistentProgramParameter(msg));
     }
 
-    // if logging folder does not exist create it
+    // With run, the named jobs (or all of them) are taken from the configuration, in the order given.
 
-    let mut log_folder = folder_pars.log_folder_path;
-    if log_folder == empty_pb {
-        log_folder = PathBuf::from("E:\\MDR\\Zipping\\logs");
-    }
-    if !folder_exists (&log_folder) { 
-        fs::create_dir_all(&log_folder)?;
-    }
+    let jobs = if cli_pars.flags.operation == Operation::Run {
+        select_jobs(config_file.jobs, &cli_pars.job_names, cli_pars.flags.all_jobs)?
+    } else {
+        Vec::new()
+    };
+
+    let schedules = if cli_pars.flags.operation == Operation::Daemon {
+        if config_file.schedules.is_empty() {
+            return Result::Err(AppError::MissingProgramParameter("[[schedules]] entries in the configuration".to_string()));
+        }
+        config_file.schedules
+    } else {
+        Vec::new()
+    };
+
+    // Progress is shown as a status line on an interactive terminal, otherwise logged periodically.
+
+    let progress = if cli_pars.flags.test_run { ProgressMode::Off } else { progress::default_mode() };
+
+    // Paths are resolved, and the folders needed checked (and the log folder created), 
+    // by path_checker::check_paths once the parameters are complete.
 
-    // For execution flags read from the environment variables
-       
     Ok(InitParams {
-        mdr_zipped: mdr_zipped,
-        mdr_unzipped: mdr_unzipped,
-        fdr_zipped: fdr_zipped,
-        fdr_unzipped: fdr_unzipped,
-        log_folder_path: log_folder,
-        source_list: source_list,
+        mdr_zipped,
+        mdr_unzipped,
+        fdr_zipped,
+        fdr_unzipped,
+        log_folder_path: folder_pars.log_folder_path,
+        source_list,
+        source_selection,
+        file_list,
+        keep: cli_pars.keep,
+        jobs,
+        schedules,
+        quiet_period_secs,
+        lock_scope: config_file.lock_scope,
+        report_path: cli_pars.report_path,
+        logging,
+        db_pars: config_file.db_pars,
+        storage: config_file.storage,
+        progress,
         flags: cli_pars.flags,
     })
 
 }
 
-fn folder_exists(folder_name: &PathBuf) -> bool {
-    let xres = folder_name.try_exists();
-    let res = match xres {
-        Ok(true) => true,
-        Ok(false) => false, 
-        Err(_e) => false,           
-    };
-    res
+
+pub fn get_command_params(command: &[String], config_string: &str) -> Result<InitParams, AppError> {
+
+    // Used by the daemon and watch modes. The command is read as if it had been given on 
+    // the command line (with the program name first), with the configuration already read.
+
+    let args: Vec<OsString> = std::iter::once("zipper".to_string())
+        .chain(command.iter().cloned())
+        .map(OsString::from)
+        .collect();
+    let cli_pars = cli_reader::fetch_valid_arguments(args)?;
+    if cli_pars.flags.operation == Operation::Daemon || cli_pars.flags.operation == Operation::Watch {
+        let msg = format!("The command '{}' cannot itself start a daemon or watch!", command.join(" "));
+        return Result::Err(AppError::InconsistentProgramParameter(msg));
+    }
+    let mut params = get_params(cli_pars, config_string)?;
+    path_checker::check_paths(&mut params)?;
+
+    // Scheduled runs may overlap, and are not watched, so their progress is only logged.
+
+    if params.progress == ProgressMode::Bar {
+        params.progress = ProgressMode::Log;
+    }
+    Ok(params)
+}
+
+
+fn select_jobs(config_jobs: Vec<JobPars>, job_names: &[String], all_jobs: bool) -> Result<Vec<JobPars>, AppError> {
+
+    if config_jobs.is_empty() {
+        return Result::Err(AppError::MissingProgramParameter("[[jobs]] entries in the configuration".to_string()));
+    }
+    if all_jobs {
+        return Ok(config_jobs);
+    }
+
+    let unknown: Vec<&str> = job_names.iter()
+        .filter(|n| !config_jobs.iter().any(|j| &j.name == *n))
+        .map(|n| n.as_str())
+        .collect();
+    if !unknown.is_empty() {
+        return Result::Err(AppError::ConfigurationError("Job(s) not found in the configuration.".to_string(),
+                    format!("Unknown job name(s): {}", unknown.join(", "))));
+    }
+    Ok(job_names.iter()
+        .filter_map(|n| config_jobs.iter().find(|j| &j.name == n).cloned())
+        .collect())
 }
+
         
-pub async fn get_db_pool() -> Result<PgPool, AppError> {  
+pub async fn get_db_pool(db_pars: &DBPars) -> Result<PgPool, AppError> {  
 
-    // Establish DB name and thence the connection string
-    // (done as two separate steps to allow for future development).
-    // Use the string to set up a connection options object and change 
-    // the time threshold for warnings. Set up a DB pool option and 
+    // Use the DB parameters to set up a connection options object,
+    // (the options are built directly, so no connection string with the password is ever
+    // formed), and change the time threshold for warnings. Set up a DB pool option and 
     // connect using the connection options object.
 
-    let db_name = match config_reader::fetch_db_name() {
-        Ok(n) => n,
-        Err(e) => return Err(e),
-    };
-
-    let db_conn_string = config_reader::fetch_db_conn_string(&db_name)?;  
-   
-    let mut opts: PgConnectOptions = db_conn_string.parse()
-                     .map_err(|e| AppError::DBPoolError("Problem with parsing conection string".to_string(), e))?;
+    let mut opts = PgConnectOptions::new()
+                     .host(&db_pars.db_host)
+                     .port(db_pars.db_port as u16)
+                     .username(&db_pars.db_user)
+                     .password(&db_pars.db_password)
+                     .database(&db_pars.db_name);
 
     opts = opts.log_slow_statements(log::LevelFilter::Warn, Duration::from_secs(3));
 
     PgPoolOptions::new()
         .max_connections(5) 
         .connect_with(opts).await
-        .map_err(|e| AppError::DBPoolError(format!("Problem with connecting to database {} and obtaining Pool", db_name), e))
+        .map_err(|e| AppError::DBPoolError(format!("Problem with connecting to database {} and obtaining Pool", db_pars.db_name), e))
 }
 
 
@@ -160,24 +260,21 @@ pub async fn get_db_pool() -> Result<PgPool, AppError> {
 pub fn establish_log(params: &InitParams) -> Result<(), AppError> {
 
     if !log_set_up() {  // can be called more than once in context of integration tests
-        log_helper::setup_log(&params.log_folder_path)?;
-        LOG_RUNNING.set(true).unwrap(); // should always work
-        log_helper::log_startup_params(&params);
+        log_helper::setup_log(&params.log_folder_path, &params.logging)?;
+        log_helper::log_startup_params(params);
     }
     Ok(())
 }
 
 pub fn log_set_up() -> bool {
-    match LOG_RUNNING.get() {
-        Some(_) => true,
-        None => false,
-    }
+
+    // The log is set up once per process. Until then no logger is set, and the level is 'off'.
+
+    log::max_level() != log::LevelFilter::Off
 }
 
 // Tests
 #[cfg(test)]
-
-
 mod tests {
     use super::*;
     use std::ffi::OsString;
@@ -206,7 +303,7 @@ db_name="mon"
 "#;
 
         let config_string = config.to_string();
-        config_reader::populate_config_vars(&config_string).unwrap();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
 
         let args : Vec<&str> = vec!["dummy target", "-z", "-m"];   // one of z, u and one of m, f, s esential
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
@@ -214,11 +311,11 @@ db_name="mon"
 
         let res = get_params(cli_pars, &config_string).unwrap();
 
-        assert_eq!(res.flags.all_mdr, true);
-        assert_eq!(res.flags.use_folder, false);
-        assert_eq!(res.flags.do_zip, true);
-        assert_eq!(res.flags.do_unzip, false);
-        assert_eq!(res.flags.test_run, false);
+        assert!(res.flags.all_mdr);
+        assert!(!res.flags.use_folder);
+        assert!(res.flags.do_zip);
+        assert!(!res.flags.do_unzip);
+        assert!(!res.flags.test_run);
         assert_eq!(res.mdr_zipped, PathBuf::from("E:\\MDR\\Zipped source files"));
         assert_eq!(res.mdr_unzipped, PathBuf::from("E:\\MDR\\MDR Source files"));
         assert_eq!(res.fdr_zipped, PathBuf::from("E:\\MDR source data\\UMLS\\zipped data"));
@@ -250,7 +347,7 @@ db_name="mon"
 "#;
 
         let config_string = config.to_string();
-        config_reader::populate_config_vars(&config_string).unwrap();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
 
         let args : Vec<&str> = vec!["dummy target", "-z", "-f", 
                              "--fz", "E:\\MDR source data\\funny\\zipped data", "--fu", "E:\\MDR source data\\funny\\data"];
@@ -259,11 +356,11 @@ db_name="mon"
 
         let res = get_params(cli_pars, &config_string).unwrap();
 
-        assert_eq!(res.flags.do_zip, true);
-        assert_eq!(res.flags.all_mdr, false);
-        assert_eq!(res.flags.use_folder, true);
-        assert_eq!(res.flags.do_unzip, false);
-        assert_eq!(res.flags.test_run, false);
+        assert!(res.flags.do_zip);
+        assert!(!res.flags.all_mdr);
+        assert!(res.flags.use_folder);
+        assert!(!res.flags.do_unzip);
+        assert!(!res.flags.test_run);
         assert_eq!(res.mdr_zipped, PathBuf::from("E:\\MDR\\Zipped source files"));
         assert_eq!(res.mdr_unzipped, PathBuf::from("E:\\MDR\\MDR Source files"));
         assert_eq!(res.fdr_zipped, PathBuf::from("E:\\MDR source data\\funny\\zipped data"));
@@ -292,7 +389,7 @@ db_name="mon"
 "#;
 
         let config_string = config.to_string();
-        config_reader::populate_config_vars(&config_string).unwrap();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
 
         let args : Vec<&str> = vec!["dummy target", "-z", "-s", "101, 102, 103, 104, 105"];
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
@@ -300,11 +397,11 @@ db_name="mon"
 
         let res = get_params(cli_pars, &config_string).unwrap();
 
-        assert_eq!(res.flags.do_zip, true);
-        assert_eq!(res.flags.all_mdr, false);
-        assert_eq!(res.flags.use_folder, false);
-        assert_eq!(res.flags.do_unzip, false);
-        assert_eq!(res.flags.test_run, false);
+        assert!(res.flags.do_zip);
+        assert!(!res.flags.all_mdr);
+        assert!(!res.flags.use_folder);
+        assert!(!res.flags.do_unzip);
+        assert!(!res.flags.test_run);
         assert_eq!(res.mdr_zipped, PathBuf::from("E:\\MDR\\Zipped source files"));
         assert_eq!(res.mdr_unzipped, PathBuf::from("E:\\MDR\\MDR Source files"));
         assert_eq!(res.fdr_zipped, PathBuf::from(""));
@@ -333,7 +430,7 @@ db_name="mon"
 "#;
 
         let config_string = config.to_string();
-        config_reader::populate_config_vars(&config_string).unwrap();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
 
         let args : Vec<&str> = vec!["dummy target", "-z", "-u"];
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
@@ -363,7 +460,7 @@ db_name="mon"
 "#;
 
         let config_string = config.to_string();
-        config_reader::populate_config_vars(&config_string).unwrap();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
 
         let args : Vec<&str> = vec!["dummy target", "-z", "-f", "-m"];
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
@@ -395,7 +492,7 @@ db_name="mon"
     "#;
     
             let config_string = config.to_string();
-            config_reader::populate_config_vars(&config_string).unwrap();
+            config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
     
             let args : Vec<&str> = vec!["dummy target", "-z", "-f", "-s", "101, 102, 103"];
             let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
@@ -406,6 +503,125 @@ db_name="mon"
         }
 
 
+    #[test]
+    fn check_x_value_interpreted_correctly() {
+
+    let config = r#"
+[folders]
+mdr_zipped="E:\\MDR\\Zipped source files"
+mdr_unzipped="E:\\MDR\\MDR Source files"
+log_folder_path="E:\\MDR\\Zipping\\logs"
+
+[database]
+db_host="localhost"
+db_user="user_name"
+db_password="password"
+db_port="5433"
+db_name="mon"
+"#;
+
+        let config_string = config.to_string();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
+
+        let args : Vec<&str> = vec!["dummy target", "-u", "-s", "100120", "-x", "NCT00000102, ,NCT00000104.json"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let cli_pars = cli_reader::fetch_valid_arguments(test_args).unwrap();
+
+        let res = get_params(cli_pars, &config_string).unwrap();
+
+        assert!(res.flags.do_unzip);
+        assert_eq!(res.source_list, vec![100120]);
+        assert_eq!(res.file_list, vec!["NCT00000102".to_string(), "NCT00000104.json".to_string()]);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn check_x_with_multiple_sources_panics() {
+
+    let config = r#"
+[folders]
+mdr_zipped="E:\\MDR\\Zipped source files"
+mdr_unzipped="E:\\MDR\\MDR Source files"
+log_folder_path="E:\\MDR\\Zipping\\logs"
+
+[database]
+db_host="localhost"
+db_user="user_name"
+db_password="password"
+db_port="5433"
+db_name="mon"
+"#;
+
+        let config_string = config.to_string();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
+
+        let args : Vec<&str> = vec!["dummy target", "-u", "-s", "100120, 100121", "-x", "NCT00000102"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let cli_pars = cli_reader::fetch_valid_arguments(test_args).unwrap();
+
+        let _res = get_params(cli_pars, &config_string).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn check_malformed_source_id_panics() {
+
+    let config = r#"
+[folders]
+mdr_zipped="E:\\MDR\\Zipped source files"
+mdr_unzipped="E:\\MDR\\MDR Source files"
+log_folder_path="E:\\MDR\\Zipping\\logs"
+
+[database]
+db_host="localhost"
+db_user="user_name"
+db_password="password"
+db_port="5433"
+db_name="mon"
+"#;
+
+        let config_string = config.to_string();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
+
+        let args : Vec<&str> = vec!["dummy target", "zip", "-s", "100120, 1001O1"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let cli_pars = cli_reader::fetch_valid_arguments(test_args).unwrap();
+
+        let _res = get_params(cli_pars, &config_string).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn check_changed_only_with_unzip_panics() {
+
+    let config = r#"
+[folders]
+mdr_zipped="E:\\MDR\\Zipped source files"
+mdr_unzipped="E:\\MDR\\MDR Source files"
+log_folder_path="E:\\MDR\\Zipping\\logs"
+
+[database]
+db_host="localhost"
+db_user="user_name"
+db_password="password"
+db_port="5433"
+db_name="mon"
+"#;
+
+        let config_string = config.to_string();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
+
+        let args : Vec<&str> = vec!["dummy target", "-u", "-m", "--changed-only"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let cli_pars = cli_reader::fetch_valid_arguments(test_args).unwrap();
+
+        let _res = get_params(cli_pars, &config_string).unwrap();
+    }
+
+
     #[test]
     #[should_panic]
     fn check_no_z_or_u_panics() {
@@ -425,7 +641,7 @@ db_name="mon"
 "#;
 
         let config_string = config.to_string();
-        config_reader::populate_config_vars(&config_string).unwrap();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
 
         let args : Vec<&str> = vec!["dummy target", "-m"];
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
@@ -455,7 +671,7 @@ db_name="mon"
 "#;
 
         let config_string = config.to_string();
-        config_reader::populate_config_vars(&config_string).unwrap();
+        config_reader::read_config_vars(&config_string, |_| None, None).unwrap();
 
         let args : Vec<&str> = vec!["dummy target", "-z"];
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
@@ -463,5 +679,60 @@ db_name="mon"
 
         let _res = get_params(cli_pars, &config_string).unwrap();
     }
-}
 
+
+    const JOBS_CONFIG: &str = r#"
+[folders]
+mdr_zipped="E:\\MDR\\Zipped source files"
+mdr_unzipped="E:\\MDR\\MDR Source files"
+log_folder_path="E:\\MDR\\Zipping\\logs"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[[jobs]]
+name="umls"
+source="/data/umls"
+destination="/archives/umls"
+
+[[jobs]]
+name="ontologies"
+source="/data/ontologies"
+destination="/archives/ontologies"
+"#;
+
+    #[test]
+    fn check_named_jobs_selected_in_order() {
+
+        let args : Vec<&str> = vec!["dummy target", "run", "ontologies", "umls"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let cli_pars = cli_reader::fetch_valid_arguments(test_args).unwrap();
+
+        let res = get_params(cli_pars, JOBS_CONFIG).unwrap();
+        assert_eq!(res.flags.operation, Operation::Run);
+        assert_eq!(res.jobs.iter().map(|j| j.name.as_str()).collect::<Vec<&str>>(), vec!["ontologies", "umls"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_unknown_job_name_panics() {
+
+        let args : Vec<&str> = vec!["dummy target", "run", "umls", "exports"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let cli_pars = cli_reader::fetch_valid_arguments(test_args).unwrap();
+
+        let _res = get_params(cli_pars, JOBS_CONFIG).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_report_with_watch_panics() {
+
+        let args : Vec<&str> = vec!["dummy target", "watch", "-m", "--report", "run.json"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+        let cli_pars = cli_reader::fetch_valid_arguments(test_args).unwrap();
+
+        let _res = get_params(cli_pars, JOBS_CONFIG).unwrap();
+    }
+}
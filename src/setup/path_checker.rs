@@ -0,0 +1,384 @@
+/***************************************************************************
+ * Resolves the paths given in the configuration and on the command line,
+ * and checks, before any work starts, that the folders and files needed by
+ * the chosen operation exist and can be read or written. Paths may start with
+ * '~' (the user's home folder) and include environment variables, as $VAR or
+ * ${VAR} (and %VAR% on Windows). Separators are converted to the platform's
+ * own. All the problems found are reported together.
+ ***************************************************************************/
+
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR};
+use crate::err::AppError;
+use crate::locks::LockScope;
+use crate::setup::InitParams;
+use crate::setup::cli_reader::Operation;
+use crate::setup::config_reader::StorageBackend;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    ReadFolder,
+    WriteFolder,       // may not exist yet, if it can be created
+    ReadWriteFolder,
+    ReadFile,
+    ReadFileOrFolder,
+}
+
+
+pub fn check_paths(params: &mut InitParams) -> Result<(), AppError> {
+
+    let mut problems: Vec<String> = Vec::new();
+
+    // Resolve every path, so that all are logged and used in their final form.
+
+    let mut resolve = |label: &str, p: &mut PathBuf| {
+        if p.as_os_str().is_empty() {
+            return;
+        }
+        match resolve_path(&p.to_string_lossy(), |k| env::var(k).ok()) {
+            Ok(r) => *p = r,
+            Err(e) => problems.push(format!("{} {:?}: {}", label, p, e)),
+        }
+    };
+    resolve("mdr_zipped", &mut params.mdr_zipped);
+    resolve("mdr_unzipped", &mut params.mdr_unzipped);
+    resolve("fdr_zipped", &mut params.fdr_zipped);
+    resolve("fdr_unzipped", &mut params.fdr_unzipped);
+    resolve("log_folder_path", &mut params.log_folder_path);
+    match &mut params.storage.backend {
+        StorageBackend::Local(Some(root)) => resolve("storage root", root),
+        StorageBackend::Sftp(sftp) => {
+            resolve("SFTP key_file", &mut sftp.key_file);
+            resolve("SFTP known_hosts", &mut sftp.known_hosts);
+        },
+        _ => (),
+    }
+    if let Some(report_path) = params.report_path.as_mut() {
+        resolve("report", report_path);
+    }
+    for job in params.jobs.iter_mut() {
+        resolve(&format!("job '{}' source", job.name), &mut job.source);
+        resolve(&format!("job '{}' destination", job.name), &mut job.destination);
+    }
+    if !problems.is_empty() {
+        return Err(AppError::InvalidPaths(problems));
+    }
+
+    for (label, path, access) in get_needed_paths(params) {
+        if let Err(e) = check_access(&path, access) {
+            problems.push(format!("{} {:?} {}", label, path, e));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(AppError::InvalidPaths(problems));
+    }
+
+    // The log folder is created here if need be, as the log is set up next.
+
+    if !params.flags.test_run && !params.log_folder_path.is_dir() {
+        fs::create_dir_all(&params.log_folder_path)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, params.log_folder_path.to_owned()))?;
+    }
+    Ok(())
+}
+
+
+fn get_needed_paths(params: &InitParams) -> Vec<(String, PathBuf, Access)> {
+
+    // Operations that take a lock write a lock file in the zipped folder(s) - see lock_folder.
+
+    let flags = params.flags;
+    let op = flags.operation;
+    let mdr = flags.all_mdr || !params.source_selection.is_empty();
+    let mut needed: Vec<(String, PathBuf, Access)> = Vec::new();
+    let mut need = |label: &str, path: &Path, access: Access| {
+        needed.push((label.to_string(), path.to_owned(), access));
+    };
+
+    if !flags.test_run {
+        need("Log folder", &params.log_folder_path, Access::WriteFolder);
+    }
+    if let Some(report_path) = &params.report_path {
+        need("Folder for the report", parent_folder(report_path), Access::WriteFolder);
+    }
+
+    if op == Operation::Run {
+        for job in &params.jobs {
+            let source_access = if job.operation == Operation::Zip { Access::ReadFolder } else { Access::ReadFileOrFolder };
+            need(&format!("Job '{}' source", job.name), &job.source, source_access);
+            need(&format!("Job '{}' destination", job.name), &job.destination, Access::WriteFolder);
+        }
+        if params.lock_scope == LockScope::Global {
+            need("mdr zipped folder (for the lock)", &params.mdr_zipped, Access::WriteFolder);
+        }
+    }
+    else if flags.use_folder {
+        let fdr_parent = parent_folder(&params.fdr_zipped);
+        match op {
+            Operation::Zip => {
+                need("fdr unzipped folder", &params.fdr_unzipped, Access::ReadFolder);
+                need("Folder for the fdr archive", fdr_parent, Access::WriteFolder);
+            },
+            Operation::Unzip => {
+                need("fdr archive", &params.fdr_zipped, Access::ReadFile);
+                need("Folder of the fdr archive (for the lock)", fdr_parent, Access::WriteFolder);
+                need("fdr unzipped folder", &params.fdr_unzipped, Access::WriteFolder);
+            },
+            Operation::Diff => {
+                need("fdr archive", &params.fdr_zipped, Access::ReadFile);
+                need("fdr unzipped folder", &params.fdr_unzipped, Access::ReadFolder);
+            },
+            _ => need("fdr archive", &params.fdr_zipped, Access::ReadFile),
+        }
+    }
+    else if mdr {
+
+        // With archives held in storage, mdr_zipped is also where sets are fetched to before they are read.
+
+        if let StorageBackend::Local(Some(root)) = &params.storage.backend {
+            let access = if matches!(op, Operation::Zip | Operation::Watch | Operation::Prune) { Access::WriteFolder } else { Access::ReadFolder };
+            need("Archive storage folder", root, access);
+        }
+        if let StorageBackend::Sftp(sftp) = &params.storage.backend {
+            need("SFTP key file", &sftp.key_file, Access::ReadFile);
+            need("SFTP known hosts file", &sftp.known_hosts, Access::ReadFile);
+        }
+        if !params.storage.is_default() && matches!(op, Operation::Verify | Operation::Diff) {
+            need("mdr zipped folder", &params.mdr_zipped, Access::WriteFolder);
+        }
+        match op {
+            Operation::Zip | Operation::Watch => {
+                need("mdr unzipped folder", &params.mdr_unzipped, Access::ReadFolder);
+                need("mdr zipped folder", &params.mdr_zipped, Access::WriteFolder);
+            },
+            Operation::Unzip => {
+                need("mdr zipped folder", &params.mdr_zipped, Access::ReadWriteFolder);
+                need("mdr unzipped folder", &params.mdr_unzipped, Access::WriteFolder);
+            },
+            Operation::Prune => need("mdr zipped folder", &params.mdr_zipped, Access::ReadWriteFolder),
+            Operation::Diff => {
+                need("mdr zipped folder", &params.mdr_zipped, Access::ReadFolder);
+                need("mdr unzipped folder", &params.mdr_unzipped, Access::ReadFolder);
+            },
+            _ => need("mdr zipped folder", &params.mdr_zipped, Access::ReadFolder),
+        }
+    }
+
+    needed.dedup();
+    needed
+}
+
+
+fn check_access(path: &Path, access: Access) -> Result<(), String> {
+
+    match access {
+        Access::ReadFolder => check_readable_folder(path),
+        Access::ReadWriteFolder => {
+            check_readable_folder(path)?;
+            check_writable_folder(path)
+        },
+        Access::WriteFolder => {
+
+            // A folder that does not yet exist is created when needed, so its nearest
+            // existing parent must be writable.
+
+            if path.exists() {
+                check_writable_folder(path)
+            }
+            else {
+                let existing = path.ancestors().skip(1)
+                    .map(|a| if a.as_os_str().is_empty() { Path::new(".") } else { a })
+                    .find(|a| a.exists());
+                match existing {
+                    Some(a) => check_writable_folder(a)
+                                .map_err(|e| format!("does not exist, and cannot be created ({:?} {})", a, e)),
+                    None => Err("does not exist, and cannot be created".to_string()),
+                }
+            }
+        },
+        Access::ReadFile => {
+            if !path.exists() {
+                return Err("does not exist".to_string());
+            }
+            if !path.is_file() {
+                return Err("is not a file".to_string());
+            }
+            fs::File::open(path).map(|_| ()).map_err(|e| format!("cannot be read ({})", e))
+        },
+        Access::ReadFileOrFolder => {
+            if path.is_dir() {
+                check_readable_folder(path)
+            } else {
+                check_access(path, Access::ReadFile)
+            }
+        },
+    }
+}
+
+
+fn check_readable_folder(path: &Path) -> Result<(), String> {
+    if !path.exists() {
+        return Err("does not exist".to_string());
+    }
+    if !path.is_dir() {
+        return Err("is not a folder".to_string());
+    }
+    fs::read_dir(path).map(|_| ()).map_err(|e| format!("cannot be read ({})", e))
+}
+
+
+fn check_writable_folder(path: &Path) -> Result<(), String> {
+
+    // Permissions alone do not show whether a folder can be written (e.g. read only mounts,
+    // Windows ACLs), so a small file is created and removed.
+
+    if !path.is_dir() {
+        return Err("is not a folder".to_string());
+    }
+    let probe = path.join(format!(".zipper_write_check_{}", std::process::id()));
+    fs::write(&probe, b"")
+        .map_err(|e| format!("cannot be written ({})", e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+
+fn parent_folder(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+
+pub fn resolve_path(raw: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<PathBuf, String> {
+
+    // Separators are normalised first, so that '~/' and '~\' are treated alike.
+
+    let other_separator = if MAIN_SEPARATOR == '/' { '\\' } else { '/' };
+    let normalised = raw.trim().replace(other_separator, MAIN_SEPARATOR_STR);
+    let expanded = expand_variables(&normalised, &lookup)?;
+
+    let home_relative = expanded == "~" || expanded.starts_with(&format!("~{}", MAIN_SEPARATOR));
+    let path = if home_relative {
+        let home = lookup("HOME").or_else(|| lookup("USERPROFILE"))
+                    .ok_or("'~' is used, but the home folder is not known (HOME is not set)")?;
+        PathBuf::from(home).join(expanded[1..].trim_start_matches(MAIN_SEPARATOR))
+    } else {
+        PathBuf::from(expanded)
+    };
+
+    // '.' components (and doubled or trailing separators) are removed. '..' is kept,
+    // as it cannot be resolved without following links.
+
+    Ok(path.components().filter(|c| *c != Component::CurDir).collect())
+}
+
+
+fn expand_variables(s: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (name, next) = if c == '$' && chars.get(i + 1) == Some(&'{') {
+            match chars[i + 2..].iter().position(|c| *c == '}') {
+                Some(n) => (chars[i + 2..i + 2 + n].iter().collect::<String>(), i + 3 + n),
+                None => return Err(format!("'${{' without a closing '}}' in '{}'", s)),
+            }
+        }
+        else if c == '$' && chars.get(i + 1).is_some_and(|c| is_name_char(*c)) {
+            let n = chars[i + 1..].iter().take_while(|c| is_name_char(**c)).count();
+            (chars[i + 1..i + 1 + n].iter().collect::<String>(), i + 1 + n)
+        }
+        else if cfg!(windows) && c == '%' {
+            match chars[i + 1..].iter().position(|c| *c == '%') {
+                Some(n) if n > 0 && chars[i + 1..i + 1 + n].iter().all(|c| is_name_char(*c)) =>
+                    (chars[i + 1..i + 1 + n].iter().collect::<String>(), i + 2 + n),
+                _ => {
+                    out.push(c);
+                    i += 1;
+                    continue;
+                }
+            }
+        }
+        else {
+            out.push(c);
+            i += 1;
+            continue;
+        };
+        match lookup(&name) {
+            Some(v) => out.push_str(&v),
+            None => return Err(format!("the environment variable {} is not set", name)),
+        }
+        i = next;
+    }
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(k: &str) -> Option<String> {
+        match k {
+            "HOME" => Some("/home/zipper".to_string()),
+            "MDR_ROOT" => Some("/data/mdr".to_string()),
+            _ => None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_paths_resolved() {
+        assert_eq!(resolve_path("~/logs", lookup).unwrap(), PathBuf::from("/home/zipper/logs"));
+        assert_eq!(resolve_path("$MDR_ROOT/zipped/", lookup).unwrap(), PathBuf::from("/data/mdr/zipped"));
+        assert_eq!(resolve_path("${MDR_ROOT}\\unzipped\\.\\ctg", lookup).unwrap(), PathBuf::from("/data/mdr/unzipped/ctg"));
+        assert_eq!(resolve_path("/data/a$", lookup).unwrap(), PathBuf::from("/data/a$"));
+        assert!(resolve_path("$UNSET_FOLDER/zipped", lookup).is_err());
+        assert!(resolve_path("${MDR_ROOT/zipped", lookup).is_err());
+    }
+
+    #[test]
+    fn check_all_missing_paths_reported() {
+        let config = r#"
+[folders]
+mdr_zipped="/nonexistent/zipper/zipped"
+mdr_unzipped="/nonexistent/zipper/unzipped"
+log_folder_path="/nonexistent/zipper/logs"
+
+[database]
+db_user="user_name"
+db_password="password"
+"#;
+        let args : Vec<&str> = vec!["dummy target", "diff", "-m", "-t"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<std::ffi::OsString>>();
+        let cli_pars = crate::setup::cli_reader::fetch_valid_arguments(test_args).unwrap();
+        let mut params = crate::setup::get_params(cli_pars, config).unwrap();
+
+        match check_paths(&mut params) {
+            Err(AppError::InvalidPaths(problems)) => {
+                assert_eq!(problems.len(), 2);
+                assert!(problems[0].contains("mdr zipped folder"));
+                assert!(problems[1].contains("mdr unzipped folder"));
+            },
+            _ => panic!("missing folders should be reported"),
+        }
+    }
+
+    #[test]
+    fn check_folder_to_be_created_accepted() {
+        let folder = std::env::temp_dir().join(format!("zipper_paths_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        assert!(check_access(&folder, Access::ReadWriteFolder).is_ok());
+        assert!(check_access(&folder.join("new").join("logs"), Access::WriteFolder).is_ok());
+        assert!(check_access(&folder.join("missing.zip"), Access::ReadFile).is_err());
+        assert!(check_access(Path::new("new_zipper_logs"), Access::WriteFolder).is_ok());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
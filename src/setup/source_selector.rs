@@ -0,0 +1,206 @@
+/***************************************************************************
+ * Interprets the -s source list. Each comma separated item may be a source
+ * id (100120), a range of ids (100120-100140) or a database name (ctg), and
+ * any of these may be prefixed by '!' to exclude it. If only exclusions are
+ * given they are applied to the full list of mdr sources.
+ ***************************************************************************/
+
+use crate::err::AppError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceSelector {
+    Id(i32),
+    Range(i32, i32),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceSelection {
+    pub include: Vec<SourceSelector>,
+    pub exclude: Vec<SourceSelector>,
+}
+
+impl SourceSelection {
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn is_single_source(&self) -> bool {
+        self.exclude.is_empty() && self.include.len() == 1
+            && !matches!(self.include[0], SourceSelector::Range(_, _))
+    }
+
+    pub fn listed_ids(&self) -> Vec<i32> {
+        self.include.iter()
+            .filter_map(|s| match s { SourceSelector::Id(id) => Some(*id), _ => None })
+            .collect()
+    }
+}
+
+
+pub fn parse_source_list(source_list: &str) -> Result<SourceSelection, AppError> {
+
+    // All malformed items are collected, so they can be reported together.
+
+    let mut selection = SourceSelection::default();
+    let mut malformed: Vec<String> = Vec::new();
+
+    for item in source_list.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let (excluded, body) = match item.strip_prefix('!') {
+            Some(b) => (true, b.trim()),
+            None => (false, item),
+        };
+        match parse_selector(body) {
+            Some(s) => if excluded { selection.exclude.push(s) } else { selection.include.push(s) },
+            None => malformed.push(item.to_string()),
+        }
+    }
+
+    if !malformed.is_empty() {
+        return Err(AppError::InvalidSources("Malformed item(s) in the source list".to_string(), malformed));
+    }
+    Ok(selection)
+}
+
+
+fn parse_selector(item: &str) -> Option<SourceSelector> {
+
+    // Items starting with a digit must be an id or a range. A database name must start with
+    // a letter, so that a mistyped id (e.g. '1001O1') is reported rather than taken as a name.
+
+    let first = item.chars().next()?;
+    if first.is_ascii_digit() {
+        match item.split_once('-') {
+            Some((from, to)) => {
+                let from = from.trim().parse::<i32>().ok()?;
+                let to = to.trim().parse::<i32>().ok()?;
+                if from <= to { Some(SourceSelector::Range(from, to)) } else { None }
+            },
+            None => item.parse::<i32>().ok().map(SourceSelector::Id),
+        }
+    }
+    else if first.is_ascii_alphabetic() && item.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some(SourceSelector::Name(item.to_string()))
+    }
+    else {
+        None
+    }
+}
+
+
+pub fn resolve_sources(selection: &SourceSelection, catalogue: &[(i32, String)], all_ids: &[i32]) -> Result<Vec<i32>, AppError> {
+
+    // Checks every item against the source catalogue (id, database name) before any work is done,
+    // and returns the selected ids in the order given. Items that match no source are reported together.
+
+    let mut unknown: Vec<String> = Vec::new();
+
+    let mut ids: Vec<i32> = if selection.include.is_empty() {
+        all_ids.to_vec()
+    }
+    else {
+        let mut ids = Vec::new();
+        for s in &selection.include {
+            let matched = match_selector(s, catalogue);
+            if matched.is_empty() {
+                unknown.push(describe(s));
+            }
+            for id in matched {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    };
+
+    for s in &selection.exclude {
+        let matched = match_selector(s, catalogue);
+        if matched.is_empty() {
+            unknown.push(format!("!{}", describe(s)));
+        }
+        ids.retain(|id| !matched.contains(id));
+    }
+
+    if !unknown.is_empty() {
+        return Err(AppError::InvalidSources("Item(s) in the source list match no source in source.parameters".to_string(), unknown));
+    }
+    Ok(ids)
+}
+
+
+fn match_selector(selector: &SourceSelector, catalogue: &[(i32, String)]) -> Vec<i32> {
+    catalogue.iter()
+        .filter(|(id, name)| match selector {
+            SourceSelector::Id(s) => id == s,
+            SourceSelector::Range(from, to) => id >= from && id <= to,
+            SourceSelector::Name(n) => name.eq_ignore_ascii_case(n),
+        })
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+
+fn describe(selector: &SourceSelector) -> String {
+    match selector {
+        SourceSelector::Id(id) => id.to_string(),
+        SourceSelector::Range(from, to) => format!("{}-{}", from, to),
+        SourceSelector::Name(n) => n.clone(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_catalogue() -> Vec<(i32, String)> {
+        vec![(100120, "ctg".to_string()), (100123, "euctr".to_string()), (100126, "isrctn".to_string()),
+             (100159, "who".to_string()), (101900, "biolincc".to_string())]
+    }
+
+    #[test]
+    fn check_ids_ranges_names_and_exclusions_parsed() {
+        let res = parse_source_list("100120, 100123-100140, ctg, !100126, !who").unwrap();
+        assert_eq!(res.include, vec![SourceSelector::Id(100120), SourceSelector::Range(100123, 100140),
+                                     SourceSelector::Name("ctg".to_string())]);
+        assert_eq!(res.exclude, vec![SourceSelector::Id(100126), SourceSelector::Name("who".to_string())]);
+        assert_eq!(res.listed_ids(), vec![100120]);
+    }
+
+    #[test]
+    fn check_malformed_items_all_reported() {
+        match parse_source_list("100120, 1001O1, 100140-100120, !, 10-x") {
+            Err(AppError::InvalidSources(_, items)) => assert_eq!(items, vec!["1001O1", "100140-100120", "!", "10-x"]),
+            _ => panic!("malformed items not reported"),
+        }
+    }
+
+    #[test]
+    fn check_selection_resolved_against_catalogue() {
+        let sel = parse_source_list("100120-100130, biolincc, !100123").unwrap();
+        let res = resolve_sources(&sel, &test_catalogue(), &[]).unwrap();
+        assert_eq!(res, vec![100120, 100126, 101900]);
+    }
+
+    #[test]
+    fn check_exclusions_only_applied_to_all_sources() {
+        let sel = parse_source_list("!who").unwrap();
+        let res = resolve_sources(&sel, &test_catalogue(), &[100120, 100123, 100159]).unwrap();
+        assert_eq!(res, vec![100120, 100123]);
+    }
+
+    #[test]
+    fn check_unknown_items_all_reported() {
+        let sel = parse_source_list("100120, 100121, nosuchdb, !100122").unwrap();
+        match resolve_sources(&sel, &test_catalogue(), &[]) {
+            Err(AppError::InvalidSources(_, items)) => assert_eq!(items, vec!["100121", "nosuchdb", "!100122"]),
+            _ => panic!("unknown items not reported"),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::fs::File;
+use std::io::{copy, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::err::AppError;
+use crate::storage::{sha256_hex, Storage, StoredObject};
+
+// Objects held as files under a root folder, with the '/' separated parts of each name as sub-folders.
+// Files are written under a temporary name and renamed when complete.
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+
+    pub fn new(root: impl Into<PathBuf>) -> LocalStorage {
+        LocalStorage { root: root.into() }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        name.split('/').fold(self.root.clone(), |p, part| p.join(part))
+    }
+}
+
+impl Storage for LocalStorage {
+
+    fn describe(&self) -> String {
+        format!("{:?}", self.root)
+    }
+
+    fn put(&self, name: &str, source: &mut dyn Read, _length: u64, _sha256: Option<&str>) -> Result<(), AppError> {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| AppError::IoWriteErrorWithPath(e, parent.to_owned()))?;
+        }
+        let part_path = path.with_file_name(format!("{}.part", path.file_name().unwrap_or_default().to_string_lossy()));
+        let mut file = File::create(&part_path)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, part_path.to_owned()))?;
+        copy(source, &mut file).map_err(|e| AppError::IoWriteErrorWithPath(e, part_path.to_owned()))?;
+        drop(file);
+        fs::rename(&part_path, &path).map_err(|e| AppError::IoWriteErrorWithPath(e, path.to_owned()))
+    }
+
+    fn get(&self, name: &str, destination: &mut dyn Write) -> Result<u64, AppError> {
+        let path = self.path(name);
+        let mut file = File::open(&path)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))?;
+        copy(&mut file, destination).map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, AppError> {
+
+        // Only the folder named by the prefix (up to its last '/') needs to be searched.
+
+        let folder = match prefix.rsplit_once('/') {
+            Some((dir, _)) => self.path(dir),
+            None => self.root.clone(),
+        };
+        let mut objects: Vec<StoredObject> = Vec::new();
+        if folder.is_dir() {
+            collect_objects(&self.root, &folder, &mut objects)?;
+        }
+        objects.retain(|o| o.name.starts_with(prefix) && !o.name.ends_with(".part"));
+        Ok(objects)
+    }
+
+    fn delete(&self, name: &str) -> Result<(), AppError> {
+        let path = self.path(name);
+        fs::remove_file(&path).map_err(|e| AppError::IoWriteErrorWithPath(e, path.to_owned()))
+    }
+
+    fn sha256(&self, name: &str) -> Result<Option<String>, AppError> {
+        let path = self.path(name);
+        let mut file = File::open(&path)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))?;
+        sha256_hex(&mut file).map(Some).map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+
+fn collect_objects(root: &Path, folder: &Path, objects: &mut Vec<StoredObject>) -> Result<(), AppError> {
+
+    let entries = fs::read_dir(folder)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, folder.to_owned()))?;
+    for e in entries {
+        let e = e?;
+        let path = e.path();
+        if path.is_dir() {
+            collect_objects(root, &path, objects)?;
+        }
+        else if let Ok(rel) = path.strip_prefix(root) {
+            let name = rel.components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<String>>()
+                    .join("/");
+            objects.push(StoredObject { name, size: e.metadata()?.len() });
+        }
+    }
+    Ok(())
+}
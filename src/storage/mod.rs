@@ -0,0 +1,572 @@
+mod local;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "sftp")]
+mod sftp;
+
+pub use local::LocalStorage;
+#[cfg(feature = "s3")]
+pub use s3::S3Storage;
+#[cfg(feature = "sftp")]
+pub use sftp::SftpStorage;
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use log::info;
+use sha2::{Digest, Sha256};
+use crate::api::ArchiveObserver;
+use crate::cancel::CancelToken;
+use crate::err::AppError;
+use crate::setup::config_reader::{StorageBackend, StoragePars};
+use crate::setup::log_helper::LogContext;
+use crate::{archive_sets, catalogue};
+use crate::{ArchiveDetails, ArchiveSummary, SourceDetails};
+
+// MDR archive sets are held in a Storage backend: by default the local file system, or an S3
+// compatible object store, or a backup host reached by SFTP. Sets are always made, and read, in
+// mdr_zipped, which for other backends is a staging area: each archive is stored as soon as it is
+// complete (see ArchiveUploader), and before a set is read (unzipped, verified or compared) the
+// source's latest stored set is fetched, if not already present. Retention (prune)
+// and listing work on the stored sets as well. Objects are named '{db name}/{file name}', so that
+// archive names, and thus set selection, are the same on every backend.
+
+pub trait Storage: Send + Sync {
+
+    // A description of the backend, for the log.
+    fn describe(&self) -> String;
+
+    // Stores the content read from the source (of the given length) under the name, replacing any existing object.
+    // Backends able to check the content against its SHA-256 checksum (as hex) as it is stored do so, if it is given.
+    fn put(&self, name: &str, source: &mut dyn Read, length: u64, sha256: Option<&str>) -> Result<(), AppError>;
+
+    // Writes the named object's content to the destination, returning the bytes written.
+    fn get(&self, name: &str, destination: &mut dyn Write) -> Result<u64, AppError>;
+
+    // The objects whose names start with the prefix.
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, AppError>;
+
+    fn delete(&self, name: &str) -> Result<(), AppError>;
+
+    // The SHA-256 checksum (as hex) of the named object's content, for backends able to give one,
+    // so that stored archives can be checked against the local files as well as by size.
+    fn sha256(&self, _name: &str) -> Result<Option<String>, AppError> {
+        Ok(None)
+    }
+
+    // For local storage, the folder holding the objects.
+    fn local_root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredObject {
+    pub name: String,
+    pub size: u64,
+}
+
+
+pub fn open_storage(pars: &StoragePars, mdr_zipped: &Path) -> Result<Arc<dyn Storage>, AppError> {
+    check_backend_built(&pars.backend)?;
+    Ok(match &pars.backend {
+        StorageBackend::Local(None) => Arc::new(LocalStorage::new(mdr_zipped)),
+        StorageBackend::Local(Some(root)) => Arc::new(LocalStorage::new(root)),
+        #[cfg(feature = "s3")]
+        StorageBackend::S3(s3_pars) => Arc::new(S3Storage::new(s3_pars)),
+        #[cfg(feature = "sftp")]
+        StorageBackend::Sftp(sftp_pars) => Arc::new(SftpStorage::new(sftp_pars)),
+        #[allow(unreachable_patterns)]
+        _ => unreachable!("backends that are not built are rejected above"),
+    })
+}
+
+
+pub fn check_backend_built(backend: &StorageBackend) -> Result<(), AppError> {
+
+    // The S3 and SFTP backends (and the HTTP, SSL and SSH libraries they need) are only built
+    // with the 's3' and 'sftp' features, so a default build stores archive sets locally.
+
+    let missing = match backend {
+        StorageBackend::S3(_) if !cfg!(feature = "s3") => "s3",
+        StorageBackend::Sftp(_) if !cfg!(feature = "sftp") => "sftp",
+        _ => return Ok(()),
+    };
+    Err(AppError::ConfigurationError("Invalid [storage] section.".to_string(),
+            format!("Kind '{0}' needs a zipper built with the '{0}' feature (cargo build --release --features {0}).", missing)))
+}
+
+
+fn is_staging_area(storage: &dyn Storage, parent_zipped: &Path) -> bool {
+
+    // When the storage is mdr_zipped itself there is nothing to store or fetch.
+
+    match storage.local_root() {
+        Some(root) => root == parent_zipped
+                        || fs::canonicalize(root).ok().is_some_and(|r| fs::canonicalize(parent_zipped).ok() == Some(r)),
+        None => false,
+    }
+}
+
+
+fn object_name(database_name: &str, file_name: &str) -> String {
+    format!("{}/{}", database_name, file_name)
+}
+
+
+pub struct ArchiveUploader<'a> {
+    observer: &'a dyn ArchiveObserver,
+    storage: Arc<dyn Storage>,
+    database_name: String,
+    parent_zipped: PathBuf,
+    sender: Mutex<Option<mpsc::Sender<PathBuf>>>,
+    worker: Option<thread::JoinHandle<(Vec<String>, Option<AppError>)>>,
+}
+
+impl<'a> ArchiveUploader<'a> {
+
+    // Used as the observer of a source's zip, passing on each call to the run's own observer. Each
+    // archive is stored, on a separate thread, as soon as the zipper has finished it, so that uploads
+    // overlap the zipping of the following archives. finish then completes (or, if the zip failed,
+    // removes) the stored set.
+
+    pub fn start(storage: Arc<dyn Storage>, database_name: &str, parent_zipped: &Path, observer: &'a dyn ArchiveObserver,
+                 cancel: &CancelToken) -> ArchiveUploader<'a> {
+
+        let mut uploader = ArchiveUploader { observer, storage: storage.clone(), database_name: database_name.to_string(),
+                    parent_zipped: parent_zipped.to_owned(), sender: Mutex::new(None), worker: None };
+        if is_staging_area(storage.as_ref(), parent_zipped) {
+            return uploader;
+        }
+
+        let (sender, receiver) = mpsc::channel::<PathBuf>();
+        let (database_name, cancel) = (database_name.to_string(), cancel.clone());
+        uploader.sender = Mutex::new(Some(sender));
+        uploader.worker = Some(thread::spawn(move || {
+            let _ctx = LogContext::new().with("source", &database_name);
+            let mut stored: Vec<String> = Vec::new();
+            for path in receiver {
+                match cancel.check().and_then(|_| store_file(storage.as_ref(), &database_name, &path)) {
+                    Ok(name) => stored.push(name),
+                    Err(e) => return (stored, Some(e)),
+                }
+            }
+            (stored, None)
+        }));
+        uploader
+    }
+
+    pub fn finish(self, res: Result<ArchiveSummary, AppError>, keep_local: bool) -> Result<ArchiveSummary, AppError> {
+
+        // An incomplete set is removed from storage, as it would otherwise be taken as the source's latest set.
+
+        drop(self.sender.lock().unwrap_or_else(|e| e.into_inner()).take());
+        let (stored, upload_error) = match self.worker {
+            Some(worker) => worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e)),
+            None => return res,
+        };
+        match (res, upload_error) {
+            (Ok(summary), None) => {
+                store_set(self.storage.as_ref(), &self.database_name, &summary, &self.parent_zipped, keep_local, &stored)?;
+                Ok(summary)
+            },
+            (res, upload_error) => {
+                for name in &stored {
+                    let _ = self.storage.delete(name);
+                }
+                if !stored.is_empty() {
+                    info!("{} archive(s) of the incomplete set removed from {}", stored.len(), self.storage.describe());
+                }
+                res.and(Err(upload_error.unwrap_or(AppError::Cancelled)))
+            },
+        }
+    }
+}
+
+impl ArchiveObserver for ArchiveUploader<'_> {
+
+    fn source_started(&self, name: &str) {
+        self.observer.source_started(name);
+    }
+
+    fn totals_found(&self, file_count: usize, bytes: u64) {
+        self.observer.totals_found(file_count, bytes);
+    }
+
+    fn archive_opened(&self, archive_name: &str) {
+        self.observer.archive_opened(archive_name);
+    }
+
+    fn file_processed(&self, file_name: &str, bytes: u64) {
+        self.observer.file_processed(file_name, bytes);
+    }
+
+    fn archive_finished(&self, archive: &ArchiveDetails) {
+        self.observer.archive_finished(archive);
+        if let Some(sender) = self.sender.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            let _ = sender.send(self.parent_zipped.join(&self.database_name).join(&archive.name));
+        }
+    }
+
+    fn source_finished(&self, summary: &ArchiveSummary) {
+        self.observer.source_finished(summary);
+    }
+
+    fn source_skipped(&self, name: &str, reason: &str) {
+        self.observer.source_skipped(name, reason);
+    }
+
+    fn error(&self, error: &AppError) {
+        self.observer.error(error);
+    }
+}
+
+
+pub fn store_set(storage: &dyn Storage, database_name: &str, summary: &ArchiveSummary, parent_zipped: &Path,
+                 keep_local: bool, already_stored: &[String]) -> Result<(), AppError> {
+
+    // Any of the set's archives not already stored are stored, and then the catalogue index, so a
+    // stored set with an index is complete. Local copies of the archives are only removed once all
+    // have been stored and checked. The index is always kept locally, for locate.
+
+    if is_staging_area(storage, parent_zipped) || summary.archives.is_empty() {
+        return Ok(());
+    }
+    let folder = parent_zipped.join(database_name);
+    let files: Vec<PathBuf> = summary.archives.iter()
+                .filter(|a| !already_stored.contains(&object_name(database_name, &a.name)))
+                .map(|a| folder.join(&a.name))
+                .collect();
+    let set_date = summary.set_name.as_deref().and_then(|n| n.rsplit(' ').next());
+    for f in &files {
+        store_file(storage, database_name, f)?;
+    }
+
+    // As locally (see zipper), any archives of an earlier set made on the same day, and not
+    // replaced by this one, are removed before the index is stored.
+
+    if let Some(date) = set_date {
+        let stored = stored_sizes(storage, database_name)?;
+        let mut sets = archive_sets::group_archive_sets(database_name, stored.keys().map(PathBuf::from));
+        for a in sets.remove(date).unwrap_or_default() {
+            if !summary.archives.iter().any(|s| s.name == a.file_name()) {
+                storage.delete(&object_name(database_name, a.file_name()))?;
+                info!("{} of an earlier set deleted from {}", a.file_name(), storage.describe());
+            }
+        }
+        let index_path = catalogue::index_path(database_name, date, &folder);
+        if index_path.is_file() {
+            store_file(storage, database_name, &index_path)?;
+        }
+    }
+    info!("Set {:?} stored in {}", summary.set_name.as_deref().unwrap_or_default(), storage.describe());
+
+    if !keep_local {
+        for a in &summary.archives {
+            let p = folder.join(&a.name);
+            fs::remove_file(&p).map_err(|e| AppError::IoWriteErrorWithPath(e, p.to_owned()))?;
+        }
+        info!("Local copies of the {} archive(s) removed", summary.archives.len());
+    }
+    Ok(())
+}
+
+
+fn store_file(storage: &dyn Storage, database_name: &str, path: &Path) -> Result<String, AppError> {
+
+    // The checksum of the local file is found before it is stored, so that backends able to
+    // (S3) can check the content as they receive it, and is compared with that of the stored
+    // file where the backend can give one. Returns the object's name.
+
+    let name = object_name(database_name, &path.file_name().unwrap_or_default().to_string_lossy());
+    let mut file = File::open(path)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))?;
+    let length = file.metadata()?.len();
+    let checksum = sha256_hex(&mut file).map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))?;
+    file.seek(SeekFrom::Start(0)).map_err(|e| AppError::IoReadErrorWithPath(e, path.to_owned()))?;
+    storage.put(&name, &mut file, length, Some(&checksum))?;
+
+    let stored_size = storage.list(&name)?.into_iter().find(|o| o.name == name).map(|o| o.size);
+    if stored_size != Some(length) {
+        return Err(AppError::StorageError(format!("{} was not stored correctly in {}.", name, storage.describe()),
+                        format!("Local size {} bytes, stored size {:?}", length, stored_size)));
+    }
+    let checked = match storage.sha256(&name)? {
+        Some(stored_checksum) if stored_checksum != checksum => {
+            return Err(AppError::StorageError(format!("{} was not stored correctly in {}.", name, storage.describe()),
+                        format!("Local SHA-256 {}, stored SHA-256 {}", checksum, stored_checksum)));
+        },
+        Some(_) => "size and checksum checked",
+        None => "size checked",
+    };
+    info!("{} stored in {} ({} bytes, {})", name, storage.describe(), length, checked);
+    Ok(name)
+}
+
+
+pub fn fetch_latest_set(storage: &dyn Storage, database_name: &str, parent_zipped: &Path) -> Result<usize, AppError> {
+
+    // Archives already present locally, at the stored size, are not fetched again. Each is written
+    // to a '.part' file first, so that an interrupted fetch never leaves an incomplete archive.
+    // Returns the number of files fetched.
+
+    if is_staging_area(storage, parent_zipped) {
+        return Ok(0);
+    }
+    let stored = stored_sizes(storage, database_name)?;
+    let mut sets = archive_sets::group_archive_sets(database_name, stored.keys().map(PathBuf::from));
+    let (date, set) = match sets.pop_last() {
+        Some(s) => s,
+        None => return Ok(0),
+    };
+
+    let folder = parent_zipped.join(database_name);
+    fs::create_dir_all(&folder).map_err(|e| AppError::IoWriteErrorWithPath(e, folder.to_owned()))?;
+    let mut file_names: Vec<String> = set.iter().map(|a| a.file_name().to_string()).collect();
+    let index_name = catalogue::index_path(database_name, &date, Path::new("")).to_string_lossy().to_string();
+    if stored.contains_key(&index_name) {
+        file_names.push(index_name);
+    }
+
+    let mut fetched = 0;
+    for file_name in file_names {
+        let local_path = folder.join(&file_name);
+        let stored_size = stored[&file_name];
+        if fs::metadata(&local_path).is_ok_and(|m| m.len() == stored_size) {
+            continue;
+        }
+        let part_path = folder.join(format!("{}.part", file_name));
+        let mut part_file = File::create(&part_path)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, part_path.to_owned()))?;
+        let fetched_size = storage.get(&object_name(database_name, &file_name), &mut part_file)?;
+        drop(part_file);
+        if fetched_size != stored_size {
+            let _ = fs::remove_file(&part_path);
+            return Err(AppError::StorageError(format!("{} was not fetched correctly from {}.", file_name, storage.describe()),
+                        format!("Stored size {} bytes, {} bytes received", stored_size, fetched_size)));
+        }
+        fs::rename(&part_path, &local_path)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, local_path.to_owned()))?;
+        fetched += 1;
+    }
+    info!("Set '{} {}' fetched from {}: {} file(s) fetched, {} already present", database_name, date,
+              storage.describe(), fetched, set.len() - fetched.min(set.len()));
+    Ok(fetched)
+}
+
+
+pub fn prune_stored_sets(storage: &dyn Storage, database_name: &str, parent_zipped: &Path, keep: usize,
+                         dry_run: bool) -> Result<ArchiveSummary, AppError> {
+
+    // As prune_mdr_archives, for the sets held in storage.
+
+    let mut summary = ArchiveSummary::default();
+    if is_staging_area(storage, parent_zipped) {
+        return Ok(summary);
+    }
+    let stored = stored_sizes(storage, database_name)?;
+    let sets = archive_sets::group_archive_sets(database_name, stored.keys().map(PathBuf::from));
+    let prune_num = sets.len().saturating_sub(keep);
+
+    for (date, set) in sets.into_iter().take(prune_num) {
+        let index_name = catalogue::index_path(database_name, &date, Path::new("")).to_string_lossy().to_string();
+        let mut file_names: Vec<&str> = set.iter().map(|a| a.file_name()).collect();
+        if stored.contains_key(&index_name) {
+            file_names.push(&index_name);
+        }
+        for file_name in file_names {
+            let name = object_name(database_name, file_name);
+            if dry_run {
+                info!("{} in {} would be deleted", name, storage.describe());
+            }
+            else {
+                storage.delete(&name)?;
+                info!("{} deleted from {}", name, storage.describe());
+            }
+        }
+        for a in &set {
+            let archive_bytes = stored[a.file_name()];
+            summary.bytes_in += archive_bytes;
+            summary.archives.push(ArchiveDetails { name: a.file_name().to_string(), file_count: 0, archive_bytes });
+        }
+    }
+
+    info!("{}: {} stored archive set(s) {}, {} archive(s), {} bytes", database_name, prune_num,
+              if dry_run {"would be pruned"} else {"pruned"}, summary.archives.len(), summary.bytes_in);
+    Ok(summary)
+}
+
+
+pub fn list_stored_sets(storage: &dyn Storage, source: &SourceDetails, parent_zipped: &Path) -> Result<usize, AppError> {
+
+    // Used with list, after the sets in mdr_zipped, when the sets are held elsewhere.
+
+    if is_staging_area(storage, parent_zipped) {
+        return Ok(0);
+    }
+    let stored = stored_sizes(storage, &source.database_name)?;
+    let sets = archive_sets::group_archive_sets(&source.database_name, stored.keys().map(PathBuf::from));
+    if sets.is_empty() {
+        println!("{} ({}): no archive sets in {}", source.database_name, source.id, storage.describe());
+    }
+    for (date, set) in sets.iter().rev() {
+        let set_bytes: u64 = set.iter().map(|a| stored[a.file_name()]).sum();
+        println!("{} ({}): set '{} {}' in {}, {} archive(s), {} bytes",
+                    source.database_name, source.id, source.database_name, date, storage.describe(), set.len(), set_bytes);
+        for a in set {
+            println!("    {}", a.file_name());
+        }
+    }
+    Ok(sets.len())
+}
+
+
+fn stored_sizes(storage: &dyn Storage, database_name: &str) -> Result<HashMap<String, u64>, AppError> {
+
+    // The source's stored objects, by file name. Objects in sub-folders of the source's are not included.
+
+    let prefix = object_name(database_name, "");
+    Ok(storage.list(&prefix)?.into_iter()
+        .filter_map(|o| {
+            let file_name = o.name.strip_prefix(&prefix)?;
+            (!file_name.contains('/')).then(|| (file_name.to_string(), o.size))
+        })
+        .collect())
+}
+
+
+pub(crate) fn sha256_hex(reader: &mut dyn Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    copy_buffered(reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+
+pub(crate) fn copy_buffered(reader: &mut dyn Read, writer: &mut dyn Write) -> std::io::Result<u64> {
+
+    // Larger reads and writes than io::copy's make far fewer round trips with remote backends.
+
+    let mut buf = vec![0u8; 256 * 1024];
+    let mut total = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        total += n as u64;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_set(folder: &Path, date: &str, archive_count: usize) -> ArchiveSummary {
+        let mut summary = ArchiveSummary::default();
+        for i in 0..archive_count {
+            let name = format!("ctg {} {} to {}.zip", date, i * 10 + 1, i * 10 + 10);
+            fs::write(folder.join(&name), format!("archive {} {}", date, i)).unwrap();
+            summary.archives.push(ArchiveDetails { name, file_count: 10, archive_bytes: 0 });
+        }
+        fs::write(catalogue::index_path("ctg", date, folder), "{}").unwrap();
+        summary.set_name = Some(format!("ctg {}", date));
+        summary
+    }
+
+    #[test]
+    fn check_sets_stored_fetched_and_pruned() {
+        let base = std::env::temp_dir().join(format!("zipper_storage_test_{}", std::process::id()));
+        let (staging, stored_root) = (base.join("zipped"), base.join("stored"));
+        fs::create_dir_all(staging.join("ctg")).unwrap();
+        let storage = LocalStorage::new(&stored_root);
+        let source = SourceDetails { id: 100120, database_name: "ctg".to_string(), local_folder: String::new(),
+                                     local_files_grouped: false, grouping_range_by_id: None };
+
+        let older = make_set(&staging.join("ctg"), "250101", 1);
+        store_set(&storage, "ctg", &older, &staging, true, &[]).unwrap();
+        let newer = make_set(&staging.join("ctg"), "250201", 2);
+        store_set(&storage, "ctg", &newer, &staging, false, &[]).unwrap();
+        assert!(staging.join("ctg").join("ctg 250101 1 to 10.zip").exists());
+        assert!(!staging.join("ctg").join("ctg 250201 1 to 10.zip").exists());
+        assert_eq!(storage.list("ctg/").unwrap().len(), 5);
+        assert_eq!(list_stored_sets(&storage, &source, &staging).unwrap(), 2);
+
+        // Only the missing archives of the latest set are fetched.
+
+        assert_eq!(fetch_latest_set(&storage, "ctg", &staging).unwrap(), 2);
+        assert_eq!(fs::read_to_string(staging.join("ctg").join("ctg 250201 11 to 20.zip")).unwrap(), "archive 250201 1");
+        assert_eq!(fetch_latest_set(&storage, "ctg", &staging).unwrap(), 0);
+
+        let summary = prune_stored_sets(&storage, "ctg", &staging, 1, false).unwrap();
+        assert_eq!(summary.archive_names(), vec!["ctg 250101 1 to 10.zip"]);
+        let mut remaining: Vec<String> = storage.list("ctg/").unwrap().into_iter().map(|o| o.name).collect();
+        remaining.sort();
+        assert_eq!(remaining, vec!["ctg/ctg 250201 1 to 10.zip", "ctg/ctg 250201 11 to 20.zip", "ctg/ctg 250201 index.json"]);
+
+        // With the default storage (mdr_zipped itself) nothing is copied.
+
+        let default_storage = LocalStorage::new(&staging);
+        assert_eq!(fetch_latest_set(&default_storage, "ctg", &staging).unwrap(), 0);
+        assert!(prune_stored_sets(&default_storage, "ctg", &staging, 1, false).unwrap().archives.is_empty());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn check_archives_uploaded_as_finished() {
+        let base = std::env::temp_dir().join(format!("zipper_uploader_test_{}", std::process::id()));
+        let staging = base.join("zipped");
+        fs::create_dir_all(staging.join("ctg")).unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(base.join("stored")));
+        let observer = crate::api::NoObserver;
+
+        // A completed set is stored, with each archive's size and checksum checked.
+
+        let summary = make_set(&staging.join("ctg"), "250301", 2);
+        let uploader = ArchiveUploader::start(storage.clone(), "ctg", &staging, &observer, &CancelToken::new());
+        for a in &summary.archives {
+            uploader.archive_finished(a);
+        }
+        uploader.finish(Ok(summary), false).unwrap();
+        assert_eq!(storage.list("ctg/").unwrap().len(), 3);
+        assert_eq!(storage.sha256("ctg/ctg 250301 1 to 10.zip").unwrap(),
+                   Some(sha256_hex(&mut "archive 250301 0".as_bytes()).unwrap()));
+        assert!(!staging.join("ctg").join("ctg 250301 1 to 10.zip").exists());
+
+        // A failed zip leaves none of its archives in storage.
+
+        let summary = make_set(&staging.join("ctg"), "250401", 2);
+        let uploader = ArchiveUploader::start(storage.clone(), "ctg", &staging, &observer, &CancelToken::new());
+        uploader.archive_finished(&summary.archives[0]);
+        assert!(matches!(uploader.finish(Err(AppError::Cancelled), true), Err(AppError::Cancelled)));
+        assert_eq!(storage.list("ctg/ctg 250401").unwrap(), vec![]);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+
+    #[test]
+    fn check_backends_need_their_features() {
+        use crate::setup::config_reader::S3Pars;
+
+        let s3 = StorageBackend::S3(S3Pars {
+            endpoint: "http://localhost:9000".to_string(), bucket: "mdr".to_string(), region: "us-east-1".to_string(),
+            prefix: String::new(), access_key: "minio".to_string(), secret_key: "minio123".to_string(),
+        });
+        assert!(check_backend_built(&StorageBackend::Local(None)).is_ok());
+        assert_eq!(check_backend_built(&s3).is_ok(), cfg!(feature = "s3"));
+        let pars = StoragePars { backend: s3, keep_local: true };
+        match open_storage(&pars, Path::new(".")) {
+            Ok(storage) => assert!(cfg!(feature = "s3") && storage.describe().contains("mdr")),
+            Err(e) => assert!(!cfg!(feature = "s3") && e.to_string().contains("feature")),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+use crate::err::AppError;
+use crate::setup::config_reader::SftpPars;
+use crate::storage::{copy_buffered, sha256_hex, Storage, StoredObject};
+
+// Objects held as files on a backup host, reached over SFTP, with the '/' separated parts of each name
+// as folders under the configured root (or under the login folder if there is none). Only key based
+// authentication is used, and the host's key must be listed in the known_hosts file. Files are written
+// under a temporary name and renamed when complete. Checksums are found on the host with sha256sum,
+// where the account can run commands, or otherwise by reading the file back.
+// One connection is kept open, and re-made after any failure.
+
+const TIMEOUT_SECS: u64 = 60;
+
+struct Connection {
+    session: Session,
+    sftp: Sftp,
+}
+
+pub struct SftpStorage {
+    pars: SftpPars,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl SftpStorage {
+
+    pub fn new(pars: &SftpPars) -> SftpStorage {
+        SftpStorage { pars: pars.clone(), connection: Mutex::new(None) }
+    }
+
+    fn path(&self, name: &str) -> String {
+        if self.pars.root.is_empty() {
+            name.to_string()
+        }
+        else {
+            format!("{}/{}", self.pars.root, name)
+        }
+    }
+
+    fn connect(&self) -> Result<Connection, AppError> {
+        let failed = |e: &dyn Display| AppError::StorageError(format!("Unable to connect to {}.", self.describe()), e.to_string());
+
+        let address = (self.pars.host.as_str(), self.pars.port).to_socket_addrs()
+                .map_err(|e| failed(&e))?
+                .next()
+                .ok_or_else(|| failed(&"the host name could not be resolved"))?;
+        let tcp = TcpStream::connect_timeout(&address, Duration::from_secs(TIMEOUT_SECS))
+                .map_err(|e| failed(&e))?;
+        let mut session = Session::new().map_err(|e| failed(&e))?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout((TIMEOUT_SECS * 1000) as u32);
+        session.handshake().map_err(|e| failed(&e))?;
+
+        let mut known_hosts = session.known_hosts().map_err(|e| failed(&e))?;
+        known_hosts.read_file(&self.pars.known_hosts, KnownHostFileKind::OpenSSH)
+                .map_err(|e| failed(&format!("{:?} could not be read: {}", self.pars.known_hosts, e)))?;
+        let (host_key, _) = session.host_key().ok_or_else(|| failed(&"the host did not give a key"))?;
+        match known_hosts.check_port(&self.pars.host, self.pars.port, host_key) {
+            CheckResult::Match => (),
+            CheckResult::NotFound => return Err(failed(&format!("the host's key is not listed in {:?} (add it with ssh-keyscan)", self.pars.known_hosts))),
+            CheckResult::Mismatch => return Err(failed(&format!("the host's key does not match that listed in {:?}", self.pars.known_hosts))),
+            CheckResult::Failure => return Err(failed(&"the host's key could not be checked")),
+        }
+
+        session.userauth_pubkey_file(&self.pars.user, None, &self.pars.key_file, self.pars.key_passphrase.as_deref())
+                .map_err(|e| failed(&format!("key authentication as {} failed: {}", self.pars.user, e)))?;
+        let sftp = session.sftp().map_err(|e| failed(&e))?;
+        Ok(Connection { session, sftp })
+    }
+
+    fn with_connection<T>(&self, work: impl FnOnce(&Connection) -> Result<T, AppError>) -> Result<T, AppError> {
+
+        // The connection is dropped after an error, as it may no longer be usable.
+
+        let mut guard = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.is_none() {
+            *guard = Some(self.connect()?);
+        }
+        let res = work(guard.as_ref().expect("connection is made above"));
+        if res.is_err() {
+            *guard = None;
+        }
+        res
+    }
+
+    fn error(&self, action: &str, name: &str, e: impl Display) -> AppError {
+        AppError::StorageError(format!("Unable to {} {} on {}.", action, name, self.describe()), e.to_string())
+    }
+}
+
+impl Storage for SftpStorage {
+
+    fn describe(&self) -> String {
+        format!("sftp://{}@{}:{}/{}", self.pars.user, self.pars.host, self.pars.port, self.pars.root.trim_start_matches('/'))
+    }
+
+    fn put(&self, name: &str, source: &mut dyn Read, _length: u64, _sha256: Option<&str>) -> Result<(), AppError> {
+        let path = self.path(name);
+        self.with_connection(|c| {
+            if let Some((folder, _)) = path.rsplit_once('/') {
+                make_folders(&c.sftp, folder).map_err(|e| self.error("store", name, e))?;
+            }
+            let part_path = format!("{}.part", path);
+            let mut file = c.sftp.create(Path::new(&part_path)).map_err(|e| self.error("store", name, e))?;
+            copy_buffered(source, &mut file).map_err(|e| self.error("store", name, e))?;
+            drop(file);
+
+            // Renaming over an existing file is not possible with SFTP version 3, so it is removed first.
+
+            if c.sftp.stat(Path::new(&path)).is_ok() {
+                c.sftp.unlink(Path::new(&path)).map_err(|e| self.error("replace", name, e))?;
+            }
+            c.sftp.rename(Path::new(&part_path), Path::new(&path), None).map_err(|e| self.error("store", name, e))
+        })
+    }
+
+    fn get(&self, name: &str, destination: &mut dyn Write) -> Result<u64, AppError> {
+        self.with_connection(|c| {
+            let mut file = c.sftp.open(Path::new(&self.path(name))).map_err(|e| self.error("fetch", name, e))?;
+            copy_buffered(&mut file, destination).map_err(|e| self.error("fetch", name, e))
+        })
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, AppError> {
+
+        // As with local storage, only the folder named by the prefix is searched. A folder that
+        // does not exist (SFTP status 2, 'no such file') holds no objects.
+
+        let (folder, strip) = match prefix.rsplit_once('/') {
+            Some((dir, _)) => (self.path(dir), self.path(dir).len() - dir.len()),
+            None => (self.pars.root.clone(), self.path("").len()),
+        };
+        self.with_connection(|c| {
+            let mut objects: Vec<StoredObject> = Vec::new();
+            match collect_objects(&c.sftp, &folder, strip, &mut objects) {
+                Err(e) if e.code() == ssh2::ErrorCode::SFTP(2) => (),
+                res => res.map_err(|e| self.error("list", prefix, e))?,
+            }
+            objects.retain(|o| o.name.starts_with(prefix) && !o.name.ends_with(".part"));
+            Ok(objects)
+        })
+    }
+
+    fn delete(&self, name: &str) -> Result<(), AppError> {
+        self.with_connection(|c| c.sftp.unlink(Path::new(&self.path(name))).map_err(|e| self.error("delete", name, e)))
+    }
+
+    fn sha256(&self, name: &str) -> Result<Option<String>, AppError> {
+        let path = self.path(name);
+        self.with_connection(|c| {
+            if let Some(checksum) = remote_sha256(&c.session, &path) {
+                return Ok(Some(checksum));
+            }
+            let mut file = c.sftp.open(Path::new(&path)).map_err(|e| self.error("read back", name, e))?;
+            sha256_hex(&mut file).map(Some).map_err(|e| self.error("read back", name, e))
+        })
+    }
+}
+
+
+fn make_folders(sftp: &Sftp, folder: &str) -> Result<(), ssh2::Error> {
+    let mut path = String::new();
+    for part in folder.split('/') {
+        if !path.is_empty() || folder.starts_with('/') {
+            path.push('/');
+        }
+        path.push_str(part);
+        if !part.is_empty() && sftp.stat(Path::new(&path)).is_err() {
+            sftp.mkdir(Path::new(&path), 0o755)?;
+        }
+    }
+    Ok(())
+}
+
+
+fn collect_objects(sftp: &Sftp, folder: &str, strip: usize, objects: &mut Vec<StoredObject>) -> Result<(), ssh2::Error> {
+    let folder = if folder.is_empty() { "." } else { folder };
+    for (path, stat) in sftp.readdir(Path::new(folder))? {
+        let path = path.to_string_lossy().replace('\\', "/");
+        if stat.is_dir() {
+            collect_objects(sftp, &path, strip, objects)?;
+        }
+        else if let Some(name) = path.strip_prefix("./").unwrap_or(&path).get(strip..) {
+            objects.push(StoredObject { name: name.to_string(), size: stat.size.unwrap_or(0) });
+        }
+    }
+    Ok(())
+}
+
+
+fn remote_sha256(session: &Session, path: &str) -> Option<String> {
+
+    // Returns None if the command cannot be run, e.g. on an SFTP only account.
+
+    let mut channel = session.channel_session().ok()?;
+    channel.exec(&format!("sha256sum -- '{}'", path.replace('\'', "'\\''"))).ok()?;
+    let mut output = String::new();
+    channel.read_to_string(&mut output).ok()?;
+    channel.wait_close().ok()?;
+    if channel.exit_status().ok()? != 0 {
+        return None;
+    }
+    let checksum = output.split_whitespace().next()?.to_lowercase();
+    (checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())).then_some(checksum)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    #[ignore]
+    fn check_round_trip_with_sftp_server() {
+
+        // Run against a local sshd container, e.g.
+        //   docker run -d -p 2222:2222 -e USER_NAME=zipper -e PUBLIC_KEY="$(cat ~/.ssh/id_ed25519.pub)" linuxserver/openssh-server
+        //   ssh-keyscan -p 2222 localhost >> ~/.ssh/known_hosts
+        // then ZIPPER_TEST_SFTP_PORT=2222 ZIPPER_TEST_SFTP_USER=zipper ZIPPER_TEST_SFTP_KEY=~/.ssh/id_ed25519 cargo test --features sftp -- --ignored
+
+        let env = |k: &str| std::env::var(k).expect("SFTP test variables should be set");
+        let home = std::env::var("HOME").unwrap_or_default();
+        let storage = SftpStorage::new(&SftpPars {
+            host: std::env::var("ZIPPER_TEST_SFTP_HOST").unwrap_or("localhost".to_string()),
+            port: env("ZIPPER_TEST_SFTP_PORT").parse().unwrap(),
+            user: env("ZIPPER_TEST_SFTP_USER"),
+            key_file: PathBuf::from(env("ZIPPER_TEST_SFTP_KEY").replacen('~', &home, 1)),
+            key_passphrase: None,
+            known_hosts: PathBuf::from(format!("{}/.ssh/known_hosts", home)),
+            root: format!("zipper_test_{}", std::process::id()),
+        });
+
+        let content = b"archive content".to_vec();
+        storage.put("ctg/ctg 250101 1 to 10.zip", &mut content.as_slice(), content.len() as u64, None).unwrap();
+        assert_eq!(storage.list("ctg/").unwrap(),
+                   vec![StoredObject { name: "ctg/ctg 250101 1 to 10.zip".to_string(), size: content.len() as u64 }]);
+        assert_eq!(storage.sha256("ctg/ctg 250101 1 to 10.zip").unwrap(), Some(sha256_hex(&mut content.as_slice()).unwrap()));
+        let mut fetched = Vec::new();
+        storage.get("ctg/ctg 250101 1 to 10.zip", &mut fetched).unwrap();
+        assert_eq!(fetched, content);
+        storage.delete("ctg/ctg 250101 1 to 10.zip").unwrap();
+        assert!(storage.list("ctg/").unwrap().is_empty());
+    }
+}
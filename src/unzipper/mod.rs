@@ -1,13 +1,21 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
-use log::info;
+use log::{error, info};
 use crate::err::AppError;
-use crate::SourceDetails;
+use crate::{ArchiveDetails, ArchiveSummary, SourceDetails};
+use crate::catalogue;
+use crate::setup::log_helper::archive_context;
+use crate::api::ArchiveObserver;
+use crate::cancel::CancelToken;
+use crate::archive_sets;
 use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::io::{copy, sink};
 use zip::ZipArchive;
-use zip_extensions::read::zip_extract;
+use zip::result::ZipError;
 
-pub fn unzip_folder(zipped_source: &PathBuf, unzipped_destination: &PathBuf) -> Result<usize, AppError>{
+pub fn unzip_folder(zipped_source: &Path, unzipped_destination: &Path, observer: &dyn ArchiveObserver,
+                    cancel: &CancelToken) -> Result<ArchiveSummary, AppError>{
 
     // check source folder exists, destination can be created if necessary.
 
@@ -18,22 +26,138 @@ pub fn unzip_folder(zipped_source: &PathBuf, unzipped_destination: &PathBuf) ->
         return Result::Err(AppError::FileSystemError(problem, detail));
     }
 
-    let file = File::open(&zipped_source)?;
-    let archive = ZipArchive::new(file)
-            .map_err(|e| AppError::UnzipError(e, unzipped_destination.to_owned()))?;
+    let mut summary = ArchiveSummary::default();
+    summary.add_archive(zipped_source, false)?;
 
-    zip_extract(zipped_source, unzipped_destination)
-            .map_err(|e| AppError::UnzipError(e, unzipped_destination.to_owned()))?;
+    // The totals for the whole archive are already in the summary, so the counts returned are not needed.
 
-    Ok(archive.len())
+    let mut archive = open_archive(zipped_source)?;
+    extract_entries(zipped_source, &mut archive, unzipped_destination, &|_| true, &mut summary, observer, cancel)?;
+    observer.archive_finished(&summary.archives[0]);
+
+    Ok(summary)
+}
+
+pub fn unzip_filtered_archive(zipped_source: &Path, unzipped_destination: &Path, is_selected: &dyn Fn(&str) -> bool,
+                              observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
+
+    // Used by jobs. Extracts the entries that pass the job's filters, applied to the entry names.
+
+    if !file_exists(zipped_source) {
+        let problem = "There is a problem accessing a designated folder or file".to_string();
+        let detail = format!("Archive {:?} does not appear to exist.", zipped_source);
+        return Result::Err(AppError::FileSystemError(problem, detail));
+    }
+
+    info!("Unzipping files from {:?} to {:?}", zipped_source, unzipped_destination);
+
+    let mut archive = open_archive(zipped_source)?;
+    let mut selected = (0, 0);
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)
+                .map_err(|e| AppError::UnzipError(e, zipped_source.to_owned()))?;
+        if !entry.is_dir() && is_selected(entry.name()) {
+            selected = (selected.0 + 1, selected.1 + entry.size());
+        }
+    }
+    observer.totals_found(selected.0, selected.1);
+
+    let mut summary = ArchiveSummary::default();
+    let (file_count, bytes_in, bytes_out) = extract_entries(zipped_source, &mut archive, unzipped_destination, 
+                                                            is_selected, &mut summary, observer, cancel)?;
+    summary.file_count = file_count;
+    summary.bytes_in = bytes_in;
+    summary.bytes_out = bytes_out;
+    summary.archives.push(ArchiveDetails::new(zipped_source, file_count));
+    observer.archive_finished(&summary.archives[0]);
+
+    let _ctx = archive_context(zipped_source, summary.file_count);
+    info!("{} of {} files extracted from {:?}", summary.file_count, archive.len(), zipped_source);
+    Ok(summary)
+}
+
+
+fn extract_entries(zipped_source: &Path, archive: &mut ZipArchive<File>, unzipped_destination: &Path, is_selected: &dyn Fn(&str) -> bool,
+                   summary: &mut ArchiveSummary, observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<(usize, u64, u64), AppError> {
+
+    // Extracts the selected entries, returning the number of files, and the bytes read (compressed) and written.
+    // enclosed_name guards against entries that would be written outside the destination - they are skipped with a warning.
+
+    observer.archive_opened(&zipped_source.file_name().unwrap_or_default().to_string_lossy());
+    let (mut file_count, mut bytes_in, mut bytes_out) = (0, 0, 0);
+
+    for i in 0..archive.len() {
+        cancel.check()?;
+        let mut entry = archive.by_index(i)
+                .map_err(|e| AppError::UnzipError(e, zipped_source.to_owned()))?;
+        if !is_selected(entry.name()) {
+            continue;
+        }
+        let rel_path = match entry.enclosed_name() {
+            Some(p) => p,
+            None => {
+                summary.add_warning(format!("Entry {} in {:?} has an unsafe path and has not been extracted", entry.name(), zipped_source));
+                continue;
+            }
+        };
+        let out_path = unzipped_destination.join(rel_path);
+        if entry.is_dir() {
+            fs::create_dir_all(&out_path)
+                    .map_err(|e| AppError::IoWriteErrorWithPath(e, out_path.to_owned()))?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out_file = File::create(&out_path)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, out_path.to_owned()))?;
+        let bytes_written = copy(&mut entry, &mut out_file)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, out_path.to_owned()))?;
+
+        observer.file_processed(entry.name(), bytes_written);
+        file_count += 1;
+        bytes_in += entry.compressed_size();
+        bytes_out += bytes_written;
+    }
+    Ok((file_count, bytes_in, bytes_out))
+}
+
+
+fn open_archive(archive_path: &Path) -> Result<ZipArchive<File>, AppError> {
+    let file = File::open(archive_path)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, archive_path.to_owned()))?;
+    ZipArchive::new(file)
+            .map_err(|e| AppError::UnzipError(e, archive_path.to_owned()))
 }
 
-pub fn unzip_mdr_folder(source: SourceDetails, parent_zipped_src_fdr: &PathBuf, parent_unzipped_dest_fdr: &PathBuf) -> Result<usize, AppError> {
+
+fn get_archive_totals(archive_paths: &[PathBuf]) -> Result<(usize, u64), AppError> {
+
+    // The number of files and their uncompressed size, read from the archives' central directories.
+
+    let (mut files, mut bytes) = (0, 0);
+    for p in archive_paths {
+        let mut archive = open_archive(p)?;
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i)
+                    .map_err(|e| AppError::UnzipError(e, p.to_owned()))?;
+            if !entry.is_dir() {
+                files += 1;
+                bytes += entry.size();
+            }
+        }
+    }
+    Ok((files, bytes))
+}
+
+
+pub fn unzip_mdr_folder(source: SourceDetails, parent_zipped_src_fdr: &PathBuf, parent_unzipped_dest_fdr: &PathBuf,
+                        observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
 
     // both source and destination PARENT folders already confirmed to exist
 
     let database_name = PathBuf::from(source.database_name);
-    if database_name == PathBuf::from("".to_string()) {
+    if database_name.as_os_str().is_empty() {
         let p = "No database name in Source details".to_string();
         let d = "Unable to unzip correspondig archive".to_string();
         return Err(AppError::FileSystemError(p, d));
@@ -44,40 +168,280 @@ pub fn unzip_mdr_folder(source: SourceDetails, parent_zipped_src_fdr: &PathBuf,
 
     info!("Unzipping files from {:?} to {:?}", srce_folder, dest_folder);
     
-    // get each zip file in the source folder... (each source has one or more zip files in the associated folder)
-    // Zip files are arranged in a single list, with no hierarchy of folders within each source's folder.
+    // Only the source's most recent archive set is unzipped. Older sets may still be in the folder (until
+    // pruned), and their files would otherwise overwrite newer versions, depending on the order read.
     // No need to delete existing files in dest folder - they will be over-written if necessary.
 
-    let entries = fs::read_dir(&srce_folder)
-                .map_err(|e| AppError::IoReadErrorWithPath(e, srce_folder))?;
-
-    let mut file_num = 0;
-
-    for e in entries {
-         let src_path = e?.path();
-         if src_path.is_file() {
-            match src_path.extension() {
-                Some(s) => {
-                    if s == PathBuf::from("zip") {
-                        file_num += unzip_folder(&src_path, &dest_folder)?;
-                        info!("{:?} unzipped. Total files generated so far: {}", src_path, file_num);
-                    } 
-                    else {
-                        continue;
+    let zip_paths: Vec<PathBuf> = archive_sets::get_latest_archive_set(&database_name.to_string_lossy(), &srce_folder)?
+                .into_iter()
+                .map(|a| a.path)
+                .collect();
+    let (file_total, byte_total) = get_archive_totals(&zip_paths)?;
+    observer.totals_found(file_total, byte_total);
+
+    let mut summary = ArchiveSummary::default();
+
+    for src_path in zip_paths {
+        let archive_summary = unzip_folder(&src_path, &dest_folder, observer, cancel)?;
+        let _ctx = archive_context(&src_path, archive_summary.file_count);
+        summary.merge(archive_summary);
+        info!("{:?} unzipped. Total files generated so far: {}", src_path, summary.file_count);
+    }
+    
+    info!("Files generated in total: {}", summary.file_count);
+    Ok(summary)
+
+}
+
+
+pub fn extract_mdr_files(source: SourceDetails, file_list: &[String], parent_zipped_src_fdr: &Path, parent_unzipped_dest_fdr: &Path,
+                         observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
+
+    // Used with -x. Extracts only the named files (or the files of the named trials) 
+    // from a single source's archives, rather than unpacking the whole archive set.
+
+    let database_name = source.database_name;
+    if database_name.trim() == "" {
+        let p = "No database name in Source details".to_string();
+        let d = "Unable to extract from corresponding archives".to_string();
+        return Err(AppError::FileSystemError(p, d));
+    }
+
+    let srce_folder: PathBuf = [parent_zipped_src_fdr, Path::new(&database_name)].iter().collect();
+    let dest_folder: PathBuf = [parent_unzipped_dest_fdr, Path::new(&database_name)].iter().collect();
+
+    info!("Extracting {} selected files from {:?} to {:?}", file_list.len(), srce_folder, dest_folder);
+
+    // Trial ids are turned into file names by adding the json extension. For sources with
+    // grouped files a name may be given with its folder ('folder/file'), which allows the 
+    // folder range in each archive's name to be used to skip irrelevant archives.
+
+    let mut wanted: HashSet<String> = file_list.iter()
+                .map(|f| catalogue::normalise_file_name(f))
+                .collect();
+    observer.totals_found(wanted.len(), 0);
+
+    let archives = archive_sets::get_latest_archive_set(&database_name, &srce_folder)?;
+    if archives.is_empty() {
+        let p = "There is a problem accessing a designated folder or file".to_string();
+        let d = format!("No archives found for {} in {:?}", database_name, srce_folder);
+        return Err(AppError::FileSystemError(p, d));
+    }
+
+    // If the set has a catalogue index it identifies the archives needed directly.
+
+    let indexed: Option<HashSet<String>> = catalogue::read_index(&database_name, &archives[0].date, &srce_folder)?
+                .map(|c| wanted.iter().flat_map(|w| c.find(w)).map(|e| e.archive.clone()).collect());
+
+    let mut summary = ArchiveSummary::default();
+
+    for a in archives {
+
+        if wanted.is_empty() {
+            break;
+        }
+        
+        match &indexed {
+            Some(names) => {
+                let archive_name = a.path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                if !names.contains(archive_name) {
+                    continue;
+                }
+            },
+            None => {
+                if source.local_files_grouped && !wanted.iter().any(|w| archive_sets::archive_may_hold(&a, w)) {
+                    continue;
+                }
+            },
+        }
+
+        let file = File::open(&a.path)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, a.path.to_owned()))?;
+        let mut archive = ZipArchive::new(file)
+                .map_err(|e| AppError::UnzipError(e, a.path.to_owned()))?;
+
+        // Match either on the full entry name or on the final (file name) part of it.
+        
+        let matches: Vec<(String, String)> = archive.file_names()
+                .filter_map(|n| {
+                    let file_part = n.rsplit('/').next().unwrap_or(n);
+                    if wanted.contains(n) {
+                        Some((n.to_string(), n.to_string()))
+                    } else if wanted.contains(file_part) {
+                        Some((n.to_string(), file_part.to_string()))
+                    } else {
+                        None
                     }
-                },
-                None => continue, 
+                })
+                .collect();
+
+        if matches.is_empty() {
+            continue;
+        }
+        observer.archive_opened(a.file_name());
+        summary.archives.push(ArchiveDetails::new(&a.path, matches.len()));
+
+        for (entry_name, key) in matches {
+
+            cancel.check()?;
+
+            let mut entry = archive.by_name(&entry_name)
+                    .map_err(|e| AppError::UnzipError(e, a.path.to_owned()))?;
+
+            // enclosed_name guards against entries that would be written outside the destination.
+
+            let rel_path = match entry.enclosed_name() {
+                Some(p) => p,
+                None => {
+                    summary.add_warning(format!("Entry {} in {:?} has an unsafe path and has not been extracted", entry_name, a.path));
+                    continue;
+                }
             };
+            let out_path = dest_folder.join(rel_path);
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut out_file = File::create(&out_path)
+                    .map_err(|e| AppError::IoWriteErrorWithPath(e, out_path.to_owned()))?;
+            let bytes_written = copy(&mut entry, &mut out_file)
+                    .map_err(|e| AppError::IoWriteErrorWithPath(e, out_path.to_owned()))?;
+
+            info!("{} extracted from {:?}", entry_name, a.path);
+            wanted.remove(&key);
+            summary.file_count += 1;
+            summary.bytes_in += entry.compressed_size();
+            summary.bytes_out += bytes_written;
+            observer.file_processed(&entry_name, bytes_written);
+        }
+        if let Some(details) = summary.archives.last() {
+            observer.archive_finished(details);
         }
     }
-    
-    info!("Files generated in total: {}", file_num);
-    Ok(file_num)
 
+    for w in &wanted {
+        summary.add_warning(format!("{} could not be found in any archive for {}", w, database_name));
+    }
+
+    info!("Files extracted in total: {}", summary.file_count);
+    Ok(summary)
+}
+
+
+pub fn verify_folder(zipped_source: &Path, observer: &dyn ArchiveObserver) -> Result<ArchiveSummary, AppError> {
+
+    // Used with verify -f. Checks the single designated archive.
+
+    if !file_exists(zipped_source) {
+        let problem = "There is a problem accessing a designated folder or file".to_string();
+        let detail = "Archive file to be verified does not appear to exist.".to_string();
+        return Result::Err(AppError::FileSystemError(problem, detail));
+    }
+
+    let (file_total, byte_total) = get_archive_totals(&[zipped_source.to_owned()])?;
+    observer.totals_found(file_total, byte_total);
+    observer.archive_opened(&zipped_source.file_name().unwrap_or_default().to_string_lossy());
+    verify_archive(zipped_source, None, observer)
+        .map_err(|e| AppError::CorruptArchive(format!("{:?} failed verification", zipped_source), e))?;
+    let mut summary = ArchiveSummary::default();
+    summary.add_archive(zipped_source, false)?;
+    observer.archive_finished(&summary.archives[0]);
+    let _ctx = archive_context(zipped_source, summary.file_count);
+    info!("{:?} verified, {} files", zipped_source, summary.file_count);
+    Ok(summary)
+}
+
+
+pub fn verify_mdr_folder(source: SourceDetails, parent_zipped_src_fdr: &Path, observer: &dyn ArchiveObserver,
+                         cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
+
+    // Used with verify. Every entry of every archive in the source's latest set is read, 
+    // so that its crc32 checksum is checked, and if the set has a catalogue index the 
+    // entries are also checked against it. All archives are checked before any problems are reported.
+    // A cancelled verification stops before the next archive.
+
+    let database_name = source.database_name;
+    let srce_folder = parent_zipped_src_fdr.join(&database_name);
+    let set = archive_sets::get_latest_archive_set(&database_name, &srce_folder)?;
+    if set.is_empty() {
+        let p = "There is a problem accessing a designated folder or file".to_string();
+        let d = format!("No archives found for {} in {:?}", database_name, srce_folder);
+        return Err(AppError::FileSystemError(p, d));
+    }
+
+    let catalogue = catalogue::read_index(&database_name, &set[0].date, &srce_folder)?;
+    let (file_total, byte_total) = get_archive_totals(&set.iter().map(|a| a.path.clone()).collect::<Vec<PathBuf>>())?;
+    observer.totals_found(file_total, byte_total);
+
+    let mut summary = ArchiveSummary::default();
+    let mut problems: Vec<String> = Vec::new();
+
+    for a in &set {
+        cancel.check()?;
+        let indexed = catalogue.as_ref().map(|c| c.entries.iter()
+                        .filter(|e| e.archive == a.file_name())
+                        .map(|e| (e.name.clone(), e.crc32))
+                        .collect::<HashMap<String, u32>>());
+        observer.archive_opened(a.file_name());
+        match verify_archive(&a.path, indexed.as_ref(), observer) {
+            Ok(n) => {
+                summary.add_archive(&a.path, false)?;
+                if let Some(details) = summary.archives.last() {
+                    observer.archive_finished(details);
+                }
+                let _ctx = archive_context(&a.path, n);
+                info!("{:?} verified, {} files", a.path, n);
+            },
+            Err(e) => {
+                let _ctx = archive_context(&a.path, 0);
+                error!("{:?} failed verification: {}", a.path, e);
+                problems.push(format!("{}: {}", a.file_name(), e));
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        let p = format!("{} of {} archives for {} failed verification", problems.len(), set.len(), database_name);
+        return Err(AppError::CorruptArchive(p, problems.join("\n")));
+    }
+    Ok(summary)
+}
+
+
+fn verify_archive(archive_path: &Path, indexed: Option<&HashMap<String, u32>>, observer: &dyn ArchiveObserver) -> Result<usize, String> {
+
+    // Reading each entry to its end makes the zip crate compare its crc32 with the stored value.
+    // Problems are returned as text so that they can be collected by the caller.
+
+    let file = File::open(archive_path).map_err(|e| e.to_string())?;
+    let mut archive = ZipArchive::new(file).map_err(|e| e.to_string())?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| e.to_string())?;
+        let bytes_read = copy(&mut entry, &mut sink())
+                .map_err(|e| format!("entry {}: {}", entry.name(), ZipError::Io(e)))?;
+        if !entry.is_dir() {
+            observer.file_processed(entry.name(), bytes_read);
+        }
+
+        if let Some(idx) = indexed {
+            match idx.get(entry.name()) {
+                Some(crc) if *crc != entry.crc32() => return Err(format!("entry {}: crc32 differs from catalogue index", entry.name())),
+                None => return Err(format!("entry {}: not listed in catalogue index", entry.name())),
+                _ => {},
+            }
+        }
+    }
+
+    if let Some(idx) = indexed {
+        if idx.len() != archive.len() {
+            return Err(format!("{} entries in archive but {} in catalogue index", archive.len(), idx.len()));
+        }
+    }
+    Ok(archive.len())
 }
 
 
-fn file_exists(file_path: &PathBuf) -> bool {
+fn file_exists(file_path: &Path) -> bool {
     let xres = file_path.try_exists();
     match xres {
         Ok(true) => true,
@@ -0,0 +1,73 @@
+mod monitor;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use log::{error, info};
+use sqlx::{Pool, Postgres};
+use crate::err::AppError;
+use crate::{data, setup};
+use crate::cancel::CancelToken;
+use crate::setup::InitParams;
+use monitor::ChangeMonitor;
+
+// In watch mode the unzipped folders of the selected sources are monitored, using the platform's
+// change notification (see monitor.rs). Once a source has had no further changes
+// for the quiet period its folder is zipped, exactly as by 'zip -s {id}', and the run recorded.
+// Only changes made after the watch starts are acted on. The watch stops on SIGTERM or SIGINT,
+// which also cancels any zip in progress.
+
+pub async fn run_watch(params: &InitParams, source_list: Vec<i32>, config_string: &str, pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    let quiet_period = Duration::from_secs(params.quiet_period_secs);
+    let mut monitor = ChangeMonitor::new()?;
+
+    for source_id in &source_list {
+        let source = data::get_source_details(*source_id, pool).await?;
+        let folder = params.mdr_unzipped.join(&source.database_name);
+        monitor.add_source(*source_id, &folder, source.local_files_grouped)?;
+        info!("Watching {:?} for source {} ({})", folder, source_id, source.database_name);
+    }
+
+    let cancel = CancelToken::on_signal();
+    let mut last_changed: HashMap<i32, Instant> = HashMap::new();
+
+    while !cancel.is_cancelled() {
+
+        let now = Instant::now();
+        for source_id in monitor.changed_sources()? {
+            if last_changed.insert(source_id, now).is_none() {
+                info!("Changes detected for source {}", source_id);
+            }
+        }
+
+        let mut quiet: Vec<i32> = last_changed.iter()
+            .filter(|(_, t)| now.duration_since(**t) >= quiet_period)
+            .map(|(id, _)| *id)
+            .collect();
+        quiet.sort();
+
+        for source_id in quiet {
+            last_changed.remove(&source_id);
+            info!("No changes for source {} in {} seconds - zipping", source_id, quiet_period.as_secs());
+
+            // A failed zip is logged, and the watch continues.
+
+            let command = vec!["zip".to_string(), "-s".to_string(), source_id.to_string()];
+            let res = match setup::get_command_params(&command, config_string) {
+                Ok(p) => crate::execute(&p, &cancel, pool).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                error!("Zipping source {} failed: {}", source_id, e);
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    if !last_changed.is_empty() {
+        info!("Sources changed but not yet zipped: {:?}", last_changed.keys().collect::<Vec<&i32>>());
+    }
+    info!("Watch stopped");
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use log::warn;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::err::AppError;
+
+// Uses the notify crate, and so the platform's own change notification (inotify on Linux,
+// FSEvents on macOS, ReadDirectoryChangesW on Windows). Events are queued on a channel by the
+// watcher's thread, and each is mapped back to a source by the watched folder it falls within.
+// For sources with grouped files the folder is watched recursively, which also covers any
+// sub-folders created while the watch is running.
+
+pub struct ChangeMonitor {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    folders: Vec<(PathBuf, i32)>,
+}
+
+impl ChangeMonitor {
+
+    pub fn new() -> Result<ChangeMonitor, AppError> {
+        let (sender, events) = channel();
+        let watcher = notify::recommended_watcher(sender)
+            .map_err(|e| AppError::IoError(io::Error::other(e)))?;
+        Ok(ChangeMonitor { watcher, events, folders: Vec::new() })
+    }
+
+
+    pub fn add_source(&mut self, source_id: i32, folder: &Path, files_grouped: bool) -> Result<(), AppError> {
+
+        // The canonical path is watched, as that is the form in which some platforms report events.
+
+        let folder = folder.canonicalize()
+            .map_err(|e| AppError::IoReadErrorWithPath(e, folder.to_owned()))?;
+        let mode = if files_grouped { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        self.watcher.watch(&folder, mode)
+            .map_err(|e| AppError::IoReadErrorWithPath(io::Error::other(e), folder.to_owned()))?;
+        self.folders.push((folder, source_id));
+        Ok(())
+    }
+
+
+    pub fn changed_sources(&mut self) -> Result<Vec<i32>, AppError> {
+
+        // Takes all the events queued since the last call, and returns the sources they relate to.
+        // Files only being read are not changes. If the watcher reports an error, or that events
+        // have been lost, every source is taken as changed.
+
+        let mut changed: Vec<i32> = Vec::new();
+        for res in self.events.try_iter() {
+            let event = match res {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("Error reported while watching folders: {}", e);
+                    changed.extend(self.folders.iter().map(|(_, id)| *id));
+                    continue;
+                },
+            };
+            if event.need_rescan() {
+                changed.extend(self.folders.iter().map(|(_, id)| *id));
+                continue;
+            }
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            for path in &event.paths {
+                if let Some((_, id)) = self.folders.iter()
+                        .filter(|(f, _)| path.starts_with(f))
+                        .max_by_key(|(f, _)| f.components().count()) {
+                    changed.push(*id);
+                }
+            }
+        }
+
+        changed.sort();
+        changed.dedup();
+        Ok(changed)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    fn wait_for_changes(monitor: &mut ChangeMonitor) -> Vec<i32> {
+
+        // Events arrive from the watcher's thread, so are waited for (briefly).
+
+        let start = Instant::now();
+        let mut changed = Vec::new();
+        while start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(100));
+            changed.extend(monitor.changed_sources().unwrap());
+            if !changed.is_empty() {
+                std::thread::sleep(Duration::from_millis(100));
+                changed.extend(monitor.changed_sources().unwrap());
+                break;
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+
+    #[test]
+    fn check_changes_reported_for_source_and_new_groups() {
+
+        let folder = std::env::temp_dir().join(format!("zipper_watch_test_{}", std::process::id()));
+        let other = std::env::temp_dir().join(format!("zipper_watch_test_other_{}", std::process::id()));
+        fs::create_dir_all(folder.join("NCT0000")).unwrap();
+        fs::create_dir_all(&other).unwrap();
+
+        let mut monitor = ChangeMonitor::new().unwrap();
+        monitor.add_source(100120, &folder, true).unwrap();
+        monitor.add_source(100126, &other, false).unwrap();
+        assert!(monitor.changed_sources().unwrap().is_empty());
+
+        fs::write(folder.join("NCT0000").join("NCT00000102.json"), "{}").unwrap();
+        let in_group = wait_for_changes(&mut monitor);
+
+        // A group folder created during the watch is also watched.
+
+        fs::create_dir(folder.join("NCT0001")).unwrap();
+        let new_group = wait_for_changes(&mut monitor);
+        fs::write(folder.join("NCT0001").join("NCT00010104.json"), "{}").unwrap();
+        let in_new_group = wait_for_changes(&mut monitor);
+
+        fs::write(other.join("ISRCTN10000001.json"), "{}").unwrap();
+        let in_other = wait_for_changes(&mut monitor);
+
+        fs::remove_dir_all(&folder).unwrap();
+        fs::remove_dir_all(&other).unwrap();
+        assert_eq!(in_group, vec![100120]);
+        assert_eq!(new_group, vec![100120]);
+        assert_eq!(in_new_group, vec![100120]);
+        assert_eq!(in_other, vec![100126]);
+    }
+}
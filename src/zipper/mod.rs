@@ -1,46 +1,138 @@
 use std::io::Read;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crate::err::AppError;
-use crate::SourceDetails;
-use zip_extensions::write::zip_create_from_directory_with_options;
+use crate::{ArchiveDetails, ArchiveSummary, SourceDetails};
+use crate::{archive_sets, catalogue};
+use crate::setup::log_helper::archive_context;
+use crate::api::ArchiveObserver;
+use crate::cancel::CancelToken;
 use zip::write::SimpleFileOptions;
 use zip::CompressionMethod;
 use zip::ZipWriter;
-use log::info;
+use log::{info, warn};
 use std::fs;
 use std::fs::File;
-use chrono::Local;
+use chrono::{DateTime, Local};
+use std::time::SystemTime;
 use std::io::copy;
 
-pub fn zip_folder(unzipped_source_folder: &PathBuf, zipped_destination_file: &PathBuf) -> Result<(), AppError> {
+pub fn zip_folder(unzipped_source_folder: &Path, zipped_destination_file: &Path, observer: &dyn ArchiveObserver,
+                  cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
    
-    // Used with -f. Zips all of the folder. 
-    // Check source folder exists, destination zip file will be created if necessary .
+    // Used with -f. Zips all of the folder, with the default (deflated) compression. 
 
-    if !folder_exists(unzipped_source_folder) 
-    {
+    zip_filtered_folder(unzipped_source_folder, zipped_destination_file, CompressionMethod::Deflated, None, &|_| true, observer, cancel)
+}
+
+
+pub fn zip_filtered_folder(unzipped_source_folder: &Path, zipped_destination_file: &Path, compression: CompressionMethod,
+                           compression_level: Option<i64>, is_selected: &dyn Fn(&str) -> bool, observer: &dyn ArchiveObserver,
+                           cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
+
+    // Used by jobs, and with -f. Zips the files in the folder tree that pass the job's filters, which are
+    // applied to each file's path relative to the source folder (with '/' separators).
+
+    if !folder_exists(unzipped_source_folder) {
         let problem = "There is a problem accessing a designated folder or file".to_string();
-        let detail = "Source folder (of unzipped files) does not appear to exist.".to_string();
+        let detail = format!("Source folder {:?} does not appear to exist.", unzipped_source_folder);
         return Result::Err(AppError::FileSystemError(problem, detail));
     }
 
     info!("Zipping files from {:?} to {:?}", unzipped_source_folder, zipped_destination_file);
 
-    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
-    zip_create_from_directory_with_options(zipped_destination_file, unzipped_source_folder, |_| options)
-                .map_err(|e| AppError::ZipError(e, zipped_destination_file.to_owned()))?;
-    
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    collect_files(unzipped_source_folder, unzipped_source_folder, &mut files)?;
+    files.retain(|(name, _)| is_selected(name));
+    files.sort();
+    observer.totals_found(files.len(), files.iter().map(|(_, p)| file_size(p)).sum());
+    observer.archive_opened(&zipped_destination_file.file_name().unwrap_or_default().to_string_lossy());
+
+    let options = SimpleFileOptions::default()
+                    .compression_method(compression)
+                    .compression_level(compression_level);
+
+    // The archive is written to a hidden '.partial' file beside the destination, and only renamed
+    // over it once complete, so that an existing archive of the same name (e.g. a job's archive
+    // made earlier the same day) is left in place if the zip fails or is cancelled.
+
+    let file_name = zipped_destination_file.file_name().unwrap_or_default().to_string_lossy();
+    let partial_file = zipped_destination_file.with_file_name(format!(".{}.partial", file_name));
+    let res = write_filtered_archive(&files, &partial_file, options, observer, cancel)
+        .and_then(|_| fs::rename(&partial_file, zipped_destination_file)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, zipped_destination_file.to_owned())));
+    if let Err(e) = res {
+        let _ = fs::remove_file(&partial_file);
+        return Err(e);
+    }
+
+    let _ctx = archive_context(zipped_destination_file, files.len());
+    info!("{:?} archive created from {} files", zipped_destination_file, files.len());
+
+    let mut summary = ArchiveSummary::default();
+    summary.add_archive(zipped_destination_file, true)?;
+    observer.archive_finished(&summary.archives[0]);
+    Ok(summary)
+}
+
+
+fn write_filtered_archive(files: &[(String, PathBuf)], zip_file_path: &Path, options: SimpleFileOptions,
+                          observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<(), AppError> {
+
+    let zip_file = File::create(zip_file_path)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, zip_file_path.to_owned()))?;
+    let mut zip = ZipWriter::new(zip_file);
+
+    for (name, p) in files {
+        if cancel.is_cancelled() {
+            return Err(discard_archive(zip, zip_file_path));
+        }
+        let mut file = File::open(p)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, p.to_owned()))?;
+        let large_file = file.metadata()?.len() >= u32::MAX as u64;
+        zip.start_file(name.as_str(), options.large_file(large_file))
+                .map_err(|e| AppError::ZipError(e, p.to_owned()))?;
+        let bytes_read = copy(&mut file, &mut zip)
+                .map_err(|e| AppError::IoWriteErrorWithPath(e, zip_file_path.to_owned()))?;
+        observer.file_processed(name, bytes_read);
+    }
+    zip.finish()
+        .map_err(|e| AppError::ZipError(e, zip_file_path.to_owned()))?;
+    Ok(())
+}
+
+
+pub(crate) fn collect_files(base: &Path, folder: &Path, files: &mut Vec<(String, PathBuf)>) -> Result<(), AppError> {
+
+    // Each file's name is made relative to the base folder, with '/' separators as in the archives.
+
+    let entries = fs::read_dir(folder)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, folder.to_owned()))?;
+    for e in entries {
+        let path = e?.path();
+        if path.is_dir() {
+            collect_files(base, &path, files)?;
+        }
+        else if let Ok(rel) = path.strip_prefix(base) {
+            let name = rel.components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<String>>()
+                    .join("/");
+            files.push((name, path));
+        }
+    }
     Ok(())
 }
 
 
-pub fn zip_mdr_folder(source: SourceDetails, parent_unzipped_src_fdr: &PathBuf, parent_zipped_dest_fdr: &PathBuf) -> Result<usize, AppError> {
+pub fn zip_mdr_folder(source: SourceDetails, parent_unzipped_src_fdr: &PathBuf, parent_zipped_dest_fdr: &PathBuf,
+                      observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<ArchiveSummary, AppError> {
 
     // Used with -s or -m. Both source and destination PARENT folders already confirmed to exist
 
+    let started_at = Local::now();
     let database_name = source.database_name;
-    if database_name.trim() == "".to_string() {
+    if database_name.trim() == "" {
         let p = "No database name in Source details".to_string();
         let d = "Unable to unzip correspondig archive".to_string();
         return Err(AppError::FileSystemError(p, d));
@@ -58,16 +150,115 @@ pub fn zip_mdr_folder(source: SourceDetails, parent_unzipped_src_fdr: &PathBuf,
     }
     
 
-    if source.local_files_grouped {
-        zip_mdr_files_in_multiple_folders(&database_name, &srce_folder, &dest_folder)
+    // The archives created are listed, so that a catalogue index for the set can be written.
+
+    let today = Local::now().format("%y%m%d").to_string();
+    let mut archives: Vec<PathBuf> = Vec::new();
+    archive_sets::remove_archive_set(&database_name, &today, &dest_folder)?;
+
+    let (file_total, byte_total) = get_folder_totals(&srce_folder, source.local_files_grouped)?;
+    observer.totals_found(file_total, byte_total);
+
+    let res = if source.local_files_grouped {
+        zip_mdr_files_in_multiple_folders(&database_name, &today, &srce_folder, &dest_folder, &mut archives, observer, cancel)
     }
     else {
-        zip_mdr_files_in_single_folder(&database_name, &srce_folder, &dest_folder)
+        zip_mdr_files_in_single_folder(&database_name, &today, &srce_folder, &dest_folder, &mut archives, observer, cancel)
+    };
+
+    // If cancelled, the archives already completed are removed as well, as an incomplete
+    // set would otherwise be taken as the source's latest set.
+
+    if let Err(AppError::Cancelled) = res {
+        for a in &archives {
+            let _ = fs::remove_file(a);
+        }
+        warn!("Zip of {} cancelled - {} completed archive(s) of the incomplete set removed", database_name, archives.len());
+    }
+    res?;
+
+    let mut summary = ArchiveSummary::default();
+    if !archives.is_empty() {
+        catalogue::write_index(&database_name, &today, &archives, &dest_folder)?;
+        for a in &archives {
+            summary.add_archive(a, true)?;
+        }
+        summary.set_name = Some(format!("{} {}", database_name, today));
+        summary.started_at = Some(started_at);
+        summary.completed_at = Some(Local::now());
     }
+
+    Ok(summary)
+}
+
+
+pub fn get_latest_change(source: &SourceDetails, parent_unzipped_src_fdr: &Path) -> Result<Option<DateTime<Local>>, AppError> {
+
+    // Used with --changed-only. Returns the most recent modification time of the files in the 
+    // source's folder (and of the folders themselves, which also change when files are deleted).
+
+    let srce_folder: PathBuf = [parent_unzipped_src_fdr, Path::new(&source.database_name)].iter().collect();
+    Ok(get_folder_latest_change(&srce_folder, source.local_files_grouped)?.map(DateTime::<Local>::from))
 }
 
 
-fn zip_mdr_files_in_single_folder(database_name: &String, srce_folder: &PathBuf, dest_folder: &PathBuf) -> Result<usize, AppError> {
+fn get_folder_totals(srce_folder: &Path, files_grouped: bool) -> Result<(usize, u64), AppError> {
+
+    // The number and total size of the files to be zipped, so that progress can be shown against them.
+    // Sources with grouped files have their files one level of folders down.
+
+    let mut folders = vec![srce_folder.to_owned()];
+    if files_grouped {
+        let folder_list = fs::read_dir(srce_folder)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, srce_folder.to_owned()))?;
+        folders = folder_list.filter_map(|entry| Some(entry.ok()?.path())).filter(|p| p.is_dir()).collect();
+    }
+
+    let (mut files, mut bytes) = (0, 0);
+    for f in folders {
+        let entries = fs::read_dir(&f)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, f.to_owned()))?;
+        for m in entries.filter_map(|entry| entry.ok()?.metadata().ok()).filter(|m| m.is_file()) {
+            files += 1;
+            bytes += m.len();
+        }
+    }
+    Ok((files, bytes))
+}
+
+
+pub fn get_folder_latest_change(srce_folder: &Path, files_grouped: bool) -> Result<Option<SystemTime>, AppError> {
+
+    // Sources with grouped files are examined down one level of folders.
+
+    if !folder_exists(srce_folder) {
+        return Ok(None);
+    }
+
+    let mut folders = vec![srce_folder.to_owned()];
+    if files_grouped {
+        let folder_list = fs::read_dir(srce_folder)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, srce_folder.to_owned()))?;
+        folders.extend(folder_list.filter_map(|entry| Some(entry.ok()?.path())).filter(|p| p.is_dir()));
+    }
+
+    let mut latest: Option<SystemTime> = None;
+    for f in folders {
+        let entries = fs::read_dir(&f)
+            .map_err(|e| AppError::IoReadErrorWithPath(e, f.to_owned()))?;
+        let folder_time = fs::metadata(&f).and_then(|m| m.modified()).ok();
+        let entry_times = entries.filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok());
+        if let Some(t) = entry_times.chain(folder_time).max() {
+            latest = Some(latest.map_or(t, |l| l.max(t)));
+        }
+    }
+
+    Ok(latest)
+}
+
+
+fn zip_mdr_files_in_single_folder(database_name: &String, today: &str, srce_folder: &PathBuf, dest_folder: &PathBuf,
+                                  archives: &mut Vec<PathBuf>, observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<usize, AppError> {
 
     let file_list = fs::read_dir(srce_folder)
         .map_err(|e| AppError::IoReadErrorWithPath(e, srce_folder.to_owned()))?;
@@ -83,31 +274,40 @@ fn zip_mdr_files_in_single_folder(database_name: &String, srce_folder: &PathBuf,
 
     // Set up constants to be used within the loop.
 
-    let today = Local::now().format("%y%m%d").to_string();
     let file_name_stem = format!("{} {}", database_name, today);
     let files_per_zip = 10000;
     let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
 
-    // initialise these mutable variables used within the loop
+    // initialise these mutable variables used within the loop - there is no
+    // archive (and no writer) until the first file is to be added
     
     let mut zip_file_name: String;
-    let mut zip_file_path = paths[0].clone(); 
-    let mut zip_file = File::create(&zip_file_path)?;
-    let mut zip = ZipWriter::new(zip_file); 
+    let mut zip_file_path = PathBuf::new(); 
+    let mut current_zip: Option<ZipWriter<File>> = None;
 
     let mut i = 0;
     let mut j = 0;
 
     for p in paths {
+
+        if cancel.is_cancelled() {
+            return Err(match current_zip {
+                Some(zip) => discard_archive(zip, &zip_file_path),
+                None => AppError::Cancelled,
+            });
+        }
         
         if j == 0 {    // New zip file required?
 
-            if i != 0 {
+            if let Some(zip) = current_zip.take() {
 
                 zip.finish()        // complete previous zip
                     .map_err(|e| AppError::ZipError(e, zip_file_path.to_owned()))?;
 
+                let _ctx = archive_context(&zip_file_path, files_per_zip);
                 info!("{:?} archive created from {} files", zip_file_path, files_per_zip);
+                observer.archive_finished(&ArchiveDetails::new(&zip_file_path, files_per_zip));
+                archives.push(zip_file_path.clone());
             }
 
             let start_file = (i + 1).to_string();
@@ -118,12 +318,14 @@ fn zip_mdr_files_in_single_folder(database_name: &String, srce_folder: &PathBuf,
             let end_file = end_file_num.to_string();
             
             zip_file_name = format!("{} {} to {}.zip", file_name_stem, start_file, end_file);
+            observer.archive_opened(&zip_file_name);
             zip_file_path = [dest_folder, &PathBuf::from(zip_file_name)].iter().collect(); 
-            zip_file = File::create(&zip_file_path)?;
-            zip = ZipWriter::new(zip_file); 
+            let zip_file = File::create(&zip_file_path)?;
+            current_zip = Some(ZipWriter::new(zip_file)); 
 
         }
 
+        let zip = current_zip.as_mut().expect("an archive is opened for the first file");
         let file = File::open(&p)?;
         let file_name = get_f_name(&p)?;
         
@@ -134,6 +336,7 @@ fn zip_mdr_files_in_single_folder(database_name: &String, srce_folder: &PathBuf,
         let mut buffer = Vec::new();
         copy(&mut file.take(u64::MAX), &mut buffer)?;
         zip.write_all(&buffer)?;
+        observer.file_processed(file_name, buffer.len() as u64);
 
         i += 1;
         j += 1;
@@ -142,8 +345,12 @@ fn zip_mdr_files_in_single_folder(database_name: &String, srce_folder: &PathBuf,
         }
     }
 
-    zip.finish()
-        .map_err(|e| AppError::ZipError(e, zip_file_path.to_owned()))?;
+    if let Some(zip) = current_zip {
+        zip.finish()
+            .map_err(|e| AppError::ZipError(e, zip_file_path.to_owned()))?;
+    }
+    observer.archive_finished(&ArchiveDetails::new(&zip_file_path, i - (archives.len() * files_per_zip)));
+    archives.push(zip_file_path.clone());
 
     info!("{} files zipped in total", i);
     
@@ -152,7 +359,8 @@ fn zip_mdr_files_in_single_folder(database_name: &String, srce_folder: &PathBuf,
  }
 
 
-fn zip_mdr_files_in_multiple_folders(database_name: &String, srce_folder: &PathBuf, dest_folder: &PathBuf) -> Result<usize, AppError> {
+fn zip_mdr_files_in_multiple_folders(database_name: &String, today: &str, srce_folder: &PathBuf, dest_folder: &PathBuf,
+                                     archives: &mut Vec<PathBuf>, observer: &dyn ArchiveObserver, cancel: &CancelToken) -> Result<usize, AppError> {
 
     let folder_list = fs::read_dir(srce_folder)
         .map_err(|e| AppError::IoReadErrorWithPath(e, srce_folder.to_owned()))?;
@@ -167,7 +375,6 @@ fn zip_mdr_files_in_multiple_folders(database_name: &String, srce_folder: &PathB
 
     // Set up constants to be used within the loop.
    
-    let today = Local::now().format("%y%m%d").to_string();
     let file_name_stem = format!("{} {}", database_name, today);
     let min_files_per_zip = 10000;
     let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
@@ -177,7 +384,7 @@ fn zip_mdr_files_in_multiple_folders(database_name: &String, srce_folder: &PathB
     // so that a mutable ZipWriter can be created.
 
     let initial_folder = &folders[0].clone();
-    let initial_folder_name = get_f_name(&initial_folder)?.to_string();
+    let initial_folder_name = get_f_name(initial_folder)?.to_string();
     let zip_file_name = format!("{} {} to .zip", file_name_stem, initial_folder_name);
 
     let mut zip_file_path: PathBuf = [dest_folder, &PathBuf::from(&zip_file_name)].iter().collect(); 
@@ -194,6 +401,7 @@ fn zip_mdr_files_in_multiple_folders(database_name: &String, srce_folder: &PathB
     
     let mut i = 0;  // accumulative total of files zipped, overall
     let mut j = 0;  // accumulative total of files zipped in the current zip file
+    let mut archive_start = 0;  // the overall total when the current zip file was started
 
     for f in folders {
 
@@ -212,13 +420,18 @@ fn zip_mdr_files_in_multiple_folders(database_name: &String, srce_folder: &PathB
                 let new_zip_file_name = format!("{} {} to {}.zip", file_name_stem, initial_folder, last_folder_name);
                 let new_zip_file_path: PathBuf = [dest_folder, &PathBuf::from(&new_zip_file_name)].iter().collect(); 
                 std::fs::rename(&zip_file_path, &new_zip_file_path)?;
+                let _ctx = archive_context(&new_zip_file_path, i);
                 info!("{:?} archive created; {} files zipped so far", new_zip_file_path, i);
+                observer.archive_finished(&ArchiveDetails::new(&new_zip_file_path, i - archive_start));
+                archives.push(new_zip_file_path);
             }
 
             // ***** Create next (or first) zip file ***************************
 
             initial_folder = folder_name.clone();
+            archive_start = i;
             zip_file_name = format!("{} {} to .zip", file_name_stem, initial_folder);
+            observer.archive_opened(&zip_file_name);
             zip_file_path = [dest_folder, &PathBuf::from(&zip_file_name)].iter().collect(); 
             zip_file = File::create(&zip_file_path)?;
             curr_zip = ZipWriter::new(zip_file); 
@@ -238,15 +451,20 @@ fn zip_mdr_files_in_multiple_folders(database_name: &String, srce_folder: &PathB
         let mut m = 0;
         for p in paths {
 
+            if cancel.is_cancelled() {
+                return Err(discard_archive(curr_zip, &zip_file_path));
+            }
+
             let file = File::open(&p)?;
             let file_name = format!("{}/{}", &folder_name, get_f_name(&p)?);
     
-            curr_zip.start_file(file_name, options)
+            curr_zip.start_file(file_name.as_str(), options)
                     .map_err(|e| AppError::ZipError(e, p.to_owned()))?;
             let mut buffer = Vec::new();
             copy(&mut file.take(u64::MAX), &mut buffer)?;
             
             curr_zip.write_all(&buffer)?;
+            observer.file_processed(&file_name, buffer.len() as u64);
     
             m += 1;
         }
@@ -269,34 +487,53 @@ fn zip_mdr_files_in_multiple_folders(database_name: &String, srce_folder: &PathB
     let new_zip_file_name = format!("{} {} to {}.zip", file_name_stem, initial_folder, last_folder_name);
     let new_zip_file_path: PathBuf = [dest_folder, &PathBuf::from(&new_zip_file_name)].iter().collect(); 
     std::fs::rename(&zip_file_path, &new_zip_file_path)?;
+    let _ctx = archive_context(&new_zip_file_path, i);
     info!("{:?} archive created; {} files zipped so far", new_zip_file_path, i);
+    observer.archive_finished(&ArchiveDetails::new(&new_zip_file_path, i - archive_start));
+    archives.push(new_zip_file_path);
 
     Ok(folder_num)
 }
 
 
-fn folder_exists(folder_name: &PathBuf) -> bool {
+fn discard_archive(zip: ZipWriter<File>, zip_file_path: &Path) -> AppError {
+
+    // The writer is dropped (closing the file) before the incomplete archive is removed.
+
+    drop(zip);
+    let _ = fs::remove_file(zip_file_path);
+    warn!("Run cancelled - incomplete archive {:?} removed", zip_file_path);
+    AppError::Cancelled
+}
+
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or_default()
+}
+
+
+fn folder_exists(folder_name: &Path) -> bool {
     let xres = folder_name.try_exists();
-    let res = match xres {
+    
+    match xres {
         Ok(true) => true,
         Ok(false) => false, 
         Err(_e) => false,           
-    };
-    res
+    }
 }
 
 
-fn get_f_name(path: &PathBuf) -> Result<&str, AppError> {
+fn get_f_name(path: &Path) -> Result<&str, AppError> {
 
     match path.file_name() {
         Some(oss) => { 
             match oss.to_str() {
             Some(filename) => Ok(filename),
-            None => return Err(AppError::FileSystemError("Error when extracting folder name from path".to_string(), 
+            None => Err(AppError::FileSystemError("Error when extracting folder name from path".to_string(), 
                     "Could not turn OsStr to string".to_string())),
                 }
         },
-        None => return Err(AppError::FileSystemError("Error when extracting folder name from path".to_string(), 
+        None => Err(AppError::FileSystemError("Error when extracting folder name from path".to_string(), 
                 "Could not read file name as OsStr".to_string())),
     }
 }